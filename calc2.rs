@@ -1,372 +1,880 @@
-// Грамматика арифметических формул с поддержкой функций
-// -----------------------------------------------------
-// expr := [plusminus]*
-// plusminus := muldiv [('+' | '-') muldiv]*
-// muldiv := multiplier [('*' | '/') multiplier]*
-// multiplier := factor ['^' factor]*
-// factor := ['-']? pfactor
-// pfactor := NUMBER | function | '(' expr ')'
-// function := FUNCNAME '(' [args]* ')'
-// args := expr | [',' expr]*
-
-use std::fmt::{Display, Formatter};
-use std::str::FromStr;
-
-#[derive(Debug, PartialEq)]
-enum TokenType {
-    LeftBracket, RightBracket, OpPlus, OpMinus,
-    OpMul, OpDiv, Caret, NUMBER, FUNCNAME, Comma, EOF
-}
-
-#[derive(Debug)]
-struct Token {
-    lex_type: TokenType,
-    lex_num_value: f64,
-    lex_str_value: String
-}
-
-impl Token {
-    fn null() -> Self { Self { lex_type: TokenType::EOF,
-        lex_num_value: 0., lex_str_value: "".to_string() } }
-    fn oper(t: TokenType) -> Self { Self { lex_type: t,
-        lex_num_value: 0., lex_str_value: "".to_string() } }
-    fn num(n: f64) -> Self { Self { lex_type: TokenType::NUMBER,
-        lex_num_value: n, lex_str_value: "".to_string() } }
-    fn func(s: &String) -> Self { Self { lex_type: TokenType::FUNCNAME,
-        lex_num_value: 0., lex_str_value: s.to_string() } }
-}
-
-#[derive(Debug)]
-struct Formula {
-    items: Vec<Token>
-}
-
-impl Formula {
-    // Лексический анализ
-    // Разбиваем выражение на лексемы
-    // Проверяем на корректность числа, баланс скобок
-    fn new(s: &str) -> Self {
-        let mut items = vec![];
-        let mut bn = 0;
-        let mut i = 0;
-        while i < s.len() {
-            let xc = FromStr::from_str(&s[i..i + 1]);
-            if xc.is_err() {
-                println!("Syntax error: {:?}", xc.err().unwrap());
-                return Self { items: vec![Token::null()] }
-            }
-            let c = xc.unwrap();
-            let mut t = Token::null();
-            match c {
-                '+' => t = Token::oper(TokenType::OpPlus),
-                '-' => t = Token::oper(TokenType::OpMinus),
-                '*' => t = Token::oper(TokenType::OpMul),
-                '/' => t = Token::oper(TokenType::OpDiv),
-                '^' => t = Token::oper(TokenType::Caret),
-                '(' => {
-                    bn += 1;
-                    t = Token::oper(TokenType::LeftBracket);
-                },
-                ')' => {
-                    bn -= 1;
-                    if bn < 0 {
-                        println!("Error: invalid symbol ')' in the position {}", i + 1);
-                        return Self { items: vec![Token::null()] }
-                    }
-                    t = Token::oper(TokenType::RightBracket);
-                },
-                '0'..='9' | '.' => {
-                    let mut num_val = c.to_string();
-                    i += 1;
-                    while i < s.len() {
-                        let xc = FromStr::from_str(&s[i..i + 1]);
-                        if xc.is_err() {
-                            println!("Syntax error: {:?}", xc.err().unwrap());
-                            return Self { items: vec![Token::null()] }
-                        }
-                        let c = xc.unwrap();
-                        match c {
-                            '0'..='9' | '.' => {
-                                num_val.push_str(c.to_string().as_str());
-                                i += 1;
-                                continue
-                            },
-                            ' ' => { i += 1; continue },
-                            _ => {
-                                i -= 1;
-                                break;
-                            }
-                        }
-                    }
-                    if let Ok(x) = num_val.parse::<f64>() {
-                        t = Token::num(x);
-                    } else {
-                        println!("Syntax error: invalid number '{num_val}'");
-                        return Self { items: vec![Token::null()] }
-                    }
-                },
-                ',' => t = Token::oper(TokenType::Comma),
-                'a'..='z' | 'A'..='Z' => {
-                    let mut fname = c.to_string();
-                    i += 1;
-                    while i < s.len() {
-                        let xc = FromStr::from_str(&s[i..i + 1]);
-                        if xc.is_err() {
-                            println!("Syntax error: {:?}", xc.err().unwrap());
-                            return Self { items: vec![Token::null()] }
-                        }
-                        let c = xc.unwrap();
-                        match c {
-                            'a'..='z' | 'A'..='Z' => {
-                                fname.push_str(c.to_string().as_str());
-                                i += 1;
-                                continue
-                            },
-                            ' ' => { i += 1; continue },
-                            _ => {
-                                i -= 1;
-                                break;
-                            }
-                        }
-                    }
-                    t = Token::func(&fname);
-                },
-                ' ' => { i += 1; continue; },
-                _ => {
-                    println!("Syntax error: unknown symbol '{c}' in the position {}", i + 1);
-                    return Self { items: vec![Token::null()] }
-                }
-            }
-            items.push(t);
-            i += 1;
-        }
-        // проверяем баланс скобок
-        if bn != 0 {
-            println!("Error: invalid symbol '('");
-            return Self { items: vec![Token::null()] }
-        }
-        items.push(Token::null());
-        Self { items }
-    }
-}
-
-pub struct Calc {
-    tokens: Formula,
-    pos: usize
-}
-
-impl Calc {
-    pub fn new(s: &str) -> Self {
-        let tokens = Formula::new(s);
-        Self { tokens, pos: 0 }
-    }
-
-    pub fn calc_expr(&mut self) -> f64 {
-        if self.tokens.items[self.pos].lex_type != TokenType::EOF {
-            self.calc_plusminus()
-        } else { 0. }
-    }
-
-    fn calc_plusminus(&mut self) -> f64 {
-        let mut x = self.calc_muldiv();
-        loop {
-            match self.tokens.items[self.pos].lex_type {
-                TokenType::OpPlus => {
-                    self.pos += 1;
-                    x += self.calc_muldiv();
-                },
-                TokenType::OpMinus => {
-                    self.pos += 1;
-                    x -= self.calc_muldiv();
-                },
-                _ => return x
-            }
-        }
-    }
-
-    fn calc_muldiv(&mut self) -> f64 {
-        let mut x = self.calc_multiplier();
-        loop {
-            match self.tokens.items[self.pos].lex_type {
-                TokenType::OpMul => {
-                    self.pos += 1;
-                    x *= self.calc_multiplier();
-                },
-                TokenType::OpDiv => {
-                    self.pos += 1;
-                    x /= self.calc_multiplier();
-                },
-                _ => return x
-            }
-        }
-    }
-
-    fn calc_multiplier(&mut self) -> f64 {
-        let mut x = self.calc_factor();
-        loop {
-            match self.tokens.items[self.pos].lex_type {
-                TokenType::Caret => {
-                    self.pos += 1;
-                    x = x.powf(self.calc_factor());
-                }
-                _ => return x
-            }
-        }
-    }
-
-    fn calc_factor(&mut self) -> f64 {
-        if self.tokens.items[self.pos].lex_type == TokenType::OpMinus {
-            self.pos += 1;
-            -self.calc_pfactor()
-        } else {
-            self.calc_pfactor()
-        }
-    }
-
-    fn calc_pfactor(&mut self) -> f64 {
-        match self.tokens.items[self.pos].lex_type {
-            TokenType::NUMBER => {
-                let x = self.tokens.items[self.pos].lex_num_value;
-                self.pos += 1;
-                return x
-            },
-            TokenType::FUNCNAME => {
-                // function := FUNCNAME '(' [args]* ')'
-                // args := factor | [',' factor]*
-                let fname = self.tokens.items[self.pos].lex_str_value
-                    .to_ascii_lowercase().clone();
-                let mut args = vec![];
-                self.pos += 1;
-                if self.tokens.items[self.pos].lex_type == TokenType::LeftBracket {
-                    self.pos += 1;
-                    if self.tokens.items[self.pos].lex_type != TokenType::RightBracket {
-                        loop {
-                            args.push(self.calc_expr());
-                            if self.tokens.items[self.pos].lex_type == TokenType::Comma {
-                                self.pos += 1;
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                    if self.tokens.items[self.pos].lex_type == TokenType::RightBracket {
-                        self.pos += 1;
-                        // Вычисляем функцию
-                        return self.calc_func(fname.as_str(), args);
-                    } else {
-                        self.out(0)
-                    }
-                } else {
-                    self.out(0)
-                }
-            }
-            TokenType::LeftBracket => {
-                self.pos += 1;
-                let x = self.calc_expr();
-                if self.tokens.items[self.pos].lex_type == TokenType::RightBracket {
-                    self.pos += 1;
-                    return x
-                } else {
-                    self.out(0)
-                }
-            },
-            _ => self.out(0)
-        }
-    }
-
-    // ["pow", "ln", "log", "sqr", "e", "sin", "cos", "tan"]
-    fn calc_func(&self, fname: &str, args: Vec<f64>) -> f64 {
-        match fname {
-            // --> Функции с произвольным количеством аргументов
-            "min" => {
-                if args.len() == 0 { self.out1(fname, usize::MAX, 0, 0); }
-                let mut m = args[0];
-                for x in args { if x < m { m = x; } }
-                m
-            },
-            "max" => {
-                if args.len() == 0 { self.out1(fname, usize::MAX, 0, 0); }
-                let mut m = args[0];
-                for x in args { if x > m { m = x; } }
-                m
-            },
-            "avg" => {
-                if args.len() == 0 { self.out1(fname, usize::MAX, 0, 0); }
-                args.iter().sum::<f64>() / args.len() as f64
-            },
-            // --> Функции с двумя аргументами
-            "pow" => {
-                if args.len() != 2 { self.out1(fname, 2, args.len(), 0); }
-                args[0].powf(args[1])
-            },
-            // --> Функции с одним аргументом
-            "sqr" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].powf(0.5)
-            },
-            "exp" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].exp()
-            },
-            "ln" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].ln()
-            },
-            "log" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].log10()
-            },
-            "sin" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].sin()
-            },
-            "cos" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].cos()
-            },
-            "tan" => {
-                if args.len() != 1 { self.out1(fname, 1, args.len(), 0); }
-                args[0].tan()
-            },
-            // --> Функции без аргументов
-            "pi" => {
-                if args.len() > 0 { self.out1(fname, 0, args.len(), 0); }
-                std::f64::consts::PI
-            },
-            "e" => {
-                if args.len() > 0 { self.out1(fname, 0, args.len(), 0); }
-                std::f64::consts::E
-            },
-            _ => {
-                println!("Syntax error: unknown function '{fname}'");
-                std::process::exit(0);
-            }
-        }
-    }
-
-    fn out(&self, c: i32) -> f64 {
-        let xs = format!("Ошибка в позиции {}: invalid token {:?}",
-                             self.pos, self.tokens.items[self.pos].lex_type);
-        let ys = if self.tokens.items[self.pos].lex_type == TokenType::NUMBER {
-            format!("('{}')", self.tokens.items[self.pos].lex_num_value)
-        } else {
-            if self.tokens.items[self.pos].lex_str_value.len() > 0 {
-                format!(" ('{}')", self.tokens.items[self.pos].lex_str_value)
-            } else {
-                "".to_string()
-            }
-        };
-        println!("{xs}{ys}");
-        std::process::exit(c);
-    }
-
-    fn out1(&self, fname: &str, n: usize, m: usize, c: i32) -> f64 {
-        if n != usize::MAX {
-            println!("Error: invalid argument's list for the function '{fname}'");
-            println!("(must be {n} arguments, but was taken {m} arguments)");
-        } else {
-            println!("Error: empty argument's list for the function '{fname}'");
-        }
-        std::process::exit(c);
-    }
-}
+// Грамматика арифметических формул с поддержкой функций и переменных
+// ------------------------------------------------------------------
+// program := stmt [';' stmt]*
+// stmt := NAME '=' expr | NAME '(' [params]* ')' '=' expr | expr
+// params := NAME | [',' NAME]*
+// expr := prefix* operand [postfix | infix expr]* - разбор методом Пратта
+//         по таблице операций OpTable с приоритетами и ассоциативностью:
+//         or < and < not < сравнения < to < | < xor < & < << >> < + - < * / % <
+//         унарные - ~ < ^ (правая) < !
+// operand := (NUMBER [unit]? | NAME | function | list | '(' expr ')') ['[' expr ']']*
+// Если в OpTable включено неявное умножение, операнд, за которым следует имя
+// или '(', умножается на них с приоритетом '*': 2x, 3(x + 1), (a + b)(a - b), 2pi();
+// 1/2x = (1/2)*x и x^2y = (x^2)*y разбираются с предупреждением
+// unit := NAME ['^' expr]? - имя единицы измерения после числа: 3 km, 2 m^2;
+//         только в режиме Mode::Units
+// NUMBER := десятичное число с необязательным показателем (1e-3, 6.02E23) |
+//           0x, 0o, 0b и целое в шестнадцатеричной, восьмеричной или двоичной записи
+// NAME := буква Unicode, за которой следуют буквы, цифры и '_'
+// function := NAME '(' [args]* ')'
+// args := expr | [',' expr]*
+// list := '[' [expr [',' expr]*]? ']' - список чисел; xs[i] - элемент с номером i от нуля,
+//         xs[-1] - последний; арифметика над списками поэлементная;
+//         список из списков - матрица: [[1, 2], [3, 4]], A * B - произведение матриц
+// true, false - логические константы; if(cond, a, b) вычисляет только выбранную ветвь;
+// solve(expr, x, guess) и solve(expr, x, a, b) - корень уравнения expr = 0 по x;
+// integrate(expr, x, a, b), sum(expr, k, from, to), prod(expr, k, from, to) - интеграл,
+// сумма и произведение: expr вычисляется при разных значениях переменной
+
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+mod backend;
+mod bigint;
+mod complex;
+mod decimal;
+pub mod diagnostics;
+mod diff;
+mod editor;
+mod env;
+mod eval;
+pub mod expr;
+mod funcs;
+mod integrate;
+mod integer;
+mod matrix;
+mod ops;
+mod plot;
+mod rational;
+mod render;
+pub mod repl;
+mod simplify;
+mod solve;
+mod units;
+mod value;
+mod vm;
+
+pub use backend::{eval_in, Backend, ComplexBackend, DecimalBackend, Mode, RationalBackend};
+pub use env::Env;
+pub use funcs::{Angle, NativeFn, Registry};
+pub use integer::IntBackend;
+pub use ops::{Assoc, Fixity, OpAction, OpDef, OpTable};
+pub use plot::{Plot, Style};
+pub use units::{unit, UnitBackend};
+pub use value::Value;
+pub use vm::{compile, Compiled};
+pub use expr::{BinOp, Expr, Stmt};
+
+// Допустимое количество аргументов функции
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    // от min до max аргументов
+    Between(usize, usize)
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(k) => n == k,
+            Arity::AtLeast(k) => n >= k,
+            Arity::Between(a, b) => (a..=b).contains(&n)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnknownSymbol(char),
+    UnbalancedBracket,
+    BadNumber(String),
+    UnknownFunction(String),
+    UnknownVariable(String),
+    RecursionLimit(String),
+    ExpectedVariable,
+    NotDifferentiable(String),
+    DivisionByZero,
+    Domain(String),
+    Unsupported(String),
+    Overflow,
+    Dimension(String),
+    #[cfg_attr(not(test), allow(dead_code))]
+    BadOperator(String),
+    Type(String),
+    // численный метод не нашел ответ за отведенное число шагов
+    NoConvergence(String),
+    WrongArity { fname: String, expected: Arity, got: usize },
+    UnexpectedToken(String),
+    // предупреждение: неявное умножение относится ко всему выражению слева
+    Ambiguous(String),
+    // предупреждение: переменная ряда sum(expr, k, from, to) совпадает с переменной окружения
+    Shadowed(String)
+}
+
+// Ошибка разбора или вычисления формулы:
+// вид ошибки и байтовый диапазон в исходной строке
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalcError {
+    pub kind: ErrorKind,
+    pub span: Range<usize>
+}
+
+impl CalcError {
+    pub fn new(kind: ErrorKind, span: Range<usize>) -> Self { Self { kind, span } }
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let pos = self.span.start + 1;
+        match &self.kind {
+            ErrorKind::UnknownSymbol(c) =>
+                write!(f, "Syntax error: unknown symbol '{c}' in the position {pos}"),
+            ErrorKind::UnbalancedBracket =>
+                write!(f, "Syntax error: unbalanced bracket in the position {pos}"),
+            ErrorKind::BadNumber(s) =>
+                write!(f, "Syntax error: invalid number '{s}' in the position {pos}"),
+            ErrorKind::UnknownFunction(s) =>
+                write!(f, "Syntax error: unknown function '{s}' in the position {pos}"),
+            ErrorKind::UnknownVariable(s) =>
+                write!(f, "Error: unknown variable '{s}' in the position {pos}"),
+            ErrorKind::RecursionLimit(s) =>
+                write!(f, "Error: recursion depth limit exceeded in the function '{s}'"),
+            ErrorKind::ExpectedVariable =>
+                write!(f, "Error: expected a variable name in the position {pos}"),
+            ErrorKind::NotDifferentiable(s) =>
+                write!(f, "Error: cannot differentiate '{s}' in the position {pos}"),
+            ErrorKind::DivisionByZero =>
+                write!(f, "Error: division by zero in the position {pos}"),
+            ErrorKind::Domain(s) =>
+                write!(f, "Error: {s} in the position {pos}"),
+            ErrorKind::Unsupported(s) =>
+                write!(f, "Error: '{s}' is not available in this number mode in the position {pos}"),
+            ErrorKind::Overflow =>
+                write!(f, "Error: integer overflow in the position {pos}"),
+            ErrorKind::Dimension(s) =>
+                write!(f, "Error: dimension mismatch: {s} in the position {pos}"),
+            ErrorKind::Type(s) =>
+                write!(f, "Error: type mismatch: {s} in the position {pos}"),
+            ErrorKind::NoConvergence(s) =>
+                write!(f, "Error: '{s}' did not converge in the position {pos}"),
+            ErrorKind::BadOperator(s) =>
+                write!(f, "Error: invalid operator symbol '{s}'"),
+            ErrorKind::WrongArity { fname, expected: Arity::Exact(n), got } =>
+                write!(f, "Error: invalid argument's list for the function '{fname}' \
+                    (must be {n} arguments, but was taken {got} arguments)"),
+            ErrorKind::WrongArity { fname, expected: Arity::AtLeast(n), got } =>
+                if *n == 1 && *got == 0 {
+                    write!(f, "Error: empty argument's list for the function '{fname}'")
+                } else {
+                    write!(f, "Error: invalid argument's list for the function '{fname}' \
+                        (must be at least {n} arguments, but was taken {got} arguments)")
+                },
+            ErrorKind::WrongArity { fname, expected: Arity::Between(a, b), got } =>
+                write!(f, "Error: invalid argument's list for the function '{fname}' \
+                    (must be from {a} to {b} arguments, but was taken {got} arguments)"),
+            ErrorKind::UnexpectedToken(s) if s.is_empty() =>
+                write!(f, "Syntax error: unexpected end of formula"),
+            ErrorKind::UnexpectedToken(s) =>
+                write!(f, "Syntax error: invalid token '{s}' in the position {pos}"),
+            ErrorKind::Ambiguous(s) =>
+                write!(f, "Warning: the implicit multiplication applies to the whole '{s}' in the position {pos}"),
+            ErrorKind::Shadowed(s) =>
+                write!(f, "Warning: the series index '{s}' hides the variable '{s}' in the position {pos}")
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenType {
+    LeftBracket, RightBracket, LeftSquare, RightSquare, Operator, Number, Name, Comma, Assign, Semicolon, Eof
+}
+
+#[derive(Debug)]
+struct Token {
+    lex_type: TokenType,
+    lex_num_value: f64,
+    // целое число больше 2^53, не представимое точно в f64
+    lex_int_value: Option<u64>,
+    // имя, знак операции; у числа больше чем из 15 значащих цифр - его запись
+    lex_str_value: String,
+    span: Range<usize>
+}
+
+impl Token {
+    fn null() -> Self { Self { lex_type: TokenType::Eof,
+        lex_num_value: 0., lex_int_value: None, lex_str_value: "".to_string(), span: 0..0 } }
+    fn oper(t: TokenType) -> Self { Self { lex_type: t,
+        lex_num_value: 0., lex_int_value: None, lex_str_value: "".to_string(), span: 0..0 } }
+    fn num(n: f64) -> Self { Self { lex_type: TokenType::Number,
+        lex_num_value: n, lex_int_value: None, lex_str_value: "".to_string(), span: 0..0 } }
+    fn sign(s: &str) -> Self { Self { lex_type: TokenType::Operator,
+        lex_num_value: 0., lex_int_value: None, lex_str_value: s.to_string(), span: 0..0 } }
+    fn name(s: &str) -> Self { Self { lex_type: TokenType::Name,
+        lex_num_value: 0., lex_int_value: None, lex_str_value: s.to_string(), span: 0..0 } }
+    fn int(n: u64) -> Self {
+        let mut t = Self::num(n as f64);
+        if n > 1 << 53 { t.lex_int_value = Some(n); }
+        t
+    }
+    // Десятичное число: f64 восстанавливает запись не больше чем из 15 значащих цифр,
+    // для более длинной записи она сохраняется
+    fn dec(x: f64, text: &str) -> Self {
+        let mut t = Self::num(x);
+        let mantissa = text.split(['e', 'E']).next().unwrap_or(text).replace('.', "");
+        if mantissa.trim_start_matches('0').trim_end_matches('0').len() > 15 {
+            t.lex_str_value = text.to_string();
+        }
+        t
+    }
+}
+
+#[derive(Debug)]
+struct Formula {
+    src: String,
+    items: Vec<Token>
+}
+
+impl Formula {
+    // Лексический анализ
+    // Разбиваем выражение на лексемы по символам Unicode,
+    // span лексемы - диапазон байтов в исходной строке
+    // Знаки операций берутся из таблицы ops
+    // Проверяем на корректность числа, баланс скобок.
+    // Ошибки не прерывают анализ: неверное число заменяется нулем,
+    // неизвестный символ и лишняя закрывающая скобка пропускаются,
+    // незакрытые скобки закрываются в конце строки
+    fn new(s: &str, ops: &OpTable) -> (Self, Vec<CalcError>) {
+        let chars: Vec<(usize, char)> = s.char_indices().collect();
+        // байтовое смещение k-го символа
+        let at = |k: usize| chars.get(k).map_or(s.len(), |c| c.0);
+        let ch = |k: usize| chars.get(k).map(|c| c.1);
+        let bad = |a: usize, b: usize| {
+            CalcError::new(ErrorKind::BadNumber(s[at(a)..at(b)].to_string()), at(a)..at(b))
+        };
+        let close = |c: char| Token::oper(if c == '(' { TokenType::RightBracket } else { TokenType::RightSquare });
+        let (mut items, mut errors) = (vec![], vec![]);
+        // незакрытые скобки: вид и позиция
+        let mut brackets = vec![];
+        let mut k = 0;
+        while let Some(c) = ch(k) {
+            let start = k;
+            k += 1;
+            // знак операции; из подходящих знаков берется самый длинный
+            if let Some(sign) = ops.match_sign(&s[at(start)..]) {
+                k = start + sign.chars().count();
+                let mut t = Token::sign(sign);
+                t.span = at(start)..at(k);
+                items.push(t);
+                continue
+            }
+            let mut t = match c {
+                c if c.is_whitespace() => continue,
+                // целое в шестнадцатеричной, восьмеричной или двоичной записи
+                '0' if matches!(ch(k), Some('x' | 'X' | 'o' | 'O' | 'b' | 'B')) => {
+                    let radix = match ch(k) {
+                        Some('x' | 'X') => 16,
+                        Some('o' | 'O') => 8,
+                        _ => 2
+                    };
+                    k += 1;
+                    let from = k;
+                    while ch(k).is_some_and(|c| c.is_alphanumeric() || c == '_') { k += 1; }
+                    let digits = &s[at(from)..at(k)];
+                    match u64::from_str_radix(&digits.replace('_', ""), radix) {
+                        Ok(n) if !digits.starts_with('_') => Token::int(n),
+                        _ => {
+                            errors.push(bad(start, k));
+                            Token::num(0.)
+                        }
+                    }
+                },
+                // десятичное число с необязательным показателем: 12, 0.5, .5, 1e-3, 6.02E23
+                '0'..='9' | '.' => {
+                    while ch(k).is_some_and(|c| c.is_ascii_digit() || c == '.') { k += 1; }
+                    // e после цифр - показатель, если за ним знак, цифра или конец имени;
+                    // 1e и 1e+ без цифр - ошибка, а 2exp(1) - число и имя
+                    let mut missing = false;
+                    if matches!(ch(k), Some('e' | 'E')) {
+                        let sign = usize::from(matches!(ch(k + 1), Some('+' | '-')));
+                        let next = ch(k + 1 + sign);
+                        if next.is_some_and(|c| c.is_ascii_digit()) {
+                            k += 1 + sign;
+                            while ch(k).is_some_and(|c| c.is_ascii_digit()) { k += 1; }
+                        } else if sign == 1 || !next.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                            k += 1 + sign;
+                            missing = true;
+                        }
+                    }
+                    let text = &s[at(start)..at(k)];
+                    match (text.parse::<u64>(), text.parse::<f64>()) {
+                        _ if missing => {
+                            errors.push(bad(start, k));
+                            Token::num(0.)
+                        },
+                        (Ok(n), _) => Token::int(n),
+                        (_, Ok(x)) if x.is_finite() => Token::dec(x, text),
+                        _ => {
+                            errors.push(bad(start, k));
+                            Token::num(0.)
+                        }
+                    }
+                },
+                '(' | '[' => {
+                    brackets.push((c, at(start)));
+                    Token::oper(if c == '(' { TokenType::LeftBracket } else { TokenType::LeftSquare })
+                },
+                ')' | ']' => {
+                    let open = if c == ')' { '(' } else { '[' };
+                    let b = brackets.pop();
+                    if b.is_none_or(|b| b.0 != open) {
+                        errors.push(CalcError::new(ErrorKind::UnbalancedBracket, at(start)..at(k)));
+                    }
+                    // скобка другого вида закрывает открытую
+                    let Some((b, _)) = b else { continue };
+                    close(b)
+                },
+                ',' => Token::oper(TokenType::Comma),
+                '=' => Token::oper(TokenType::Assign),
+                ';' => Token::oper(TokenType::Semicolon),
+                c if c.is_alphabetic() => {
+                    while ch(k).is_some_and(|c| c.is_alphanumeric() || c == '_') { k += 1; }
+                    Token::name(&s[at(start)..at(k)])
+                },
+                _ => {
+                    errors.push(CalcError::new(ErrorKind::UnknownSymbol(c), at(start)..at(k)));
+                    continue
+                }
+            };
+            t.span = at(start)..at(k);
+            items.push(t);
+        }
+        // проверяем баланс скобок
+        while let Some((c, p)) = brackets.pop() {
+            errors.push(CalcError::new(ErrorKind::UnbalancedBracket, p..p + 1));
+            let mut t = close(c);
+            t.span = s.len()..s.len();
+            items.push(t);
+        }
+        let mut t = Token::null();
+        t.span = s.len()..s.len();
+        items.push(t);
+        (Self { src: s.to_string(), items }, errors)
+    }
+}
+
+// Синтаксический анализ: строим дерево разбора по списку лексем.
+// Ошибка в аргументе функции, элементе списка или выражении в скобках
+// запоминается, и разбор продолжается со следующей запятой или закрывающей
+// скобки, ошибка в операторе - со следующего ';'. Так за один проход
+// находятся все ошибки формулы
+struct Parser<'a> {
+    tokens: Formula,
+    pos: usize,
+    ops: &'a OpTable,
+    // найденные ошибки в порядке обнаружения
+    errors: Vec<CalcError>,
+    // предупреждения о неоднозначной записи
+    warnings: Vec<CalcError>
+}
+
+// Разбор одного выражения
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn parse(s: &str) -> Result<Expr, CalcError> {
+    parse_with(s, &OpTable::default())
+}
+
+// Разбор одного выражения с заданной таблицей операций
+pub fn parse_with(s: &str, ops: &OpTable) -> Result<Expr, CalcError> {
+    let mut p = Parser::new(s, ops);
+    if p.tokens.items[p.pos].lex_type == TokenType::Eof {
+        return p.finish(Expr::num(0.))
+    }
+    // после выражения не должно оставаться лексем
+    let x = p.parse_item(&[TokenType::Eof]);
+    p.finish(x)
+}
+
+// Результат разбора программы с восстановлением после ошибок
+pub(crate) struct Parsed {
+    // операторы без ошибок
+    pub stmts: Vec<Stmt>,
+    pub errors: Vec<CalcError>,
+    pub warnings: Vec<CalcError>
+}
+
+pub(crate) fn parse_all(s: &str, ops: &OpTable) -> Parsed {
+    let mut p = Parser::new(s, ops);
+    let mut stmts = vec![];
+    loop {
+        match p.tokens.items[p.pos].lex_type {
+            TokenType::Eof => return Parsed { stmts, errors: p.errors, warnings: p.warnings },
+            // пустой оператор
+            TokenType::Semicolon => p.pos += 1,
+            _ => {
+                let stmt = p.parse_stmt().and_then(|stmt| match p.tokens.items[p.pos].lex_type {
+                    TokenType::Semicolon | TokenType::Eof => Ok(stmt),
+                    _ => Err(p.out())
+                });
+                match stmt {
+                    Ok(stmt) => stmts.push(stmt),
+                    Err(e) => {
+                        p.errors.push(e);
+                        p.skip(&[TokenType::Semicolon]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &str, ops: &'a OpTable) -> Self {
+        let (tokens, errors) = Formula::new(s, ops);
+        Self { tokens, pos: 0, ops, errors, warnings: vec![] }
+    }
+
+    // Результат разбора или первая найденная ошибка
+    fn finish<T>(self, x: T) -> Result<T, CalcError> {
+        self.errors.into_iter().next().map_or(Ok(x), Err)
+    }
+
+    fn parse_stmt(&mut self) -> Result<Stmt, CalcError> {
+        let t = &self.tokens.items[self.pos];
+        let (name, span) = (t.lex_str_value.clone(), t.span.clone());
+        if t.lex_type == TokenType::Name
+            && self.tokens.items[self.pos + 1].lex_type == TokenType::Assign {
+            self.pos += 2;
+            let expr = self.parse_expr()?;
+            return Ok(Stmt::Assign { name, expr, span })
+        }
+        if let Some((params, next)) = self.def_params()? {
+            self.pos = next;
+            let body = self.parse_expr()?;
+            return Ok(Stmt::Def { fname: name.to_ascii_lowercase(), params, body, span })
+        }
+        Ok(Stmt::Expr(self.parse_expr()?))
+    }
+
+    // Если с текущей позиции начинается определение функции
+    // NAME '(' [params]* ')' '=', возвращает список параметров
+    // и позицию лексемы, следующей за '='
+    fn def_params(&self) -> Result<Option<(Vec<String>, usize)>, CalcError> {
+        let items = &self.tokens.items;
+        if items[self.pos].lex_type != TokenType::Name
+            || items[self.pos + 1].lex_type != TokenType::LeftBracket {
+            return Ok(None)
+        }
+        let mut params: Vec<String> = vec![];
+        let mut k = self.pos + 2;
+        if items[k].lex_type == TokenType::RightBracket {
+            k += 1;
+        } else {
+            loop {
+                if items[k].lex_type != TokenType::Name { return Ok(None) }
+                params.push(items[k].lex_str_value.clone());
+                match items[k + 1].lex_type {
+                    TokenType::Comma => k += 2,
+                    TokenType::RightBracket => { k += 2; break },
+                    _ => return Ok(None)
+                }
+            }
+        }
+        if items[k].lex_type != TokenType::Assign { return Ok(None) }
+        // параметры не должны повторяться
+        for (i, x) in params.iter().enumerate() {
+            if params[..i].contains(x) {
+                let t = &items[self.pos + 2 + 2 * i];
+                return Err(CalcError::new(ErrorKind::UnexpectedToken(x.clone()), t.span.clone()))
+            }
+        }
+        Ok(Some((params, k + 1)))
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, CalcError> {
+        self.parse_bp(0)
+    }
+
+    // Выражение из операций с приоритетом не ниже min
+    fn parse_bp(&mut self, min: u8) -> Result<Expr, CalcError> {
+        let mut x = match self.op_def(Fixity::Prefix) {
+            Some(op) => {
+                let span = self.tokens.items[self.pos].span.clone();
+                self.pos += 1;
+                let a = self.parse_bp(op.prec)?;
+                op.build(vec![a], span)
+            },
+            None => self.parse_index()?
+        };
+        loop {
+            let span = self.tokens.items[self.pos].span.clone();
+            if let Some(op) = self.op_def(Fixity::Postfix).filter(|op| op.prec >= min) {
+                self.pos += 1;
+                x = op.build(vec![x], span);
+                continue
+            }
+            let Some(op) = self.op_def(Fixity::Infix(Assoc::Left)).filter(|op| op.prec >= min) else {
+                // неявное умножение: правый множитель связывает сильнее, как после '*'
+                match self.implicit() {
+                    Some(prec) if prec >= min => {
+                        let y = self.parse_bp(prec + 1)?;
+                        x = Expr::Binary(BinOp::Mul, Box::new(x), Box::new(y), span);
+                        continue
+                    },
+                    _ => return Ok(x)
+                }
+            };
+            self.pos += 1;
+            // у левоассоциативной операции правый операнд связывает сильнее
+            let next = if op.fixity == Fixity::Infix(Assoc::Left) { op.prec + 1 } else { op.prec };
+            let y = self.parse_bp(next)?;
+            // 1/2x = (1/2)*x: неявное умножение после операции, связывающей сильнее,
+            // относится ко всему ее результату
+            if self.implicit().is_some_and(|prec| next > prec) && op.action != OpAction::Binary(BinOp::Mul) {
+                let kind = ErrorKind::Ambiguous(format!("{x} {} {y}", op.symbol));
+                self.warnings.push(CalcError::new(kind, self.tokens.items[self.pos].span.clone()));
+            }
+            x = op.build(vec![x, y], span);
+        }
+    }
+
+    // Приоритет неявного умножения, если оно включено и с текущей лексемы
+    // начинается множитель: имя, не являющееся словом-операцией, или '('
+    fn implicit(&self) -> Option<u8> {
+        let prec = self.ops.implicit_mul()?;
+        let t = &self.tokens.items[self.pos];
+        let start = match t.lex_type {
+            TokenType::LeftBracket => true,
+            TokenType::Name => [Fixity::Prefix, Fixity::Infix(Assoc::Left), Fixity::Postfix]
+                .into_iter().all(|fixity| self.op_def(fixity).is_none()),
+            _ => false
+        };
+        start.then_some(prec)
+    }
+
+    // Операция вида fixity в текущей позиции: знак или слово вроде xor
+    fn op_def(&self, fixity: Fixity) -> Option<&'a OpDef> {
+        let t = &self.tokens.items[self.pos];
+        let symbol = match t.lex_type {
+            TokenType::Operator => t.lex_str_value.clone(),
+            TokenType::Name => t.lex_str_value.to_lowercase(),
+            _ => return None
+        };
+        match fixity {
+            Fixity::Prefix => self.ops.prefix(&symbol),
+            Fixity::Infix(_) => self.ops.infix(&symbol),
+            Fixity::Postfix => self.ops.postfix(&symbol)
+        }
+    }
+
+    // Операнд с номерами элементов списка: xs[0], f(x)[1][2]
+    fn parse_index(&mut self) -> Result<Expr, CalcError> {
+        let mut x = self.parse_pfactor()?;
+        while self.tokens.items[self.pos].lex_type == TokenType::LeftSquare {
+            let span = self.tokens.items[self.pos].span.clone();
+            self.pos += 1;
+            let i = self.parse_item(&[TokenType::RightSquare]);
+            if self.tokens.items[self.pos].lex_type != TokenType::RightSquare {
+                return Err(self.out())
+            }
+            self.pos += 1;
+            x = Expr::Index(Box::new(x), Box::new(i), span);
+        }
+        Ok(x)
+    }
+
+    fn parse_pfactor(&mut self) -> Result<Expr, CalcError> {
+        match self.tokens.items[self.pos].lex_type {
+            TokenType::Number => {
+                let t = &self.tokens.items[self.pos];
+                let x = match t.lex_int_value {
+                    Some(n) => Expr::Int(n),
+                    None if !t.lex_str_value.is_empty() => Expr::Dec(t.lex_num_value, t.lex_str_value.clone()),
+                    None => Expr::num(t.lex_num_value)
+                };
+                self.pos += 1;
+                // число с единицей измерения - произведение, связывающее сильнее '*' и '/'
+                let t = &self.tokens.items[self.pos];
+                if !self.ops.units() || t.lex_type != TokenType::Name || unit(&t.lex_str_value).is_none()
+                    || self.tokens.items[self.pos + 1].lex_type == TokenType::LeftBracket {
+                    return Ok(x)
+                }
+                let span = t.span.clone();
+                let mut u = Expr::var(&t.lex_str_value, span.clone());
+                self.pos += 1;
+                // степень единицы: 2 m^2
+                let pow = self.op_def(Fixity::Infix(Assoc::Left))
+                    .filter(|op| op.action == OpAction::Binary(BinOp::Pow));
+                if let Some(op) = pow {
+                    let span = self.tokens.items[self.pos].span.clone();
+                    self.pos += 1;
+                    u = op.build(vec![u, self.parse_bp(op.prec)?], span);
+                }
+                Ok(Expr::Binary(BinOp::Mul, Box::new(x), Box::new(u), span))
+            },
+            TokenType::Name => {
+                let name = self.tokens.items[self.pos].lex_str_value.clone();
+                let span = self.tokens.items[self.pos].span.clone();
+                self.pos += 1;
+                // имя без скобок - переменная
+                if self.tokens.items[self.pos].lex_type != TokenType::LeftBracket {
+                    return Ok(Expr::var(&name, span))
+                }
+                // function := NAME '(' [args]* ')'
+                // args := expr | [',' expr]*
+                let fname = name.to_ascii_lowercase();
+                let mut args = vec![];
+                self.pos += 1;
+                if self.tokens.items[self.pos].lex_type != TokenType::RightBracket {
+                    loop {
+                        args.push(self.parse_item(&[TokenType::Comma, TokenType::RightBracket]));
+                        if self.tokens.items[self.pos].lex_type == TokenType::Comma {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                if self.tokens.items[self.pos].lex_type != TokenType::RightBracket {
+                    return Err(self.out())
+                }
+                self.pos += 1;
+                Ok(Expr::call(&fname, args, span))
+            }
+            // list := '[' [expr [',' expr]*]? ']'
+            TokenType::LeftSquare => {
+                let span = self.tokens.items[self.pos].span.clone();
+                let mut xs = vec![];
+                self.pos += 1;
+                if self.tokens.items[self.pos].lex_type != TokenType::RightSquare {
+                    loop {
+                        xs.push(self.parse_item(&[TokenType::Comma, TokenType::RightSquare]));
+                        if self.tokens.items[self.pos].lex_type == TokenType::Comma {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                if self.tokens.items[self.pos].lex_type != TokenType::RightSquare {
+                    return Err(self.out())
+                }
+                self.pos += 1;
+                Ok(Expr::List(xs, span))
+            },
+            TokenType::LeftBracket => {
+                self.pos += 1;
+                let x = self.parse_item(&[TokenType::RightBracket]);
+                if self.tokens.items[self.pos].lex_type != TokenType::RightBracket {
+                    return Err(self.out())
+                }
+                self.pos += 1;
+                Ok(x)
+            },
+            _ => Err(self.out())
+        }
+    }
+
+    // Выражение, за которым следует одна из лексем end. Ошибка в нем запоминается,
+    // разбор продолжается с ближайшей лексемы end, выражение заменяется нулем
+    fn parse_item(&mut self, end: &[TokenType]) -> Expr {
+        let x = self.parse_expr().and_then(|x| {
+            if end.contains(&self.tokens.items[self.pos].lex_type) { Ok(x) } else { Err(self.out()) }
+        });
+        x.unwrap_or_else(|e| {
+            self.errors.push(e);
+            self.skip(end);
+            Expr::num(0.)
+        })
+    }
+
+    // Пропускает лексемы до ближайшей из stop вне вложенных скобок или до конца формулы
+    fn skip(&mut self, stop: &[TokenType]) {
+        let mut depth = 0;
+        loop {
+            let t = self.tokens.items[self.pos].lex_type;
+            if t == TokenType::Eof || depth == 0 && stop.contains(&t) { return }
+            match t {
+                TokenType::LeftBracket | TokenType::LeftSquare => depth += 1,
+                TokenType::RightBracket | TokenType::RightSquare => depth -= 1,
+                _ => ()
+            }
+            self.pos += 1;
+        }
+    }
+
+    // Ошибка: недопустимая лексема в текущей позиции
+    fn out(&self) -> CalcError {
+        let t = &self.tokens.items[self.pos];
+        let text = self.tokens.src[t.span.clone()].to_string();
+        CalcError::new(ErrorKind::UnexpectedToken(text), t.span.clone())
+    }
+}
+
+// Формула разбирается один раз и может вычисляться многократно
+pub struct Calc {
+    stmts: Vec<Stmt>,
+    // предупреждения разбора
+    warnings: Vec<CalcError>
+}
+
+impl Calc {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn new(s: &str) -> Result<Self, CalcError> {
+        Self::with_ops(s, &OpTable::default())
+    }
+
+    // Разбор с собственной таблицей операций
+    pub fn with_ops(s: &str, ops: &OpTable) -> Result<Self, CalcError> {
+        let p = parse_all(s, ops);
+        if let Some(e) = p.errors.into_iter().next() { return Err(e) }
+        Ok(Self { stmts: p.stmts, warnings: p.warnings })
+    }
+
+    // Разбор для вычисления в режиме mode: в Mode::Units после чисел
+    // разбираются единицы измерения (3 km), в остальных режимах - нет
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn with_mode(s: &str, mode: Mode) -> Result<Self, CalcError> {
+        let mut ops = OpTable::default();
+        ops.set_units(mode == Mode::Units);
+        Self::with_ops(s, &ops)
+    }
+
+    pub fn stmts(&self) -> &[Stmt] { &self.stmts }
+
+    // Неоднозначная запись в формуле, например 1/2x при неявном умножении
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn warnings(&self) -> &[CalcError] { &self.warnings }
+
+    // Вычисление в пустом окружении
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn calc_expr(&self) -> Result<f64, CalcError> {
+        self.calc_with(&mut Env::new())
+    }
+
+    // Вычисление в окружении вызывающей стороны:
+    // присваивания и определения функций сохраняются в env,
+    // результат - значение последнего оператора-выражения
+    pub fn calc_value(&self, env: &mut Env) -> Result<Value, CalcError> {
+        let mut x = Value::Num(0.);
+        for stmt in &self.stmts {
+            if let Some(y) = stmt.eval(env)? { x = y; }
+        }
+        Ok(x)
+    }
+
+    // То же, но результат должен быть числом
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn calc_with(&self, env: &mut Env) -> Result<f64, CalcError> {
+        // положение последнего выражения - для ошибки, если его значение логическое
+        let span = self.stmts.iter().rev().find_map(|stmt| match stmt {
+            Stmt::Expr(e) | Stmt::Assign { expr: e, .. } => Some(e.span()),
+            Stmt::Def { .. } => None
+        }).unwrap_or(0..0);
+        self.calc_value(env)?.num().map_err(|k| CalcError::new(k, span))
+    }
+
+    // Вычисление в заданном числовом режиме, результат в виде строки
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn calc_mode(&self, mode: Mode, env: &mut Env) -> Result<String, CalcError> {
+        self.calc_mode_as(mode, env, None)
+    }
+
+    // То же; результат сохраняется в переменной name окружения, как ans в REPL
+    pub fn calc_mode_as(&self, mode: Mode, env: &mut Env, name: Option<&str>) -> Result<String, CalcError> {
+        match mode {
+            Mode::Float => {
+                let x = self.calc_value(env)?;
+                if let Some(name) = name { env.set_value(name, x.clone()) }
+                Ok(x.to_string())
+            },
+            Mode::Rational => self.run(&RationalBackend, env, name).map(|x| x.to_string()),
+            Mode::Decimal(digits) => self.run(&DecimalBackend { digits }, env, name).map(|x| x.to_string()),
+            Mode::Complex => self.run(&ComplexBackend, env, name).map(|x| x.to_string()),
+            Mode::Integer(mode) => self.run(&IntBackend { mode }, env, name)
+                .map(|x| x.map(|x| mode.format(x)).to_string()),
+            Mode::Units => self.run(&UnitBackend, env, name).map(|x| x.to_string())
+        }
+    }
+
+    fn run<B: Backend>(&self, b: &B, env: &mut Env, name: Option<&str>) -> Result<Value<B::Value>, CalcError> {
+        let x = eval_in(b, &self.stmts, env)?;
+        if let Some(name) = name { backend::keep(b, env, name, x.clone()) }
+        Ok(x)
+    }
+
+    // Байт-код для многократного вычисления с разными значениями переменных
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn compile(&self, env: &Env) -> Result<Compiled, CalcError> {
+        compile(&self.stmts, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(s: &str) -> Result<f64, ErrorKind> {
+        Calc::new(s).and_then(|c| c.calc_expr()).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(calc("2 + 3 * 4"), Ok(14.));
+        assert_eq!(calc("2 ^ 3 ^ 2"), Ok(512.));
+        assert_eq!(calc("-2 ^ 2"), Ok(-4.));
+        assert_eq!(calc("10 - 4 - 3"), Ok(3.));
+        assert_eq!(calc("3! + 1"), Ok(7.));
+        assert_eq!(calc("1 << 4 | 1"), Ok(17.));
+    }
+
+    #[test]
+    fn numbers_and_their_errors() {
+        assert_eq!(calc("1.5e3 + 2E-1 + .5"), Ok(1500.7));
+        let bad = |s: &str| Calc::new(s).map(|_| ()).map_err(|e| (e.kind, e.span));
+        assert_eq!(bad("2 * 1e"), Err((ErrorKind::BadNumber("1e".to_string()), 4..6)));
+        assert_eq!(bad("1e+ 2"), Err((ErrorKind::BadNumber("1e+".to_string()), 0..3)));
+        assert_eq!(bad("1.2.3"), Err((ErrorKind::BadNumber("1.2.3".to_string()), 0..5)));
+        assert_eq!(bad("0x1g"), Err((ErrorKind::BadNumber("0x1g".to_string()), 0..4)));
+        assert_eq!(bad("√ + 1"), Err((ErrorKind::UnknownSymbol('√'), 0..3)));
+        let mut ops = OpTable::default();
+        ops.set_implicit_mul(true);
+        let c = Calc::with_ops("2exp(0)", &ops).unwrap();
+        assert_eq!(c.calc_with(&mut Env::new()), Ok(2.));
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        let mut ops = OpTable::default();
+        let mut env = Env::new();
+        env.set("x", 3.);
+        env.set("a", 5.);
+        assert!(Calc::with_ops("2x", &ops).is_err());
+        ops.set_implicit_mul(true);
+        let mut calc = |s: &str| Calc::with_ops(s, &ops).and_then(|c| c.calc_with(&mut env)).map_err(|e| e.kind);
+        assert_eq!(calc("2x + 1"), Ok(7.));
+        assert_eq!(calc("3(x + 1)"), Ok(12.));
+        assert_eq!(calc("(a + x)(a - x)"), Ok(16.));
+        assert_eq!(calc("2 sin(0) + x a"), Ok(15.));
+        assert_eq!(calc("-2x^2"), Ok(-18.));
+        // 1/2x = (1/2)*x, как при явном умножении, но с предупреждением
+        assert_eq!(calc("1/2x"), Ok(1.5));
+        let warnings = |s: &str| Calc::with_ops(s, &ops).unwrap().warnings().iter()
+            .map(|w| (w.kind.clone(), w.span.clone())).collect::<Vec<_>>();
+        assert_eq!(warnings("1/2x"), [(ErrorKind::Ambiguous("1 / 2".to_string()), 3..4)]);
+        assert_eq!(warnings("2^3x"), [(ErrorKind::Ambiguous("2 ^ 3".to_string()), 3..4)]);
+        assert!(warnings("2*3x + x/2").is_empty());
+    }
+
+    #[test]
+    fn programs_keep_their_definitions() {
+        let mut env = Env::new();
+        let c = Calc::new("a = 3; f(x) = x^2 + a; f(2)").unwrap();
+        assert_eq!(c.calc_with(&mut env), Ok(7.));
+        assert_eq!(env.get("a"), Some(3.));
+        assert_eq!(Calc::new("f(4)").unwrap().calc_with(&mut env), Ok(19.));
+        assert_eq!(calc("f(x) = x; f(1, 2)").map_err(|e| matches!(e, ErrorKind::WrongArity { .. })), Err(true));
+    }
+}
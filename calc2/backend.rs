@@ -0,0 +1,700 @@
+// Числовые режимы вычислителя: дерево разбора вычисляется
+// над произвольным типом чисел, реализующим Backend
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::ops::Range;
+use super::bigint::BigInt;
+use super::complex::Complex;
+use super::decimal::Decimal;
+use super::diff::expand_diff;
+use super::expr::{BinOp, Expr, Stmt, UnOp};
+use super::eval::{is_series, MAX_TERMS};
+use super::funcs::{out1, Angle};
+use super::integer::IntMode;
+use super::matrix;
+use super::rational::Rational;
+use super::value::{spread, truth};
+use super::{Arity, CalcError, Env, ErrorKind, Value};
+
+// Наибольший показатель степени в точных режимах
+const MAX_EXP: i64 = 100_000;
+// Наибольший аргумент факториала в точных режимах: 5000! - около 16 тысяч цифр
+const MAX_FACT: i64 = 5_000;
+
+pub trait Backend {
+    type Value: Clone + Display + 'static;
+    // число из переменной окружения или промежуточное значение вычислителя
+    fn num(&self, x: f64) -> Result<Self::Value, ErrorKind>;
+    // число из литерала формулы
+    fn literal(&self, x: f64) -> Result<Self::Value, ErrorKind> { self.num(x) }
+    // целый литерал больше 2^53
+    fn int(&self, n: u64) -> Result<Self::Value, ErrorKind> { self.literal(n as f64) }
+    // литерал s больше чем из 15 значащих цифр; x - его приближение в f64
+    fn decimal(&self, x: f64, _s: &str) -> Result<Self::Value, ErrorKind> { self.literal(x) }
+    fn neg(&self, a: &Self::Value) -> Result<Self::Value, ErrorKind>;
+    // побитовое отрицание; есть только в целочисленном режиме
+    fn not(&self, _a: &Self::Value) -> Result<Self::Value, ErrorKind> {
+        Err(ErrorKind::Unsupported("~".to_string()))
+    }
+    fn binary(&self, op: BinOp, a: &Self::Value, b: &Self::Value) -> Result<Self::Value, ErrorKind>;
+    // сравнение op: < <= > >= == !=
+    fn compare(&self, op: BinOp, a: &Self::Value, b: &Self::Value) -> Result<bool, ErrorKind>;
+    // количество аргументов встроенной функции; None - функция недоступна в режиме
+    fn arity(&self, fname: &str) -> Option<Arity>;
+    fn call(&self, fname: &str, args: &[Self::Value]) -> Result<Self::Value, ErrorKind>;
+    // именованная константа режима, например мнимая единица i
+    fn constant(&self, _name: &str) -> Option<Self::Value> { None }
+    // номер элемента списка; None - число не целое
+    fn index(&self, _a: &Self::Value) -> Option<i64> { None }
+    // режим, в числах которого окружение хранит присвоенные значения
+    fn mode(&self) -> Option<Mode> { None }
+    // приближение числа в f64 для других режимов; None - приближения нет
+    fn float(&self, _a: &Self::Value) -> Option<f64> { None }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    #[default]
+    Float,
+    // точные дроби
+    Rational,
+    // десятичные числа с заданным числом знаков после точки
+    Decimal(usize),
+    // комплексные числа с мнимой единицей i
+    Complex,
+    // целые числа заданной разрядности
+    Integer(IntMode),
+    // величины с единицами измерения
+    Units
+}
+
+// Число знаков после точки в десятичном режиме, если оно не задано
+const DECIMAL_DIGITS: usize = 30;
+
+impl Mode {
+    // float, rational, decimal 30, complex, units, int 32 unsigned checked hex
+    pub fn name(self) -> String {
+        match self {
+            Mode::Float => "float".to_string(),
+            Mode::Rational => "rational".to_string(),
+            Mode::Decimal(digits) => format!("decimal {digits}"),
+            Mode::Complex => "complex".to_string(),
+            Mode::Units => "units".to_string(),
+            Mode::Integer(m) => {
+                let base = match m.base {
+                    16 => "hex",
+                    8 => "oct",
+                    2 => "bin",
+                    _ => "dec"
+                };
+                format!("int {} {} {} {base}", m.bits, if m.signed { "signed" } else { "unsigned" },
+                    if m.checked { "checked" } else { "wrapping" })
+            }
+        }
+    }
+
+    // Режим по имени с параметрами в любом порядке: decimal [digits],
+    // int [8|16|32|64] [signed|unsigned] [checked|wrapping] [dec|hex|oct|bin]
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut words = s.split_whitespace();
+        let mode = match words.next()? {
+            "float" => Mode::Float,
+            "rational" => Mode::Rational,
+            "decimal" => Mode::Decimal(match words.next() {
+                Some(w) => w.parse().ok().filter(|d| (1..=1000).contains(d))?,
+                None => DECIMAL_DIGITS
+            }),
+            "complex" => Mode::Complex,
+            "units" => Mode::Units,
+            "int" => {
+                let mut m = IntMode::default();
+                for w in words.by_ref() {
+                    match w {
+                        "signed" | "unsigned" => m.signed = w == "signed",
+                        "checked" | "wrapping" => m.checked = w == "checked",
+                        "dec" => m.base = 10,
+                        "hex" => m.base = 16,
+                        "oct" => m.base = 8,
+                        "bin" => m.base = 2,
+                        w => m.bits = w.parse().ok().filter(|b| matches!(b, 8 | 16 | 32 | 64))?
+                    }
+                }
+                Mode::Integer(m)
+            },
+            _ => return None
+        };
+        words.next().is_none().then_some(mode)
+    }
+}
+
+// Вычисление программы в заданном режиме. Присвоенные значения сохраняются
+// в окружении в числах режима; переменные других режимов переводятся в числа режима
+pub fn eval_in<B: Backend>(b: &B, stmts: &[Stmt], env: &mut Env) -> Result<Value<B::Value>, CalcError> {
+    let mut x = Value::Num(b.num(0.).map_err(|k| CalcError::new(k, 0..0))?);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Def { fname, params, body, .. } => env.define(fname, params.clone(), body.clone()),
+            Stmt::Assign { name, expr, .. } => {
+                x = Eval { b, env }.expr(expr, &[], 0)?;
+                keep(b, env, name, x.clone());
+            },
+            Stmt::Expr(expr) => x = Eval { b, env }.expr(expr, &[], 0)?
+        }
+    }
+    Ok(x)
+}
+
+// Сохраняет значение режима в переменной окружения name
+pub(crate) fn keep<B: Backend>(b: &B, env: &mut Env, name: &str, x: Value<B::Value>) {
+    let approx = x.clone().map(|x| b.float(&x).ok_or(())).lift().ok();
+    env.set_exact(name, b.mode(), x, approx);
+}
+
+struct Eval<'a, B: Backend> {
+    b: &'a B,
+    env: &'a Env
+}
+
+type Locals<'a, T> = [(&'a str, Value<T>)];
+
+impl<B: Backend> Eval<'_, B> {
+    fn expr(&self, e: &Expr, locals: &Locals<B::Value>, depth: usize) -> Result<Value<B::Value>, CalcError> {
+        match e {
+            Expr::Num(x) => self.b.literal(*x).map(Value::Num).map_err(|k| CalcError::new(k, 0..0)),
+            Expr::Int(n) => self.b.int(*n).map(Value::Num).map_err(|k| CalcError::new(k, 0..0)),
+            Expr::Dec(x, s) => self.b.decimal(*x, s).map(Value::Num).map_err(|k| CalcError::new(k, 0..0)),
+            Expr::Var { name, span } => {
+                if let Some((_, v)) = locals.iter().find(|(k, _)| k == name) { return Ok(v.clone()) }
+                if let Some(v) = self.env.exact::<Value<B::Value>>(name, self.b.mode()) { return Ok(v.clone()) }
+                match self.env.value(name) {
+                    Some(Value::Bool(x)) => Ok(Value::Bool(x)),
+                    Some(x) => x.map(|x| self.b.num(x)).lift().map_err(|k| CalcError::new(k, span.clone())),
+                    None => truth(name).map(Value::Bool)
+                        .or_else(|| self.b.constant(name).map(Value::Num))
+                        .ok_or(CalcError::new(ErrorKind::UnknownVariable(name.clone()), span.clone()))
+                }
+            },
+            Expr::Unary(UnOp::LNot, a, span) => Ok(Value::Bool(!self.cond(a, locals, depth, span)?)),
+            Expr::Unary(op, a, span) => {
+                // -128 в 8-битном знаковом режиме - отрицательный литерал, а не отрицание 128
+                if let (UnOp::Neg, Expr::Num(x)) = (op, &**a) {
+                    return self.b.literal(-x).map(Value::Num).map_err(|k| CalcError::new(k, span.clone()))
+                }
+                let x = match self.expr(a, locals, depth)? {
+                    Value::Bool(_) => return Err(CalcError::new(
+                        ErrorKind::Type("expected a number, found a boolean".to_string()), span.clone())),
+                    x => x
+                };
+                x.map(|x| if *op == UnOp::Neg { self.b.neg(&x) } else { self.b.not(&x) })
+                    .lift().map_err(|k| CalcError::new(k, span.clone()))
+            },
+            // второй операнд and, or вычисляется, только если от него зависит результат
+            Expr::Binary(op, a, c, span) if op.is_logical() => {
+                let x = self.cond(a, locals, depth, span)?;
+                if x == (*op == BinOp::LOr) { return Ok(Value::Bool(x)) }
+                Ok(Value::Bool(self.cond(c, locals, depth, span)?))
+            },
+            Expr::Binary(op, a, c, span) if op.is_comparison() => {
+                let (x, y) = (self.expr(a, locals, depth)?, self.expr(c, locals, depth)?);
+                x.compare(*op, y, |op, x, y| self.b.compare(op, &x, &y))
+                    .map(Value::Bool).map_err(|k| CalcError::new(k, span.clone()))
+            },
+            Expr::Binary(op, a, c, span) => {
+                let (x, y) = (self.expr(a, locals, depth)?, self.expr(c, locals, depth)?);
+                x.zip(y, |x, y| self.b.binary(*op, &x, &y)).map_err(|k| CalcError::new(k, span.clone()))
+            },
+            // матрицы есть только в режиме с плавающей точкой
+            Expr::List(xs, span) if xs.iter().any(|x| matches!(x, Expr::List(..))) =>
+                Err(CalcError::new(ErrorKind::Unsupported("matrices".to_string()), span.clone())),
+            Expr::List(xs, span) => xs.iter().map(|x| self.num(x, locals, depth, span))
+                .collect::<Result<_, _>>().map(Value::List),
+            Expr::Index(a, i, span) => {
+                let xs = self.expr(a, locals, depth)?;
+                let i = self.num(i, locals, depth, span)?;
+                let Some(k) = self.b.index(&i) else {
+                    return Err(CalcError::new(ErrorKind::Domain(format!("invalid index {i}")), span.clone()))
+                };
+                xs.at(k).map_err(|k| CalcError::new(k, span.clone()))
+            },
+            Expr::Call { fname, args, span } => {
+                if fname == "diff" && self.env.form(fname) {
+                    return self.expr(&expand_diff(args, span, Some(self.env))?, locals, depth)
+                }
+                // if(cond, a, b) - вычисляется только выбранная ветвь
+                if fname == "if" && self.env.form(fname) {
+                    if args.len() != 3 {
+                        return Err(out1(fname, Arity::Exact(3), args.len(), span.clone()))
+                    }
+                    let branch = if self.cond(&args[0], locals, depth, span)? { &args[1] } else { &args[2] };
+                    return self.expr(branch, locals, depth)
+                }
+                if is_series(fname, args) && self.env.func(fname).is_none() {
+                    return self.series(fname, args, locals, depth, span)
+                }
+                // численные методы, range и функции матриц есть только в режиме с плавающей точкой
+                let float_only = matches!(fname.as_str(), "solve" | "integrate" | "range")
+                    || matrix::FUNCS.contains(&fname.as_str());
+                if float_only && self.env.form(fname) {
+                    return Err(CalcError::new(ErrorKind::Unsupported(fname.clone()), span.clone()))
+                }
+                let args = args.iter().map(|a| self.expr(a, locals, depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(f) = self.env.func(fname) {
+                    if f.params.len() != args.len() {
+                        return Err(out1(fname, Arity::Exact(f.params.len()), args.len(), span.clone()))
+                    }
+                    if depth >= self.env.max_depth() {
+                        return Err(CalcError::new(ErrorKind::RecursionLimit(fname.clone()), span.clone()))
+                    }
+                    let locals: Vec<_> = f.params.iter().map(|p| p.as_str()).zip(args).collect();
+                    return self.expr(&f.body, &locals, depth + 1)
+                }
+                let Some(arity) = self.b.arity(fname) else {
+                    let kind = if self.env.registry().get(fname).is_some() {
+                        ErrorKind::Unsupported(fname.clone())
+                    } else {
+                        ErrorKind::UnknownFunction(fname.clone())
+                    };
+                    return Err(CalcError::new(kind, span.clone()))
+                };
+                let variadic = matches!(arity, Arity::AtLeast(_));
+                if !variadic && !arity.accepts(args.len()) {
+                    return Err(out1(fname, arity, args.len(), span.clone()))
+                }
+                // встроенные функции принимают числа и списки чисел
+                spread(args, variadic, |xs| {
+                    if !arity.accepts(xs.len()) {
+                        return Err(ErrorKind::WrongArity { fname: fname.clone(), expected: arity, got: xs.len() })
+                    }
+                    if matches!(fname.as_str(), "sin" | "cos" | "tan") {
+                        return self.b.call(fname, &[self.radians(&xs[0])?])
+                    }
+                    let y = self.b.call(fname, xs)?;
+                    if matches!(fname.as_str(), "asin" | "acos" | "atan" | "atan2") {
+                        return self.angle(&y)
+                    }
+                    Ok(y)
+                }).map_err(|k| CalcError::new(k, span.clone()))
+            }
+        }
+    }
+
+    // Угол в мере реестра, переведенный в радианы
+    fn radians(&self, x: &B::Value) -> Result<B::Value, ErrorKind> {
+        match self.radian()? {
+            Some(k) => self.b.binary(BinOp::Mul, x, &k),
+            None => Ok(x.clone())
+        }
+    }
+
+    // Угол в радианах, переведенный в меру реестра
+    fn angle(&self, x: &B::Value) -> Result<B::Value, ErrorKind> {
+        match self.radian()? {
+            Some(k) => self.b.binary(BinOp::Div, x, &k),
+            None => Ok(x.clone())
+        }
+    }
+
+    // Радиан в единицах меры реестра; None - мера реестра радианы
+    fn radian(&self) -> Result<Option<B::Value>, ErrorKind> {
+        let d = match self.env.registry().angle() {
+            Angle::Radians => return Ok(None),
+            Angle::Degrees => 180.,
+            Angle::Gradians => 200.
+        };
+        self.b.binary(BinOp::Div, &self.b.call("pi", &[])?, &self.b.num(d)?).map(Some)
+    }
+
+    // sum(expr, k, from, to), prod(expr, k, from, to) в числах режима
+    fn series(&self, fname: &str, args: &[Expr], locals: &Locals<B::Value>, depth: usize,
+              span: &Range<usize>) -> Result<Value<B::Value>, CalcError> {
+        if args.len() != 4 {
+            return Err(out1(fname, Arity::Exact(4), args.len(), span.clone()))
+        }
+        let Expr::Var { name, .. } = &args[1] else {
+            return Err(CalcError::new(ErrorKind::ExpectedVariable, span.clone()))
+        };
+        let (from, to) = (self.num(&args[2], locals, depth, span)?, self.num(&args[3], locals, depth, span)?);
+        let (op, unit) = if fname == "sum" { (BinOp::Add, 0.) } else { (BinOp::Mul, 1.) };
+        let one = self.b.num(1.).map_err(|k| CalcError::new(k, span.clone()))?;
+        let mut r = self.b.num(unit).map_err(|k| CalcError::new(k, span.clone()))?;
+        let mut k = from;
+        let mut n = 0;
+        while self.b.compare(BinOp::Le, &k, &to).map_err(|k| CalcError::new(k, span.clone()))? {
+            if n == MAX_TERMS {
+                let kind = ErrorKind::Domain(format!("too many terms in '{fname}'"));
+                return Err(CalcError::new(kind, span.clone()))
+            }
+            let mut xs = vec![(name.as_str(), Value::Num(k.clone()))];
+            xs.extend_from_slice(locals);
+            let y = self.num(&args[0], &xs, depth, span)?;
+            r = self.b.binary(op, &r, &y).map_err(|k| CalcError::new(k, span.clone()))?;
+            k = self.b.binary(BinOp::Add, &k, &one).map_err(|k| CalcError::new(k, span.clone()))?;
+            n += 1;
+        }
+        Ok(Value::Num(r))
+    }
+
+    // Операнд-число операции в положении span
+    fn num(&self, e: &Expr, locals: &Locals<B::Value>, depth: usize, span: &Range<usize>) -> Result<B::Value, CalcError> {
+        self.expr(e, locals, depth)?.num().map_err(|k| CalcError::new(k, span.clone()))
+    }
+
+    // Операнд-условие операции в положении span
+    fn cond(&self, e: &Expr, locals: &Locals<B::Value>, depth: usize, span: &Range<usize>) -> Result<bool, CalcError> {
+        self.expr(e, locals, depth)?.bool().map_err(|k| CalcError::new(k, span.clone()))
+    }
+}
+
+// Точные дроби: + - * / и целые степени
+pub struct RationalBackend;
+
+impl Backend for RationalBackend {
+    type Value = Rational;
+
+    fn num(&self, x: f64) -> Result<Rational, ErrorKind> {
+        Rational::from_f64(x).ok_or(ErrorKind::Domain(format!("{x} is not a finite number")))
+    }
+
+    fn int(&self, n: u64) -> Result<Rational, ErrorKind> { Ok(Rational::from_int(BigInt::from_u64(n))) }
+
+    fn decimal(&self, _x: f64, s: &str) -> Result<Rational, ErrorKind> {
+        Rational::parse(s).ok_or(ErrorKind::Domain(format!("{s} is not a number")))
+    }
+
+    fn neg(&self, a: &Rational) -> Result<Rational, ErrorKind> { Ok(a.neg()) }
+
+    fn binary(&self, op: BinOp, a: &Rational, b: &Rational) -> Result<Rational, ErrorKind> {
+        match op {
+            BinOp::Add => Ok(a.add(b)),
+            BinOp::Sub => Ok(a.sub(b)),
+            BinOp::Mul => Ok(a.mul(b)),
+            BinOp::Div => a.div(b).ok_or(ErrorKind::DivisionByZero),
+            BinOp::Pow => {
+                if !b.is_integer() {
+                    return Err(ErrorKind::Domain("a non-integer power has no exact value".to_string()))
+                }
+                a.powi(exp_of(b.num())?).ok_or(ErrorKind::DivisionByZero)
+            },
+            BinOp::Mod => rem(a, b).ok_or(ErrorKind::DivisionByZero),
+            _ => Err(ErrorKind::Unsupported(op.symbol().to_string()))
+        }
+    }
+
+    fn compare(&self, op: BinOp, a: &Rational, b: &Rational) -> Result<bool, ErrorKind> {
+        Ok(op.compare(Some(a.cmp(b))))
+    }
+
+    fn arity(&self, fname: &str) -> Option<Arity> {
+        match fname {
+            "min" | "max" | "avg" | "sum" | "prod" | "gcd" | "lcm" => Some(Arity::AtLeast(1)),
+            "pow" => Some(Arity::Exact(2)),
+            "fact" | "abs" | "sign" | "floor" | "ceil" | "round" | "trunc" => Some(Arity::Exact(1)),
+            _ => None
+        }
+    }
+
+    fn index(&self, a: &Rational) -> Option<i64> {
+        if a.is_integer() { a.num().to_i64() } else { None }
+    }
+
+    fn mode(&self) -> Option<Mode> { Some(Mode::Rational) }
+
+    fn float(&self, a: &Rational) -> Option<f64> { Some(a.to_f64()) }
+
+    fn call(&self, fname: &str, args: &[Rational]) -> Result<Rational, ErrorKind> {
+        Ok(match fname {
+            "min" => args.iter().min().unwrap().clone(),
+            "max" => args.iter().max().unwrap().clone(),
+            "avg" => {
+                let s = args.iter().fold(Rational::from_int(BigInt::zero()), |s, x| s.add(x));
+                s.div(&Rational::from_int(BigInt::from_u64(args.len() as u64))).unwrap()
+            },
+            "sum" | "prod" => fold(self, fname, args)?,
+            "pow" => self.binary(BinOp::Pow, &args[0], &args[1])?,
+            "abs" if args[0].is_neg() => args[0].neg(),
+            "abs" => args[0].clone(),
+            "sign" => Rational::from_int(BigInt::from_i64(args[0].cmp(&Rational::from_int(BigInt::zero())) as i64)),
+            "floor" | "ceil" | "round" | "trunc" => Rational::from_int(round(fname, &args[0])),
+            "gcd" | "lcm" => {
+                if args.iter().any(|x| !x.is_integer()) {
+                    return Err(ErrorKind::Domain(format!("'{fname}' needs integers")))
+                }
+                let mut r = if fname == "gcd" { BigInt::zero() } else { BigInt::from_u64(1) };
+                for x in args.iter().map(|x| x.num()) {
+                    r = if fname == "gcd" {
+                        r.gcd(x)
+                    } else if r.is_zero() || x.is_zero() {
+                        BigInt::zero()
+                    } else {
+                        r.divrem(&r.gcd(x)).0.mul(x).abs()
+                    };
+                }
+                Rational::from_int(r)
+            },
+            _ => {
+                if !args[0].is_integer() || args[0].is_neg() {
+                    return Err(ErrorKind::Domain("factorial needs a non-negative integer".to_string()))
+                }
+                match args[0].num().to_i64() {
+                    Some(n) if n <= MAX_FACT => Rational::from_int(factorial(n)),
+                    _ => return Err(ErrorKind::Domain(format!("factorial of a number above {MAX_FACT}")))
+                }
+            }
+        })
+    }
+}
+
+// Десятичные числа с заданным числом знаков, включая трансцендентные функции
+pub struct DecimalBackend {
+    pub digits: usize
+}
+
+impl Backend for DecimalBackend {
+    type Value = Decimal;
+
+    fn num(&self, x: f64) -> Result<Decimal, ErrorKind> {
+        Decimal::from_f64(x, self.digits).ok_or(ErrorKind::Domain(format!("{x} is not a finite number")))
+    }
+
+    fn int(&self, n: u64) -> Result<Decimal, ErrorKind> {
+        RationalBackend.int(n).map(|x| Decimal::from_rational(&x, self.digits))
+    }
+
+    fn decimal(&self, x: f64, s: &str) -> Result<Decimal, ErrorKind> {
+        RationalBackend.decimal(x, s).map(|x| Decimal::from_rational(&x, self.digits))
+    }
+
+    fn neg(&self, a: &Decimal) -> Result<Decimal, ErrorKind> { Ok(a.neg()) }
+
+    fn binary(&self, op: BinOp, a: &Decimal, b: &Decimal) -> Result<Decimal, ErrorKind> {
+        match op {
+            BinOp::Add => Ok(a.add(b)),
+            BinOp::Sub => Ok(a.sub(b)),
+            BinOp::Mul => Ok(a.mul(b)),
+            BinOp::Div => a.div(b).ok_or(ErrorKind::DivisionByZero),
+            BinOp::Pow => match b.to_i64() {
+                Some(n) if n.abs() <= MAX_EXP => a.powi(n).ok_or(ErrorKind::DivisionByZero),
+                _ => {
+                    // a^b = exp(b*ln(a))
+                    let ln = a.ln().ok_or(ErrorKind::Domain(
+                        "a non-integer power of a non-positive number".to_string()))?;
+                    Ok(b.mul(&ln).exp())
+                }
+            },
+            BinOp::Mod => {
+                let r = rem(&a.to_rational(), &b.to_rational()).ok_or(ErrorKind::DivisionByZero)?;
+                Ok(Decimal::from_rational(&r, self.digits))
+            },
+            _ => Err(ErrorKind::Unsupported(op.symbol().to_string()))
+        }
+    }
+
+    fn compare(&self, op: BinOp, a: &Decimal, b: &Decimal) -> Result<bool, ErrorKind> {
+        Ok(op.compare(Some(a.cmp(b))))
+    }
+
+    fn arity(&self, fname: &str) -> Option<Arity> {
+        match fname {
+            "min" | "max" | "avg" | "sum" | "prod" => Some(Arity::AtLeast(1)),
+            "pow" => Some(Arity::Exact(2)),
+            "sqr" | "exp" | "ln" | "sin" | "cos" | "tan" | "fact" | "abs" | "sign"
+            | "asin" | "acos" | "atan" | "sinh" | "cosh" | "tanh" | "asinh" | "acosh" | "atanh"
+            | "floor" | "ceil" | "round" | "trunc" => Some(Arity::Exact(1)),
+            "atan2" => Some(Arity::Exact(2)),
+            "log" => Some(Arity::Between(1, 2)),
+            "pi" | "e" => Some(Arity::Exact(0)),
+            _ => None
+        }
+    }
+
+    fn index(&self, a: &Decimal) -> Option<i64> { a.to_i64() }
+
+    fn mode(&self) -> Option<Mode> { Some(Mode::Decimal(self.digits)) }
+
+    fn float(&self, a: &Decimal) -> Option<f64> { Some(a.to_f64()) }
+
+    fn call(&self, fname: &str, args: &[Decimal]) -> Result<Decimal, ErrorKind> {
+        let domain = |s: &str| ErrorKind::Domain(format!("{fname}: {s}"));
+        Ok(match fname {
+            "min" => args.iter().min().unwrap().clone(),
+            "max" => args.iter().max().unwrap().clone(),
+            "avg" => {
+                let s = args.iter().fold(Decimal::zero(self.digits), |s, x| s.add(x));
+                s.div(&Decimal::from_int(args.len() as i64, self.digits)).unwrap()
+            },
+            "sum" | "prod" => fold(self, fname, args)?,
+            "pow" => self.binary(BinOp::Pow, &args[0], &args[1])?,
+            "sqr" => args[0].sqrt().ok_or(domain("negative argument"))?,
+            "sign" => Decimal::from_int(args[0].cmp(&Decimal::zero(self.digits)) as i64, self.digits),
+            "abs" if args[0] < Decimal::zero(self.digits) => args[0].neg(),
+            "abs" => args[0].clone(),
+            "exp" => args[0].exp(),
+            "ln" => args[0].ln().ok_or(domain("non-positive argument"))?,
+            "log" if args.len() == 1 => args[0].log10().ok_or(domain("non-positive argument"))?,
+            "log" => {
+                let (a, b) = (args[0].ln(), args[1].ln());
+                let (a, b) = a.zip(b).ok_or(domain("non-positive argument"))?;
+                a.div(&b).ok_or(domain("the base must not be 1"))?
+            },
+            "sin" => args[0].sin(),
+            "cos" => args[0].cos(),
+            "tan" => args[0].tan().ok_or(ErrorKind::DivisionByZero)?,
+            "asin" => args[0].asin().ok_or(domain("the argument is out of [-1, 1]"))?,
+            "acos" => args[0].acos().ok_or(domain("the argument is out of [-1, 1]"))?,
+            "atan" => args[0].atan(),
+            "atan2" => args[0].atan2(&args[1]),
+            "sinh" => args[0].sinh(),
+            "cosh" => args[0].cosh(),
+            "tanh" => args[0].tanh(),
+            "asinh" => args[0].asinh(),
+            "acosh" => args[0].acosh().ok_or(domain("the argument is less than 1"))?,
+            "atanh" => args[0].atanh().ok_or(domain("the argument is out of (-1, 1)"))?,
+            "floor" | "ceil" | "round" | "trunc" => args[0].round(fname),
+            "pi" => Decimal::pi(self.digits),
+            "e" => Decimal::e(self.digits),
+            // n! точно, x! = gamma(x + 1) для нецелого x
+            _ => match args[0].to_i64() {
+                Some(n) if (0..=MAX_FACT).contains(&n) => {
+                    let f = Rational::from_int(factorial(n));
+                    Decimal::from_rational(&f, self.digits)
+                },
+                Some(n) if n < 0 => return Err(domain("factorial of a negative integer")),
+                _ if args[0].abs() > Decimal::from_int(MAX_FACT, self.digits) =>
+                    return Err(domain(&format!("factorial of a number above {MAX_FACT}"))),
+                _ => args[0].add(&Decimal::from_int(1, self.digits)).gamma().unwrap()
+            }
+        })
+    }
+}
+
+// Комплексные числа: функции возвращают главные значения
+pub struct ComplexBackend;
+
+impl Backend for ComplexBackend {
+    type Value = Complex;
+
+    fn num(&self, x: f64) -> Result<Complex, ErrorKind> { Ok(Complex::real(x)) }
+
+    fn neg(&self, a: &Complex) -> Result<Complex, ErrorKind> { Ok(a.neg()) }
+
+    fn binary(&self, op: BinOp, a: &Complex, b: &Complex) -> Result<Complex, ErrorKind> {
+        match op {
+            BinOp::Add => Ok(a.add(b)),
+            BinOp::Sub => Ok(a.sub(b)),
+            BinOp::Mul => Ok(a.mul(b)),
+            BinOp::Div => a.div(b).ok_or(ErrorKind::DivisionByZero),
+            BinOp::Pow => a.pow(b).ok_or(ErrorKind::DivisionByZero),
+            _ => Err(ErrorKind::Unsupported(op.symbol().to_string()))
+        }
+    }
+
+    // Упорядочены только действительные числа; на равенство сравниваются любые
+    fn compare(&self, op: BinOp, a: &Complex, b: &Complex) -> Result<bool, ErrorKind> {
+        if a.is_real() && b.is_real() { return Ok(op.compare(a.re.partial_cmp(&b.re))) }
+        match op {
+            BinOp::Eq | BinOp::Ne => Ok(op.compare((a == b).then_some(Ordering::Equal))),
+            _ => Err(ErrorKind::Domain("complex numbers are not ordered".to_string()))
+        }
+    }
+
+    fn arity(&self, fname: &str) -> Option<Arity> {
+        match fname {
+            "avg" | "sum" | "prod" => Some(Arity::AtLeast(1)),
+            "pow" => Some(Arity::Exact(2)),
+            "sqr" | "exp" | "ln" | "sin" | "cos" | "tan" => Some(Arity::Exact(1)),
+            "log" => Some(Arity::Between(1, 2)),
+            "re" | "im" | "abs" | "arg" | "conj" => Some(Arity::Exact(1)),
+            "pi" | "e" => Some(Arity::Exact(0)),
+            _ => None
+        }
+    }
+
+    fn call(&self, fname: &str, args: &[Complex]) -> Result<Complex, ErrorKind> {
+        let domain = |s: &str| ErrorKind::Domain(format!("{fname}: {s}"));
+        Ok(match fname {
+            "avg" => {
+                let s = args.iter().fold(Complex::real(0.), |s, x| s.add(x));
+                s.div(&Complex::real(args.len() as f64)).unwrap()
+            },
+            "sum" | "prod" => fold(self, fname, args)?,
+            "pow" => self.binary(BinOp::Pow, &args[0], &args[1])?,
+            "sqr" => args[0].sqrt(),
+            "exp" => args[0].exp(),
+            "ln" => args[0].ln().ok_or(domain("zero argument"))?,
+            "log" => {
+                let ln = args[0].ln().ok_or(domain("zero argument"))?;
+                let base = match args.get(1) {
+                    Some(b) => b.ln().ok_or(domain("zero base"))?,
+                    None => Complex::real(std::f64::consts::LN_10)
+                };
+                ln.div(&base).ok_or(domain("the base must not be 1"))?
+            },
+            "sin" => args[0].sin(),
+            "cos" => args[0].cos(),
+            "tan" => args[0].tan().ok_or(ErrorKind::DivisionByZero)?,
+            "re" => Complex::real(args[0].re),
+            "im" => Complex::real(args[0].im),
+            "abs" => Complex::real(args[0].abs()),
+            "arg" => Complex::real(args[0].arg()),
+            "conj" => args[0].conj(),
+            "pi" => Complex::real(std::f64::consts::PI),
+            _ => Complex::real(std::f64::consts::E)
+        })
+    }
+
+    fn constant(&self, name: &str) -> Option<Complex> {
+        if name == "i" { Some(Complex::i()) } else { None }
+    }
+
+    fn index(&self, a: &Complex) -> Option<i64> {
+        if a.is_real() { float_index(a.re) } else { None }
+    }
+
+    fn mode(&self) -> Option<Mode> { Some(Mode::Complex) }
+
+    fn float(&self, a: &Complex) -> Option<f64> { a.is_real().then_some(a.re) }
+}
+
+// Сумма или произведение аргументов
+pub(crate) fn fold<B: Backend>(b: &B, fname: &str, args: &[B::Value]) -> Result<B::Value, ErrorKind> {
+    let op = if fname == "sum" { BinOp::Add } else { BinOp::Mul };
+    args[1..].iter().try_fold(args[0].clone(), |r, x| b.binary(op, &r, x))
+}
+
+// Округление дроби до целого: floor, ceil, round (половины - от нуля) или trunc
+fn round(fname: &str, x: &Rational) -> BigInt {
+    let (q, r) = x.num().divrem(x.den());
+    if r.is_zero() { return q }
+    let one = BigInt::from_i64(if x.is_neg() { -1 } else { 1 });
+    match fname {
+        "floor" if x.is_neg() => q.add(&one),
+        "ceil" if !x.is_neg() => q.add(&one),
+        // |r| / den >= 1/2
+        "round" if r.abs().mul(&BigInt::from_u64(2)) >= *x.den() => q.add(&one),
+        _ => q
+    }
+}
+
+// Остаток от деления с частным, округленным к нулю, как у % для f64
+fn rem(a: &Rational, b: &Rational) -> Option<Rational> {
+    let q = a.div(b)?;
+    let q = Rational::from_int(q.num().divrem(q.den()).0);
+    Some(a.sub(&b.mul(&q)))
+}
+
+// Номер элемента списка из числа с плавающей точкой
+pub(crate) fn float_index(x: f64) -> Option<i64> {
+    (x.fract() == 0. && x.abs() <= 9e15).then_some(x as i64)
+}
+
+fn exp_of(n: &BigInt) -> Result<i64, ErrorKind> {
+    match n.to_i64() {
+        Some(k) if k.abs() <= MAX_EXP => Ok(k),
+        _ => Err(ErrorKind::Domain("the exponent is too large".to_string()))
+    }
+}
+
+fn factorial(n: i64) -> BigInt {
+    (2..=n).fold(BigInt::from_u64(1), |f, k| f.mul(&BigInt::from_i64(k)))
+}
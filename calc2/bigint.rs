@@ -0,0 +1,287 @@
+// Целые числа произвольной длины: знак и модуль в системе
+// счисления по основанию 2^32, младшие разряды первыми
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    neg: bool,
+    // без ведущих нулей; у нуля пустой вектор и neg = false
+    mag: Vec<u32>
+}
+
+impl BigInt {
+    pub fn zero() -> Self { Self { neg: false, mag: vec![] } }
+
+    pub fn from_i64(x: i64) -> Self {
+        let mut r = Self::from_u64(x.unsigned_abs());
+        r.neg = x < 0;
+        r
+    }
+
+    pub fn from_u64(x: u64) -> Self {
+        let mut r = Self { neg: false, mag: vec![x as u32, (x >> 32) as u32] };
+        r.trim();
+        r
+    }
+
+    // Разбор строки из десятичных цифр со знаком
+    pub fn parse(s: &str) -> Option<Self> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, s)
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) { return None }
+        let mut r = Self::zero();
+        for c in digits.bytes() {
+            r.mul_small(10, (c - b'0') as u32);
+        }
+        r.neg = neg && !r.is_zero();
+        Some(r)
+    }
+
+    // 10^n
+    pub fn pow10(n: usize) -> Self {
+        let mut r = Self::from_u64(1);
+        for _ in 0..n { r.mul_small(10, 0); }
+        r
+    }
+
+    pub fn is_zero(&self) -> bool { self.mag.is_empty() }
+
+    pub fn is_neg(&self) -> bool { self.neg }
+
+    pub fn abs(&self) -> Self { Self { neg: false, mag: self.mag.clone() } }
+
+    pub fn neg(&self) -> Self {
+        Self { neg: !self.neg && !self.is_zero(), mag: self.mag.clone() }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 { return None }
+        let m = self.mag.iter().rev().fold(0u64, |acc, &d| (acc << 32) | d as u64);
+        if self.neg {
+            if m <= i64::MAX as u64 + 1 { Some((m as i64).wrapping_neg()) } else { None }
+        } else {
+            i64::try_from(m).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let m = self.mag.iter().rev().fold(0., |acc, &d| acc * 4294967296. + d as f64);
+        if self.neg { -m } else { m }
+    }
+
+    pub fn add(&self, y: &Self) -> Self {
+        if self.neg == y.neg {
+            return Self::signed(self.neg, add_mag(&self.mag, &y.mag))
+        }
+        match cmp_mag(&self.mag, &y.mag) {
+            Ordering::Less => Self::signed(y.neg, sub_mag(&y.mag, &self.mag)),
+            _ => Self::signed(self.neg, sub_mag(&self.mag, &y.mag))
+        }
+    }
+
+    pub fn sub(&self, y: &Self) -> Self { self.add(&y.neg()) }
+
+    pub fn mul(&self, y: &Self) -> Self {
+        if self.is_zero() || y.is_zero() { return Self::zero() }
+        let mut r = vec![0u32; self.mag.len() + y.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in y.mag.iter().enumerate() {
+                let t = r[i + j] as u64 + a as u64 * b as u64 + carry;
+                r[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            r[i + y.mag.len()] = carry as u32;
+        }
+        Self::signed(self.neg != y.neg, r)
+    }
+
+    // Деление с остатком, частное округляется к нулю; y != 0
+    pub fn divrem(&self, y: &Self) -> (Self, Self) {
+        if cmp_mag(&self.mag, &y.mag) == Ordering::Less {
+            return (Self::zero(), self.clone())
+        }
+        let (q, r) = if y.mag.len() == 1 {
+            let mut q = self.abs();
+            let r = q.div_small(y.mag[0]);
+            (q.mag, vec![r])
+        } else {
+            divrem_mag(&self.mag, &y.mag)
+        };
+        (Self::signed(self.neg != y.neg, q), Self::signed(self.neg, r))
+    }
+
+    pub fn pow(&self, mut n: u32) -> Self {
+        let mut r = Self::from_u64(1);
+        let mut x = self.clone();
+        while n > 0 {
+            if n & 1 == 1 { r = r.mul(&x); }
+            x = x.mul(&x);
+            n >>= 1;
+        }
+        r
+    }
+
+    pub fn gcd(&self, y: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), y.abs());
+        while !b.is_zero() {
+            let r = a.divrem(&b).1;
+            (a, b) = (b, r);
+        }
+        a
+    }
+
+    // Целая часть квадратного корня; self >= 0
+    pub fn isqrt(&self) -> Self {
+        if self.is_zero() { return Self::zero() }
+        // начальное приближение сверху: 2^(ceil(bits / 2))
+        let bits = self.mag.len() * 32 - self.mag.last().unwrap().leading_zeros() as usize;
+        let mut x = Self::from_u64(1);
+        for _ in 0..bits.div_ceil(2) { x.mul_small(2, 0); }
+        loop {
+            // метод Ньютона: y = (x + n / x) / 2
+            let mut y = x.add(&self.divrem(&x).0);
+            y.div_small(2);
+            if cmp_mag(&y.mag, &x.mag) != Ordering::Less { return x }
+            x = y;
+        }
+    }
+
+    fn signed(neg: bool, mag: Vec<u32>) -> Self {
+        let mut r = Self { neg, mag };
+        r.trim();
+        r
+    }
+
+    fn trim(&mut self) {
+        while self.mag.last() == Some(&0) { self.mag.pop(); }
+        if self.mag.is_empty() { self.neg = false; }
+    }
+
+    // self = self * m + a для модуля
+    fn mul_small(&mut self, m: u32, a: u32) {
+        let mut carry = a as u64;
+        for d in self.mag.iter_mut() {
+            let t = *d as u64 * m as u64 + carry;
+            *d = t as u32;
+            carry = t >> 32;
+        }
+        if carry > 0 { self.mag.push(carry as u32); }
+        self.trim();
+    }
+
+    // Деление модуля на малое число, возвращает остаток
+    fn div_small(&mut self, m: u32) -> u32 {
+        let mut r = 0u64;
+        for d in self.mag.iter_mut().rev() {
+            let t = (r << 32) | *d as u64;
+            *d = (t / m as u64) as u32;
+            r = t % m as u64;
+        }
+        self.trim();
+        r as u32
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, y: &Self) -> Ordering {
+        match (self.neg, y.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &y.mag),
+            (true, true) => cmp_mag(&y.mag, &self.mag)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, y: &Self) -> Option<Ordering> { Some(self.cmp(y)) }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() { return write!(f, "0") }
+        // разряды по основанию 10^9
+        let mut x = self.abs();
+        let mut parts = vec![];
+        while !x.is_zero() { parts.push(x.div_small(1_000_000_000)); }
+        let mut s = if self.neg { "-".to_string() } else { String::new() };
+        s.push_str(&parts.pop().unwrap().to_string());
+        for p in parts.iter().rev() { s.push_str(&format!("{p:09}")); }
+        write!(f, "{s}")
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut r = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in a.iter().enumerate() {
+        let t = x as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        r.push(t as u32);
+        carry = t >> 32;
+    }
+    if carry > 0 { r.push(carry as u32); }
+    r
+}
+
+// a - b при a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut t = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if t < 0 { t += 1 << 32; 1 } else { 0 };
+        r.push(t as u32);
+    }
+    r
+}
+
+// Деление модулей "уголком" по битам: a >= b, b состоит из нескольких разрядов
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = vec![];
+    for i in (0..a.len() * 32).rev() {
+        // r = 2r + i-й бит a
+        let mut carry = (a[i / 32] >> (i % 32)) & 1;
+        for d in r.iter_mut() {
+            let t = (*d >> 31) & 1;
+            *d = (*d << 1) | carry;
+            carry = t;
+        }
+        if carry > 0 { r.push(carry); }
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            while r.last() == Some(&0) { r.pop(); }
+            q[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (q, r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt { BigInt::parse(s).unwrap() }
+
+    #[test]
+    fn arithmetic() {
+        let x = big("123456789012345678901234567890");
+        assert_eq!(x.mul(&x).to_string(), "15241578753238836750495351562536198787501905199875019052100");
+        assert_eq!(x.add(&big("-123456789012345678901234567891")).to_string(), "-1");
+        let (q, r) = big("-17").divrem(&big("5"));
+        assert_eq!((q.to_string(), r.to_string()), ("-3".to_string(), "-2".to_string()));
+        assert_eq!(big("2").pow(100).to_string(), "1267650600228229401496703205376");
+        assert_eq!(big("84").gcd(&big("-36")).to_string(), "12");
+        assert_eq!(big("1000000000000000000000").isqrt().to_string(), "31622776601");
+        assert_eq!(big("-0").to_string(), "0");
+    }
+}
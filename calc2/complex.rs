@@ -0,0 +1,143 @@
+// Комплексные числа a + bi на основе f64. Многозначные функции
+// (ln, степень, корень) возвращают главное значение
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self { Self { re, im } }
+
+    pub fn real(re: f64) -> Self { Self { re, im: 0. } }
+
+    pub fn i() -> Self { Self { re: 0., im: 1. } }
+
+    pub fn is_zero(&self) -> bool { self.re == 0. && self.im == 0. }
+
+    pub fn is_real(&self) -> bool { self.im == 0. }
+
+    // модуль
+    pub fn abs(&self) -> f64 { self.re.hypot(self.im) }
+
+    // аргумент в (-pi, pi]; -0 мнимой части считается нулем
+    pub fn arg(&self) -> f64 { (self.im + 0.).atan2(self.re) }
+
+    pub fn conj(&self) -> Self { Self::new(self.re, -self.im) }
+
+    pub fn neg(&self) -> Self { Self::new(-self.re, -self.im) }
+
+    pub fn add(&self, y: &Self) -> Self { Self::new(self.re + y.re, self.im + y.im) }
+
+    pub fn sub(&self, y: &Self) -> Self { Self::new(self.re - y.re, self.im - y.im) }
+
+    pub fn mul(&self, y: &Self) -> Self {
+        Self::new(self.re * y.re - self.im * y.im, self.re * y.im + self.im * y.re)
+    }
+
+    // None при делении на ноль
+    pub fn div(&self, y: &Self) -> Option<Self> {
+        if y.is_zero() { return None }
+        let d = y.re * y.re + y.im * y.im;
+        Some(Self::new((self.re * y.re + self.im * y.im) / d, (self.im * y.re - self.re * y.im) / d))
+    }
+
+    // Целая степень умножениями, чтобы i^2 давало ровно -1
+    pub fn powi(&self, n: i64) -> Option<Self> {
+        let mut r = Self::real(1.);
+        let mut x = *self;
+        let mut k = n.unsigned_abs();
+        while k > 0 {
+            if k & 1 == 1 { r = r.mul(&x); }
+            x = x.mul(&x);
+            k >>= 1;
+        }
+        if n < 0 { Self::real(1.).div(&r) } else { Some(r) }
+    }
+
+    // a^b = exp(b*ln(a)); None для нуля в степени с неположительной действительной частью
+    pub fn pow(&self, y: &Self) -> Option<Self> {
+        if y.is_real() && y.re.fract() == 0. && y.re.abs() <= 1024. { return self.powi(y.re as i64) }
+        if self.is_zero() { return if y.re > 0. { Some(Self::real(0.)) } else { None } }
+        Some(y.mul(&self.ln()?).exp())
+    }
+
+    pub fn sqrt(&self) -> Self {
+        // главное значение: действительная часть неотрицательна
+        let r = self.abs();
+        let re = ((r + self.re) / 2.).sqrt();
+        let im = ((r - self.re) / 2.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+
+    pub fn exp(&self) -> Self {
+        let r = self.re.exp();
+        if self.im == 0. { return Self::real(r) }
+        Self::new(r * self.im.cos(), r * self.im.sin())
+    }
+
+    // None для нуля
+    pub fn ln(&self) -> Option<Self> {
+        if self.is_zero() { return None }
+        Some(Self::new(self.abs().ln(), self.arg()))
+    }
+
+    // sin(a + bi) = sin(a)ch(b) + i cos(a)sh(b)
+    pub fn sin(&self) -> Self {
+        Self::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    // cos(a + bi) = cos(a)ch(b) - i sin(a)sh(b)
+    pub fn cos(&self) -> Self {
+        Self::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+
+    // None, если косинус равен нулю
+    pub fn tan(&self) -> Option<Self> { self.sin().div(&self.cos()) }
+}
+
+// Печать в виде a + bi. Часть, которая на 14 порядков меньше другой,
+// считается погрешностью округления: exp(i*pi) печатается как -1
+impl Display for Complex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let eps = self.abs() * 1e-14;
+        let re = if self.re.abs() < eps { 0. } else { self.re };
+        let im = if self.im.abs() < eps { 0. } else { self.im };
+        let unit = |x: f64| if x == 1. { String::new() } else { x.to_string() };
+        match (re == 0., im == 0.) {
+            (_, true) => write!(f, "{}", re + 0.),
+            (true, false) if im == -1. => write!(f, "-i"),
+            (true, false) => write!(f, "{}i", unit(im)),
+            _ if im < 0. => write!(f, "{re} - {}i", unit(-im)),
+            _ => write!(f, "{re} + {}i", unit(im))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Calc, Env, Mode};
+    use super::*;
+
+    fn calc(s: &str) -> String {
+        Calc::new(s).and_then(|c| c.calc_mode(Mode::Complex, &mut Env::new())).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (a, b) = (Complex::new(1., 2.), Complex::new(3., -1.));
+        assert_eq!(a.mul(&b), Complex::new(5., 5.));
+        assert_eq!(a.mul(&b).div(&b), Some(a));
+        assert_eq!(a.div(&Complex::real(0.)), None);
+        assert_eq!(Complex::i().powi(2), Some(Complex::real(-1.)));
+        assert_eq!(Complex::real(-4.).sqrt(), Complex::new(0., 2.));
+    }
+
+    #[test]
+    fn evaluates_formulas() {
+        assert_eq!(calc("sqr(-4)"), "2i");
+        assert_eq!(calc("exp(i*pi())"), "-1");
+    }
+}
@@ -0,0 +1,396 @@
+// Десятичные числа с фиксированной точкой и заданным числом знаков:
+// значение m / 10^scale, где scale = digits + GUARD запасных знаков.
+// Трансцендентные функции считаются рядами Тейлора
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use super::bigint::BigInt;
+use super::rational::Rational;
+
+// Запасные знаки для накопления погрешности
+const GUARD: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decimal {
+    m: BigInt,
+    // знаков после точки при печати
+    digits: usize
+}
+
+impl Decimal {
+    pub fn zero(digits: usize) -> Self { Self { m: BigInt::zero(), digits } }
+
+    pub fn from_int(n: i64, digits: usize) -> Self {
+        Self { m: BigInt::from_i64(n).mul(&BigInt::pow10(digits + GUARD)), digits }
+    }
+
+    pub fn from_rational(x: &Rational, digits: usize) -> Self {
+        let m = x.num().mul(&BigInt::pow10(digits + GUARD)).divrem(x.den()).0;
+        Self { m, digits }
+    }
+
+    pub fn from_f64(x: f64, digits: usize) -> Option<Self> {
+        Some(Self::from_rational(&Rational::from_f64(x)?, digits))
+    }
+
+    pub fn is_zero(&self) -> bool { self.m.is_zero() }
+
+    pub fn is_neg(&self) -> bool { self.m.is_neg() }
+
+    pub fn to_f64(&self) -> f64 { self.to_rational().to_f64() }
+
+    pub fn to_rational(&self) -> Rational {
+        Rational::new(self.m.clone(), self.unit()).unwrap()
+    }
+
+    // Целое значение, если дробная часть равна нулю
+    pub fn to_i64(&self) -> Option<i64> {
+        let (q, r) = self.m.divrem(&self.unit());
+        if r.is_zero() { q.to_i64() } else { None }
+    }
+
+    fn unit(&self) -> BigInt { BigInt::pow10(self.digits + GUARD) }
+
+    fn with(&self, m: BigInt) -> Self { Self { m, digits: self.digits } }
+
+    fn int(&self, n: i64) -> Self { Self::from_int(n, self.digits) }
+
+    pub fn neg(&self) -> Self { self.with(self.m.neg()) }
+
+    pub fn abs(&self) -> Self { self.with(self.m.abs()) }
+
+    pub fn add(&self, y: &Self) -> Self { self.with(self.m.add(&y.m)) }
+
+    pub fn sub(&self, y: &Self) -> Self { self.with(self.m.sub(&y.m)) }
+
+    pub fn mul(&self, y: &Self) -> Self { self.with(self.m.mul(&y.m).divrem(&self.unit()).0) }
+
+    // None при делении на ноль
+    pub fn div(&self, y: &Self) -> Option<Self> {
+        if y.is_zero() { return None }
+        Some(self.with(self.m.mul(&self.unit()).divrem(&y.m).0))
+    }
+
+    fn div_int(&self, n: i64) -> Self { self.with(self.m.divrem(&BigInt::from_i64(n)).0) }
+
+    // Целая степень; None при возведении нуля в отрицательную степень
+    pub fn powi(&self, n: i64) -> Option<Self> {
+        let mut r = self.int(1);
+        let mut x = self.clone();
+        let mut k = n.unsigned_abs();
+        while k > 0 {
+            if k & 1 == 1 { r = r.mul(&x); }
+            x = x.mul(&x);
+            k >>= 1;
+        }
+        if n < 0 { self.int(1).div(&r) } else { Some(r) }
+    }
+
+    // Квадратный корень; None для отрицательного числа
+    pub fn sqrt(&self) -> Option<Self> {
+        if self.is_neg() { return None }
+        Some(self.with(self.m.mul(&self.unit()).isqrt()))
+    }
+
+    pub fn exp(&self) -> Self {
+        if self.is_neg() { return self.int(1).div(&self.neg().exp()).unwrap() }
+        // exp(x) = exp(x / 2^k)^(2^k), |x / 2^k| < 1/2
+        let half = self.int(1).div_int(2);
+        let mut x = self.clone();
+        let mut k = 0;
+        while x > half {
+            x = x.div_int(2);
+            k += 1;
+        }
+        let mut r = self.taylor(&x, |n| n, false);
+        for _ in 0..k { r = r.mul(&r); }
+        r
+    }
+
+    // Натуральный логарифм; None для x <= 0
+    pub fn ln(&self) -> Option<Self> {
+        if self.is_neg() || self.is_zero() { return None }
+        // x = y * 2^k, 1 <= y < 2
+        let (one, two) = (self.int(1), self.int(2));
+        let mut y = self.clone();
+        let mut k = 0;
+        while y >= two { y = y.div_int(2); k += 1; }
+        while y < one { y = y.add(&y); k -= 1; }
+        let r = y.sub(&one).div(&y.add(&one)).unwrap().atanh2().add(&self.ln2().mul(&self.int(k)));
+        Some(r)
+    }
+
+    pub fn log10(&self) -> Option<Self> {
+        self.ln()?.div(&self.int(10).ln()?)
+    }
+
+    // sin(x) = x - x^3/3! + x^5/5! - ...
+    pub fn sin(&self) -> Self { self.taylor(&self.reduce(), |n| 2 * n + 1, true) }
+
+    // cos(x) = 1 - x^2/2! + x^4/4! - ...
+    pub fn cos(&self) -> Self { self.taylor(&self.reduce(), |n| 2 * n, true) }
+
+    // None, если косинус равен нулю
+    pub fn tan(&self) -> Option<Self> { self.sin().div(&self.cos()) }
+
+    // atan(x) = 2*atan(x / (1 + sqrt(1 + x^2))); после двух шагов |x| < tan(pi/8)
+    pub fn atan(&self) -> Self {
+        let one = self.int(1);
+        let mut x = self.clone();
+        for _ in 0..2 {
+            x = x.div(&one.add(&one.add(&x.mul(&x)).sqrt().unwrap())).unwrap();
+        }
+        x.atan_small().mul(&self.int(4))
+    }
+
+    // Угол точки (x, y) в (-pi, pi]; self - y
+    pub fn atan2(&self, x: &Self) -> Self {
+        let pi = Self::pi(self.digits);
+        match (x.cmp(&self.int(0)), self.is_neg()) {
+            (Ordering::Greater, _) => self.div(x).unwrap().atan(),
+            (Ordering::Less, false) => self.div(x).unwrap().atan().add(&pi),
+            (Ordering::Less, true) => self.div(x).unwrap().atan().sub(&pi),
+            (Ordering::Equal, _) if self.is_zero() => self.int(0),
+            (Ordering::Equal, neg) => if neg { pi.div_int(2).neg() } else { pi.div_int(2) }
+        }
+    }
+
+    // asin(x) = atan2(x, sqrt(1 - x^2)); None для |x| > 1
+    pub fn asin(&self) -> Option<Self> {
+        Some(self.atan2(&self.int(1).sub(&self.mul(self)).sqrt()?))
+    }
+
+    // acos(x) = pi/2 - asin(x)
+    pub fn acos(&self) -> Option<Self> {
+        Some(Self::pi(self.digits).div_int(2).sub(&self.asin()?))
+    }
+
+    pub fn sinh(&self) -> Self { self.exp().sub(&self.neg().exp()).div_int(2) }
+
+    pub fn cosh(&self) -> Self { self.exp().add(&self.neg().exp()).div_int(2) }
+
+    pub fn tanh(&self) -> Self { self.sinh().div(&self.cosh()).unwrap() }
+
+    // asinh(x) = ln(x + sqrt(x^2 + 1)), для x < 0 - через -asinh(-x)
+    pub fn asinh(&self) -> Self {
+        if self.is_neg() { return self.neg().asinh().neg() }
+        self.add(&self.mul(self).add(&self.int(1)).sqrt().unwrap()).ln().unwrap()
+    }
+
+    // acosh(x) = ln(x + sqrt(x^2 - 1)); None для x < 1
+    pub fn acosh(&self) -> Option<Self> {
+        if *self < self.int(1) { return None }
+        self.add(&self.mul(self).sub(&self.int(1)).sqrt()?).ln()
+    }
+
+    // atanh(x) = ln((1 + x) / (1 - x)) / 2; None для |x| >= 1
+    pub fn atanh(&self) -> Option<Self> {
+        let one = self.int(1);
+        Some(one.add(self).div(&one.sub(self))?.ln()?.div_int(2))
+    }
+
+    // Гамма-функция; None в полюсах 0, -1, -2, ...
+    // x = n + f, 0 <= f < 1: Γ(1 + f) по приближению Спуджа, затем Γ(x + 1) = xΓ(x)
+    pub fn gamma(&self) -> Option<Self> {
+        let n = self.round("floor");
+        // параметр Спуджа: погрешность меньше (2pi)^-a; запас знаков на сокращение слагаемых
+        let a = (self.digits + GUARD) * 5 / 4 + 2;
+        let w = self.digits + a + GUARD;
+        let f = Self::from_rational(&self.sub(&n).to_rational(), w);
+        let mut g = if f.is_zero() { f.int(1) } else { f.spouge(a as i64) };
+        let n = n.to_i64()?;
+        for j in 1..n { g = g.mul(&f.add(&f.int(j))); }
+        for j in n..=0 { g = g.div(&f.add(&f.int(j)))?; }
+        Some(Self::from_rational(&g.to_rational(), self.digits))
+    }
+
+    // Γ(z + 1) = (z + a)^(z + 1/2) e^-(z + a) (c0 + Σ c_k / (z + k)), k = 1..a-1,
+    // c0 = sqrt(2pi), c_k = (-1)^(k-1) (a - k)^(k - 1) sqrt(a - k) e^(a - k) / (k - 1)!
+    fn spouge(&self, a: i64) -> Self {
+        let half = self.int(1).div_int(2);
+        let mut s = Self::pi(self.digits).mul(&self.int(2)).sqrt().unwrap();
+        // e^j для j = 0..a-1
+        let e = Self::e(self.digits);
+        let mut exps = vec![self.int(1)];
+        for j in 1..a as usize { exps.push(exps[j - 1].mul(&e)); }
+        let mut fact = self.int(1);
+        for k in 1..a {
+            if k > 1 { fact = fact.mul(&self.int(k - 1)); }
+            let ak = self.int(a - k);
+            let c = ak.powi(k - 1).unwrap().mul(&ak.sqrt().unwrap()).mul(&exps[(a - k) as usize]);
+            let t = c.div(&fact.mul(&self.add(&self.int(k)))).unwrap();
+            s = if k % 2 == 1 { s.add(&t) } else { s.sub(&t) };
+        }
+        let za = self.add(&self.int(a));
+        za.ln().unwrap().mul(&self.add(&half)).sub(&za).exp().mul(&s)
+    }
+
+    // Округление до целого: floor, ceil, round (половины - от нуля) или trunc
+    pub fn round(&self, how: &str) -> Self {
+        let unit = self.unit();
+        let (q, r) = self.m.divrem(&unit);
+        let one = BigInt::from_i64(if self.is_neg() { -1 } else { 1 });
+        let q = match how {
+            _ if r.is_zero() => q,
+            "floor" if self.is_neg() => q.add(&one),
+            "ceil" if !self.is_neg() => q.add(&one),
+            "round" if r.abs().add(&r.abs()) >= unit => q.add(&one),
+            _ => q
+        };
+        self.with(q.mul(&unit))
+    }
+
+    pub fn pi(digits: usize) -> Self {
+        // формула Мэчина: pi = 16*atan(1/5) - 4*atan(1/239)
+        let one = Self::from_int(1, digits);
+        let a = one.div_int(5).atan_small();
+        let b = one.div_int(239).atan_small();
+        a.mul(&Self::from_int(16, digits)).sub(&b.mul(&Self::from_int(4, digits)))
+    }
+
+    pub fn e(digits: usize) -> Self { Self::from_int(1, digits).exp() }
+
+    fn ln2(&self) -> Self {
+        // ln(2) = 2*atanh(1/3)
+        self.int(1).div_int(3).atanh2()
+    }
+
+    // 2*atanh(z) = 2*(z + z^3/3 + z^5/5 + ...), |z| <= 1/3
+    fn atanh2(&self) -> Self {
+        let z2 = self.mul(self);
+        let mut term = self.clone();
+        let mut sum = self.clone();
+        let mut n = 1;
+        loop {
+            term = term.mul(&z2);
+            n += 2;
+            let t = term.div_int(n);
+            if t.is_zero() { break }
+            sum = sum.add(&t);
+        }
+        sum.add(&sum)
+    }
+
+    // atan(z) = z - z^3/3 + z^5/5 - ..., |z| < 1
+    fn atan_small(&self) -> Self {
+        let z2 = self.mul(self);
+        let mut term = self.clone();
+        let mut sum = self.clone();
+        let mut n = 1;
+        loop {
+            term = term.mul(&z2).neg();
+            n += 2;
+            let t = term.div_int(n);
+            if t.is_zero() { break }
+            sum = sum.add(&t);
+        }
+        sum
+    }
+
+    // Приведение аргумента тригонометрических функций к [-pi, pi]
+    fn reduce(&self) -> Self {
+        let pi = Self::pi(self.digits);
+        let two_pi = pi.add(&pi);
+        let k = self.add(&pi).div(&two_pi).unwrap().round("floor");
+        self.sub(&two_pi.mul(&k))
+    }
+
+    // Сумма ряда x^k / k! по k = step(0), step(1), ...;
+    // alt - знакочередующийся ряд
+    fn taylor(&self, x: &Self, step: impl Fn(i64) -> i64, alt: bool) -> Self {
+        let mut sum = self.int(0);
+        let mut term = self.int(1);
+        let mut k = 0;
+        let mut n = 0;
+        loop {
+            // term = x^k / k!
+            let next = step(n);
+            while k < next {
+                k += 1;
+                term = term.mul(x).div_int(k);
+            }
+            if term.is_zero() { break }
+            sum = if alt && n % 2 == 1 { sum.sub(&term) } else { sum.add(&term) };
+            n += 1;
+        }
+        sum
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, y: &Self) -> Ordering { self.m.cmp(&y.m) }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, y: &Self) -> Option<Ordering> { Some(self.cmp(y)) }
+}
+
+// Печать с округлением до digits знаков, без хвостовых нулей
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let g = BigInt::pow10(GUARD);
+        let (mut q, r) = self.m.abs().divrem(&g);
+        if r.add(&r) >= g { q = q.add(&BigInt::from_u64(1)); }
+        let s = format!("{:0>width$}", q.to_string(), width = self.digits + 1);
+        let (int, frac) = s.split_at(s.len() - self.digits);
+        let frac = frac.trim_end_matches('0');
+        let sign = if self.is_neg() && !q.is_zero() { "-" } else { "" };
+        if frac.is_empty() { write!(f, "{sign}{int}") } else { write!(f, "{sign}{int}.{frac}") }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Angle, Calc, Env, ErrorKind, Mode};
+
+    fn calc(s: &str, env: &mut Env) -> Result<String, ErrorKind> {
+        Calc::new(s).and_then(|c| c.calc_mode(Mode::Decimal(30), env)).map_err(|e| e.kind)
+    }
+
+    fn dec(s: &str) -> String { calc(s, &mut Env::new()).unwrap() }
+
+    #[test]
+    fn rounds() {
+        assert_eq!(dec("floor(-2.5)"), "-3");
+        assert_eq!(dec("ceil(-2.5)"), "-2");
+        assert_eq!(dec("round(-2.5)"), "-3");
+        assert_eq!(dec("round(2.4)"), "2");
+        assert_eq!(dec("trunc(-2.5)"), "-2");
+        assert_eq!(dec("floor(7)"), "7");
+    }
+
+    #[test]
+    fn inverse_and_hyperbolic_functions() {
+        assert_eq!(dec("atan(1) * 4"), dec("pi()"));
+        assert_eq!(dec("asin(1) * 2"), dec("pi()"));
+        assert_eq!(dec("acos(-1)"), dec("pi()"));
+        assert_eq!(dec("atan2(0, -1)"), dec("pi()"));
+        assert_eq!(dec("sinh(1)"), "1.175201193643801456882381850596");
+        assert_eq!(dec("cosh(1)"), "1.543080634815243778477905620757");
+        assert_eq!(dec("round(cosh(2)^2 - sinh(2)^2 + 0.4)"), "1");
+        assert_eq!(dec("atanh(0.5)"), "0.549306144334054845697622618461");
+        assert!(matches!(calc("asin(2)", &mut Env::new()), Err(ErrorKind::Domain(_))));
+        assert!(matches!(calc("acosh(0.5)", &mut Env::new()), Err(ErrorKind::Domain(_))));
+        assert!(matches!(calc("atanh(1)", &mut Env::new()), Err(ErrorKind::Domain(_))));
+    }
+
+    #[test]
+    fn factorial_of_non_integers() {
+        assert_eq!(dec("5!"), "120");
+        assert_eq!(dec("0.5!"), "0.886226925452758013649083741671");
+        assert_eq!(dec("5.5!"), "287.8852778150443609963195467083");
+        assert_eq!(dec("(-1.5)!"), "-3.544907701811032054596334966682");
+        assert!(matches!(calc("(-3)!", &mut Env::new()), Err(ErrorKind::Domain(_))));
+        assert!(matches!(calc("5001!", &mut Env::new()), Err(ErrorKind::Domain(_))));
+        let big = Calc::new("5001!").and_then(|c| c.calc_mode(Mode::Rational, &mut Env::new()));
+        assert!(matches!(big.map_err(|e| e.kind), Err(ErrorKind::Domain(_))));
+    }
+
+    #[test]
+    fn inverse_trig_follows_the_angle() {
+        let mut env = Env::new();
+        env.registry_mut().set_angle(Angle::Degrees);
+        assert_eq!(calc("asin(1)", &mut env).unwrap(), "90");
+        assert_eq!(calc("atan2(1, -1)", &mut env).unwrap(), "135");
+        assert_eq!(calc("sin(30)", &mut env).unwrap(), "0.5");
+    }
+}
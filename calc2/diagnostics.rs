@@ -0,0 +1,295 @@
+// Диагностика формулы: все ошибки за один проход - ошибки разбора и ошибки,
+// видные до вычисления: неизвестные функции и переменные, неверное число аргументов.
+// Ошибка выводится со строкой формулы, подчеркиванием ^~~~ под ошибочным местом
+// и подсказкой; для опечатки в имени подсказка - похожее известное имя
+use std::collections::HashMap;
+use super::eval::is_series;
+use super::funcs::{out1, FORMS};
+use super::matrix;
+use super::value::truth;
+use super::{parse_all, Arity, Parsed, CalcError, Env, ErrorKind, Expr, OpTable, Stmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub error: CalcError,
+    pub hint: Option<String>
+}
+
+impl Diagnostic {
+    pub fn is_warning(&self) -> bool { matches!(self.error.kind, ErrorKind::Ambiguous(_) | ErrorKind::Shadowed(_)) }
+
+    // Ошибка со строкой формулы src, в которой она найдена:
+    //   Syntax error: unknown function 'sni' in the position 10
+    //     sqr(2) + sni(3)
+    //              ^~~
+    //     hint: did you mean `sin`?
+    pub fn render(&self, src: &str) -> String {
+        let span = &self.error.span;
+        let start = span.start.min(src.len());
+        // строка формулы, в которой начинается ошибка
+        let from = src[..start].rfind('\n').map_or(0, |k| k + 1);
+        let to = src[start..].find('\n').map_or(src.len(), |k| start + k);
+        let col = src[from..start].chars().count();
+        let width = src[start..span.end.clamp(start, to)].chars().count().max(1);
+        let mut s = format!("{}\n  {}\n  {}^{}", self.error, &src[from..to], " ".repeat(col), "~".repeat(width - 1));
+        if let Some(hint) = &self.hint { s += &format!("\n  hint: {hint}"); }
+        s
+    }
+}
+
+// Ошибки и предупреждения формулы s в окружении env в порядке их положения в строке
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn check(s: &str, env: &Env) -> Vec<Diagnostic> {
+    diagnose(s, &OpTable::default(), Some(env), None)
+}
+
+// То же с собственной таблицей операций
+pub fn check_with(s: &str, ops: &OpTable, env: &Env) -> Vec<Diagnostic> {
+    diagnose(s, ops, Some(env), None)
+}
+
+// Все ошибки формулы s вместе с ошибкой e, найденной при ее вычислении
+pub fn report(s: &str, ops: &OpTable, env: &Env, e: &CalcError) -> String {
+    render(s, &diagnose(s, ops, Some(env), Some(e)))
+}
+
+// Ошибки разбора формулы s вместе с ошибкой e - для режимов вычисления со своими
+// функциями и константами, в которых имена нельзя проверить заранее
+pub fn report_syntax(s: &str, ops: &OpTable, e: &CalcError) -> String {
+    render(s, &diagnose(s, ops, None, Some(e)))
+}
+
+pub fn render(src: &str, diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.render(src)).collect::<Vec<_>>().join("\n")
+}
+
+// Ошибки разбора и, если задано окружение env, ошибки в именах функций и переменных
+fn diagnose(s: &str, ops: &OpTable, env: Option<&Env>, e: Option<&CalcError>) -> Vec<Diagnostic> {
+    let Parsed { stmts, mut errors, mut warnings } = parse_all(s, ops);
+    let default = Env::new();
+    let mut c = Checker::new(&stmts, env.unwrap_or(&default));
+    if env.is_some() { c.program(&stmts); }
+    errors.append(&mut c.errors);
+    errors.append(&mut warnings);
+    if let Some(e) = e.filter(|e| !errors.contains(e)) { errors.push(e.clone()); }
+    errors.sort_by_key(|e| e.span.start);
+    errors.into_iter().map(|e| {
+        let hint = c.hint(&e);
+        Diagnostic { error: e, hint }
+    }).collect()
+}
+
+// Проверка дерева разбора без вычисления
+struct Checker<'a> {
+    env: &'a Env,
+    // функции, определенные в формуле: имя -> число параметров
+    funcs: HashMap<&'a str, usize>,
+    // переменные, которым присваивается значение в формуле
+    vars: Vec<&'a str>,
+    errors: Vec<CalcError>
+}
+
+impl<'a> Checker<'a> {
+    fn new(stmts: &'a [Stmt], env: &'a Env) -> Self {
+        let mut c = Self { env, funcs: HashMap::new(), vars: vec![], errors: vec![] };
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assign { name, .. } => c.vars.push(name),
+                Stmt::Def { fname, params, .. } => { c.funcs.insert(fname, params.len()); },
+                Stmt::Expr(_) => ()
+            }
+        }
+        c
+    }
+
+    // Число параметров пользовательской функции
+    fn user(&self, fname: &str) -> Option<usize> {
+        self.funcs.get(fname).copied().or_else(|| self.env.func(fname).map(|f| f.params.len()))
+    }
+
+    fn known_var(&self, name: &str, locals: &[&str]) -> bool {
+        locals.contains(&name) || self.vars.contains(&name)
+            || self.env.value(name).is_some() || truth(name).is_some()
+    }
+
+    fn program(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assign { expr, .. } | Stmt::Expr(expr) => self.expr(expr, &[]),
+                Stmt::Def { params, body, .. } => {
+                    let locals: Vec<_> = params.iter().map(|p| p.as_str()).collect();
+                    self.expr(body, &locals)
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, e: &Expr, locals: &[&str]) {
+        match e {
+            Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) => (),
+            Expr::Var { name, span } => if !self.known_var(name, locals) {
+                self.errors.push(CalcError::new(ErrorKind::UnknownVariable(name.clone()), span.clone()));
+            },
+            Expr::Unary(_, a, _) => self.expr(a, locals),
+            Expr::Binary(_, a, b, _) | Expr::Index(a, b, _) => {
+                self.expr(a, locals);
+                self.expr(b, locals);
+            },
+            Expr::List(xs, _) => for x in xs { self.expr(x, locals) },
+            Expr::Call { fname, args, span } => self.call(fname, args, span, locals)
+        }
+    }
+
+    fn call(&mut self, fname: &str, args: &[Expr], span: &std::ops::Range<usize>, locals: &[&str]) {
+        let user = self.user(fname);
+        // форма вычислителя, не перекрытая функцией реестра
+        let form = user.is_none() && self.env.registry().get(fname).is_none();
+        // функции с переменной: diff(expr, x, ...), solve(expr, x, ...) и т. п.
+        let bound = form && matches!(fname, "diff" | "solve" | "integrate")
+            || user.is_none() && is_series(fname, args);
+        let expected = match user {
+            Some(n) => Some(Arity::Exact(n)),
+            None => match fname {
+                _ if bound && !form => Some(Arity::Exact(4)),
+                "diff" | "range" if form => Some(Arity::Between(2, 3)),
+                "solve" if form => Some(Arity::Between(3, 4)),
+                "if" if form => Some(Arity::Exact(3)),
+                "integrate" if form => Some(Arity::Exact(4)),
+                _ if form && matrix::FUNCS.contains(&fname) => Some(matrix::arity(fname)),
+                // функции с переменным числом аргументов принимают и элементы списков
+                _ => match self.env.registry().get(fname) {
+                    Some(f) if matches!(f.arity, Arity::AtLeast(_)) => None,
+                    Some(f) => Some(f.arity),
+                    None => {
+                        self.errors.push(CalcError::new(ErrorKind::UnknownFunction(fname.to_string()), span.clone()));
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(a) = expected.filter(|a| !a.accepts(args.len())) {
+            self.errors.push(out1(fname, a, args.len(), span.clone()));
+        }
+        if !bound || args.len() < 2 {
+            for a in args { self.expr(a, locals) }
+            return
+        }
+        let Expr::Var { name, .. } = &args[1] else {
+            self.errors.push(CalcError::new(ErrorKind::ExpectedVariable, span.clone()));
+            return
+        };
+        // sum(a, b, 3, 4) при переменной b - ряд по b, а не сумма четырех значений
+        if is_series(fname, args) && self.known_var(name, locals) {
+            self.errors.push(CalcError::new(ErrorKind::Shadowed(name.clone()), args[1].span()));
+        }
+        let mut inner = locals.to_vec();
+        inner.push(name);
+        self.expr(&args[0], &inner);
+        for a in &args[2..] { self.expr(a, locals) }
+    }
+
+    // Подсказка к ошибке
+    fn hint(&self, e: &CalcError) -> Option<String> {
+        match &e.kind {
+            ErrorKind::UnknownFunction(fname) => {
+                if self.known_var(fname, &[]) {
+                    return Some(format!("`{fname}` is a variable; use `*` to multiply"))
+                }
+                let registry = self.env.registry().list().into_iter().map(|(f, _)| f);
+                let user = self.funcs.keys().copied().chain(self.env.funcs());
+                let names: Vec<_> = registry.chain(user).chain(FORMS.map(|(f, _)| f)).collect();
+                nearest(fname, &names).map(|f| format!("did you mean `{f}`?"))
+            },
+            ErrorKind::UnknownVariable(name) => {
+                let fname = name.to_ascii_lowercase();
+                if self.user(&fname) == Some(0)
+                    || self.env.registry().get(&fname).is_some_and(|f| f.arity == Arity::Exact(0)) {
+                    return Some(format!("`{fname}` is a function: `{fname}()`"))
+                }
+                let vars = self.env.vars();
+                let names: Vec<_> = vars.iter().map(|(v, _)| *v).chain(self.vars.iter().copied()).collect();
+                nearest(name, &names).map(|v| format!("did you mean `{v}`?"))
+            },
+            ErrorKind::WrongArity { fname, .. } => match self.user(fname) {
+                Some(_) => {
+                    let f = self.env.func(fname)?;
+                    Some(format!("usage: {fname}({})", f.params.join(", ")))
+                },
+                None => {
+                    let doc = &self.env.registry().get(fname)?.doc;
+                    Some(format!("usage: {}", doc.split(" - ").next().unwrap_or(doc)))
+                }
+            },
+            ErrorKind::Ambiguous(_) => Some("use `*` and brackets to make the order explicit".to_string()),
+            ErrorKind::Shadowed(_) => Some("rename the index, or put the values in a list: sum([a, b, c])".to_string()),
+            _ => None
+        }
+    }
+}
+
+// Ближайшее к name имя из names: не больше одной правки на каждые три символа
+fn nearest<'b>(name: &str, names: &[&'b str]) -> Option<&'b str> {
+    let n = name.chars().count();
+    names.iter().filter(|s| **s != name)
+        .map(|s| (distance(name, s), *s))
+        .filter(|&(d, _)| d <= (n / 3).max(1) && d < n)
+        .min()
+        .map(|(_, s)| s)
+}
+
+// Расстояние Дамерау - Левенштейна: число вставок, удалений, замен
+// и перестановок соседних символов
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d: Vec<Vec<usize>> = (0..=a.len())
+        .map(|i| (0..=b.len()).map(|j| if i == 0 { j } else if j == 0 { i } else { 0 }).collect())
+        .collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_error_in_one_pass() {
+        let ds = check("sni(1) + y + log(1, 2, 3)", &Env::new());
+        let kinds: Vec<_> = ds.iter().map(|d| &d.error.kind).collect();
+        assert!(matches!(kinds[..], [ErrorKind::UnknownFunction(_), ErrorKind::UnknownVariable(_),
+                                     ErrorKind::WrongArity { .. }]));
+        assert_eq!(ds[0].hint.as_deref(), Some("did you mean `sin`?"));
+    }
+
+    #[test]
+    fn knows_locals_and_bound_variables() {
+        assert!(check("f(t) = t^2; f(2) + sum(k, k, 1, 3) + diff(x^2, x, 1)", &Env::new()).is_empty());
+    }
+
+    #[test]
+    fn warns_when_a_series_index_is_a_variable() {
+        let ds = check("a = 1; b = 2; sum(a, b, 3, 4) + sum(k, k, 1, 3)", &Env::new());
+        assert_eq!(ds.len(), 1);
+        assert!(ds[0].is_warning());
+        assert_eq!((&ds[0].error.kind, ds[0].error.span.clone()), (&ErrorKind::Shadowed("b".to_string()), 21..22));
+        let mut env = Env::new();
+        env.set("k", 5.);
+        assert!(check("prod(k, k, 1, 3)", &env)[0].is_warning());
+        assert!(check("f(k) = sum(k, k, 1, 3)", &Env::new())[0].is_warning());
+    }
+
+    #[test]
+    fn renders_the_line_with_a_marker() {
+        let src = "sqr(2) + sni(3)";
+        let s = render(src, &check(src, &Env::new()));
+        assert!(s.ends_with("  sqr(2) + sni(3)\n           ^~~\n  hint: did you mean `sin`?"), "{s}");
+    }
+}
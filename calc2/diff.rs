@@ -0,0 +1,328 @@
+// Символьное дифференцирование дерева разбора
+use std::f64::consts::LN_10;
+use std::ops::Range;
+use super::expr::{BinOp, Expr, UnOp};
+use super::eval::is_series;
+use super::funcs::out1;
+use super::{Arity, CalcError, Env, ErrorKind};
+
+// Производная выражения e по переменной x;
+// вызовы пользовательских функций из env подставляются в выражение
+pub fn diff_in(e: &Expr, x: &str, env: &Env) -> Result<Expr, CalcError> {
+    Diff { x, env: Some(env), depth: 0 }.diff(e)
+}
+
+// Раскрывает вызов diff(expr, x[, at]) в формуле в выражение для производной
+pub(crate) fn expand_diff(args: &[Expr], span: &Range<usize>, env: Option<&Env>) -> Result<Expr, CalcError> {
+    if !Arity::Between(2, 3).accepts(args.len()) {
+        return Err(out1("diff", Arity::Between(2, 3), args.len(), span.clone()))
+    }
+    let Expr::Var { name, .. } = &args[1] else {
+        return Err(CalcError::new(ErrorKind::ExpectedVariable, span.clone()))
+    };
+    let d = Diff { x: name, env, depth: 0 }.diff(&args[0])?;
+    // diff(expr, x, at) - значение производной в точке at
+    Ok(match args.get(2) {
+        Some(at) => d.subst(&[(name.as_str(), at)]),
+        None => d
+    })
+}
+
+struct Diff<'a> {
+    x: &'a str,
+    env: Option<&'a Env>,
+    depth: usize
+}
+
+impl Diff<'_> {
+    fn diff(&mut self, e: &Expr) -> Result<Expr, CalcError> {
+        Ok(match e {
+            Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) => Expr::num(0.),
+            Expr::Var { name, .. } => Expr::num(if name == self.x { 1. } else { 0. }),
+            Expr::Unary(UnOp::Neg, a, _) => Expr::neg(self.diff(a)?),
+            // побитовые операции и остаток дифференцируем, только если они не зависят от x
+            Expr::Unary(UnOp::Not, ..) | Expr::Binary(BinOp::Mod, ..) if !e.depends_on(self.x) => Expr::num(0.),
+            Expr::Binary(op, ..) if op.is_bitwise() && !e.depends_on(self.x) => Expr::num(0.),
+            Expr::Unary(op, _, span) =>
+                return Err(CalcError::new(ErrorKind::NotDifferentiable(op.symbol().trim().to_string()), span.clone())),
+            Expr::Binary(op, a, b, span) => {
+                let (a, b) = (a.as_ref(), b.as_ref());
+                match op {
+                    BinOp::Add => add(self.diff(a)?, self.diff(b)?),
+                    BinOp::Sub => sub(self.diff(a)?, self.diff(b)?),
+                    // (ab)' = a'b + ab'
+                    BinOp::Mul => add(mul(self.diff(a)?, b.clone()), mul(a.clone(), self.diff(b)?)),
+                    // (a/b)' = (a'b - ab') / b^2
+                    BinOp::Div => div(
+                        sub(mul(self.diff(a)?, b.clone()), mul(a.clone(), self.diff(b)?)),
+                        pow(b.clone(), Expr::num(2.))),
+                    BinOp::Pow => self.diff_pow(a, b)?,
+                    _ => return Err(CalcError::new(
+                        ErrorKind::NotDifferentiable(op.symbol().to_string()), span.clone()))
+                }
+            },
+            Expr::Call { fname, args, span } => self.diff_call(fname, args, span)?,
+            // производная списка - список производных
+            Expr::List(xs, span) => Expr::List(xs.iter().map(|x| self.diff(x)).collect::<Result<_, _>>()?, span.clone()),
+            Expr::Index(_, i, span) if i.depends_on(self.x) =>
+                return Err(CalcError::new(ErrorKind::NotDifferentiable("[]".to_string()), span.clone())),
+            Expr::Index(a, i, span) => Expr::Index(Box::new(self.diff(a)?), i.clone(), span.clone())
+        })
+    }
+
+    fn diff_pow(&mut self, a: &Expr, b: &Expr) -> Result<Expr, CalcError> {
+        let (da, db) = (self.diff(a)?, self.diff(b)?);
+        Ok(if !b.depends_on(self.x) {
+            // (a^c)' = c * a^(c - 1) * a'
+            mul(mul(b.clone(), pow(a.clone(), sub(b.clone(), Expr::num(1.)))), da)
+        } else if !a.depends_on(self.x) {
+            // (c^b)' = c^b * ln(c) * b'
+            mul(mul(pow(a.clone(), b.clone()), call("ln", vec![a.clone()])), db)
+        } else {
+            // (a^b)' = a^b * (b' * ln(a) + b * a' / a)
+            mul(pow(a.clone(), b.clone()),
+                add(mul(db, call("ln", vec![a.clone()])), div(mul(b.clone(), da), a.clone())))
+        })
+    }
+
+    fn diff_call(&mut self, fname: &str, args: &[Expr], span: &Range<usize>) -> Result<Expr, CalcError> {
+        // пользовательская функция: подставляем аргументы в тело
+        if let Some(f) = self.env.and_then(|env| env.func(fname)) {
+            if f.params.len() != args.len() {
+                return Err(out1(fname, Arity::Exact(f.params.len()), args.len(), span.clone()))
+            }
+            if self.depth >= self.env.map_or(0, |env| env.max_depth()) {
+                return Err(CalcError::new(ErrorKind::RecursionLimit(fname.to_string()), span.clone()))
+            }
+            let xs: Vec<_> = f.params.iter().map(|p| p.as_str()).zip(args).collect();
+            self.depth += 1;
+            let d = self.diff(&f.body.subst(&xs));
+            self.depth -= 1;
+            return d
+        }
+        if fname == "diff" {
+            let e = expand_diff(args, span, self.env)?;
+            return self.diff(&e)
+        }
+        // if(cond, a, b)' = if(cond, a', b')
+        if fname == "if" {
+            if args.len() != 3 {
+                return Err(out1(fname, Arity::Exact(3), args.len(), span.clone()))
+            }
+            let (da, db) = (self.diff(&args[1])?, self.diff(&args[2])?);
+            return Ok(Expr::call("if", vec![args[0].clone(), da, db], span.clone()))
+        }
+        if fname == "integrate" || fname == "sum" && is_series(fname, args) {
+            if args.len() != 4 {
+                return Err(out1(fname, Arity::Exact(4), args.len(), span.clone()))
+            }
+            let Expr::Var { name: k, .. } = &args[1] else {
+                return Err(CalcError::new(ErrorKind::ExpectedVariable, span.clone()))
+            };
+            let (f, a, b) = (&args[0], &args[2], &args[3]);
+            // производная суммы или интеграла по параметру - сумма или интеграл производных
+            let mut d = if k == self.x || !f.depends_on(self.x) {
+                Expr::num(0.)
+            } else {
+                Expr::call(fname, vec![self.diff(f)?, args[1].clone(), a.clone(), b.clone()], span.clone())
+            };
+            if !a.depends_on(self.x) && !b.depends_on(self.x) { return Ok(d) }
+            if fname == "sum" {
+                return Err(CalcError::new(ErrorKind::NotDifferentiable(fname.to_string()), span.clone()))
+            }
+            // переменные пределы: integrate(f, t, a, b)' = f(b) * b' - f(a) * a' + integrate(f', t, a, b)
+            if b.depends_on(self.x) { d = add(d, mul(f.subst(&[(k, b)]), self.diff(b)?)); }
+            if a.depends_on(self.x) { d = sub(d, mul(f.subst(&[(k, a)]), self.diff(a)?)); }
+            return Ok(d)
+        }
+        let arity = match fname {
+            "pi" | "e" => Arity::Exact(0),
+            "pow" | "atan2" => Arity::Exact(2),
+            "log" => Arity::Between(1, 2),
+            "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "ln" | "exp" | "sqr" | "abs" => Arity::Exact(1),
+            "sinh" | "cosh" | "tanh" | "asinh" | "acosh" | "atanh" => Arity::Exact(1),
+            "avg" | "sum" => Arity::AtLeast(1),
+            _ => return Err(CalcError::new(ErrorKind::NotDifferentiable(fname.to_string()), span.clone()))
+        };
+        if !arity.accepts(args.len()) {
+            return Err(out1(fname, arity, args.len(), span.clone()))
+        }
+        if fname == "pi" || fname == "e" { return Ok(Expr::num(0.)) }
+        if fname == "pow" { return self.diff_pow(&args[0], &args[1]) }
+        // log(a, b) = ln(a) / ln(b)
+        if let ("log", [a, b]) = (fname, args) {
+            return self.diff(&div(call("ln", vec![a.clone()]), call("ln", vec![b.clone()])))
+        }
+        // atan2(y, x)' = (x*y' - y*x') / (x^2 + y^2)
+        if let ("atan2", [y, x]) = (fname, args) {
+            let d = div(sub(mul(x.clone(), self.diff(y)?), mul(y.clone(), self.diff(x)?)),
+                        add(pow(x.clone(), Expr::num(2.)), pow(y.clone(), Expr::num(2.))));
+            return Ok(self.angle(d, false))
+        }
+        // (sum(a, b, ...))' = sum(a', b', ...); аргументы могут быть списками
+        if fname == "sum" {
+            return Ok(call("sum", args.iter().map(|a| self.diff(a)).collect::<Result<_, _>>()?))
+        }
+        if fname == "avg" {
+            // (avg(a, b, ...))' = (a' + b' + ...) / n
+            let mut s = self.diff(&args[0])?;
+            for a in &args[1..] { s = add(s, self.diff(a)?); }
+            return Ok(div(s, Expr::num(args.len() as f64)))
+        }
+        let a = &args[0];
+        let da = self.diff(a)?;
+        // f(a)' = f'(a) * a'
+        let sq = || pow(a.clone(), Expr::num(2.));
+        let df = match fname {
+            "sin" => self.angle(call("cos", vec![a.clone()]), true),
+            "cos" => self.angle(Expr::neg(call("sin", vec![a.clone()])), true),
+            "tan" => self.angle(div(Expr::num(1.), pow(call("cos", vec![a.clone()]), Expr::num(2.))), true),
+            "asin" => self.angle(div(Expr::num(1.), call("sqr", vec![sub(Expr::num(1.), sq())])), false),
+            "acos" => self.angle(div(Expr::num(-1.), call("sqr", vec![sub(Expr::num(1.), sq())])), false),
+            "atan" => self.angle(div(Expr::num(1.), add(Expr::num(1.), sq())), false),
+            "sinh" => call("cosh", vec![a.clone()]),
+            "cosh" => call("sinh", vec![a.clone()]),
+            "tanh" => div(Expr::num(1.), pow(call("cosh", vec![a.clone()]), Expr::num(2.))),
+            "asinh" => div(Expr::num(1.), call("sqr", vec![add(sq(), Expr::num(1.))])),
+            "acosh" => div(Expr::num(1.), call("sqr", vec![sub(sq(), Expr::num(1.))])),
+            "atanh" => div(Expr::num(1.), sub(Expr::num(1.), sq())),
+            "abs" => call("sign", vec![a.clone()]),
+            "ln" => div(Expr::num(1.), a.clone()),
+            "log" => div(Expr::num(1.), mul(a.clone(), Expr::num(LN_10))),
+            "exp" => call("exp", vec![a.clone()]),
+            _ => div(Expr::num(1.), mul(Expr::num(2.), call("sqr", vec![a.clone()])))
+        };
+        Ok(mul(df, da))
+    }
+
+    // Производная тригонометрической функции при мере углов окружения:
+    // у прямой функции умножается на число радиан в единице меры, у обратной - делится
+    fn angle(&self, d: Expr, direct: bool) -> Expr {
+        let k = self.env.map_or(1., |env| env.registry().angle().factor());
+        if k == 1. { return d }
+        if direct { mul(d, Expr::num(k)) } else { div(d, Expr::num(k)) }
+    }
+}
+
+impl Expr {
+    // Входит ли переменная name в выражение
+    pub fn depends_on(&self, name: &str) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) => false,
+            Expr::Var { name: v, .. } => v == name,
+            Expr::Unary(_, a, _) => a.depends_on(name),
+            Expr::Binary(_, a, b, _) => a.depends_on(name) || b.depends_on(name),
+            Expr::Call { args, .. } | Expr::List(args, _) => args.iter().any(|a| a.depends_on(name)),
+            Expr::Index(a, i, _) => a.depends_on(name) || i.depends_on(name)
+        }
+    }
+
+    // Одновременная подстановка выражений вместо переменных. Переменная diff, solve,
+    // integrate и ряда связана в первом аргументе: вместо нее не подставляется, а если
+    // она входит в подставляемое выражение, она переименовывается
+    pub fn subst(&self, xs: &[(&str, &Expr)]) -> Expr {
+        match self {
+            // diff(f, x) - производная в точке x: x связана в f и свободна как точка.
+            // Если x заменяется или входит в выражение, подставляемое в f,
+            // запись развертывается в diff(f, x, x), и связанная x переименовывается
+            Expr::Call { fname, args, span } if fname == "diff" && args.len() == 2
+                && matches!(&args[1], Expr::Var { name, .. }
+                    if xs.iter().any(|(k, e)| k == name || args[0].depends_on(k) && e.depends_on(name))) => {
+                let mut args = args.clone();
+                args.push(args[1].clone());
+                Expr::call(fname, args, span.clone()).subst(xs)
+            },
+            Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) => self.clone(),
+            Expr::Var { name, .. } => match xs.iter().find(|(k, _)| k == name) {
+                Some((_, e)) => (*e).clone(),
+                None => self.clone()
+            },
+            Expr::Unary(op, a, span) => Expr::Unary(*op, Box::new(a.subst(xs)), span.clone()),
+            Expr::Binary(op, a, b, span) =>
+                Expr::Binary(*op, Box::new(a.subst(xs)), Box::new(b.subst(xs)), span.clone()),
+            Expr::Call { fname, args, span } => match bound(fname, args) {
+                Some((k, at)) => {
+                    let inner: Vec<_> = xs.iter().filter(|(v, _)| *v != k && args[0].depends_on(v)).copied().collect();
+                    let (body, var) = if inner.iter().any(|(_, e)| e.depends_on(k)) {
+                        // имя, которого нет ни в теле, ни в подставляемых выражениях
+                        let fresh = (1..).map(|n| format!("{k}{n}"))
+                            .find(|v| !args[0].depends_on(v) && inner.iter().all(|(_, e)| !e.depends_on(v)))
+                            .unwrap();
+                        let var = Expr::var(&fresh, at.clone());
+                        (args[0].subst(&[(k, &var)]), var)
+                    } else {
+                        (args[0].clone(), args[1].clone())
+                    };
+                    let mut ys = vec![body.subst(&inner), var];
+                    ys.extend(args[2..].iter().map(|a| a.subst(xs)));
+                    Expr::call(fname, ys, span.clone())
+                },
+                None => Expr::call(fname, args.iter().map(|a| a.subst(xs)).collect(), span.clone())
+            },
+            Expr::List(args, span) => Expr::List(args.iter().map(|a| a.subst(xs)).collect(), span.clone()),
+            Expr::Index(a, i, span) => Expr::Index(Box::new(a.subst(xs)), Box::new(i.subst(xs)), span.clone())
+        }
+    }
+}
+
+// Связанная переменная: diff(f, x, at), solve(f, x, ...), integrate(f, x, a, b), sum(f, k, a, b)
+fn bound<'a>(fname: &str, args: &'a [Expr]) -> Option<(&'a str, &'a Range<usize>)> {
+    let bound = match fname {
+        "diff" => args.len() == 3,
+        "solve" | "integrate" => args.len() >= 3,
+        _ => is_series(fname, args)
+    };
+    match args.get(1) {
+        Some(Expr::Var { name, span }) if bound => Some((name, span)),
+        _ => None
+    }
+}
+
+fn add(a: Expr, b: Expr) -> Expr { Expr::binary(BinOp::Add, a, b) }
+fn sub(a: Expr, b: Expr) -> Expr { Expr::binary(BinOp::Sub, a, b) }
+fn mul(a: Expr, b: Expr) -> Expr { Expr::binary(BinOp::Mul, a, b) }
+fn div(a: Expr, b: Expr) -> Expr { Expr::binary(BinOp::Div, a, b) }
+fn pow(a: Expr, b: Expr) -> Expr { Expr::binary(BinOp::Pow, a, b) }
+fn call(fname: &str, args: Vec<Expr>) -> Expr { Expr::call(fname, args, 0..0) }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Calc};
+    use super::*;
+
+    fn subst(s: &str, x: &str, by: &str) -> String {
+        parse(s).unwrap().subst(&[(x, &parse(by).unwrap())]).to_string()
+    }
+
+    fn calc(s: &str) -> Result<f64, ErrorKind> {
+        Calc::new(s).and_then(|c| c.calc_with(&mut Env::new())).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn differentiates() {
+        let d = |s: &str| expand_diff(&[parse(s).unwrap(), Expr::var("x", 0..0)], &(0..0), None).unwrap();
+        let mut env = Env::new();
+        env.set("x", 2.);
+        assert_eq!(d("x^3").eval(&env), Ok(12.));
+        assert_eq!(d("sin(x)*x").eval(&env), Ok(2f64.sin() + 2. * 2f64.cos()));
+        assert_eq!(calc("diff(x^2, x, 3)"), Ok(6.));
+    }
+
+    #[test]
+    fn subst_respects_bound_variables() {
+        assert_eq!(subst("x + sum(x*k, k, 1, 3)", "x", "k"), "k + sum(k*k1, k1, 1, 3)");
+        assert_eq!(subst("sum(k, k, 1, x)", "k", "2"), "sum(k, k, 1, x)");
+        assert_eq!(subst("integrate(t*x, x, 0, 1)", "t", "x + 1"), "integrate((x + 1)*x1, x1, 0, 1)");
+        assert_eq!(subst("diff(x^2*a, x)", "x", "3"), "diff(x^2*a, x, 3)");
+        assert_eq!(subst("diff(x^2*a, x)", "a", "x"), "diff(x1^2*x, x1, x)");
+        assert_eq!(subst("diff(x^2*a, x)", "a", "2"), "diff(x^2*2, x)");
+        assert_eq!(subst("diff(x^2*a, x, 1)", "a", "x"), "diff(x1^2*x, x1, 1)");
+    }
+
+    #[test]
+    fn user_functions_keep_their_bound_variables() {
+        assert_eq!(calc("w(t) = diff(t*x^2, x, 2); diff(w(x), x, 1)"), Ok(4.));
+        assert_eq!(calc("f(a) = sum(a*k, k, 1, 3); diff(f(k^2), k, 1)"), Ok(12.));
+        assert_eq!(calc("x = 1; f(a) = diff(x^2*a, x); diff(f(x), x)"), Ok(4.));
+    }
+}
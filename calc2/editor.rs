@@ -0,0 +1,225 @@
+// Редактор строки терминала: перемещение курсора, удаление, история ввода
+// с сохранением в файле. Терминал переводится в посимвольный режим
+// утилитой stty; если это не удалось, строку редактирует сам терминал
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// Наибольшее число строк истории
+const MAX_HISTORY: usize = 1000;
+
+pub(crate) struct Editor {
+    history: Vec<String>,
+    // файл истории; None - история не сохраняется
+    path: Option<PathBuf>
+}
+
+enum Key {
+    Char(char),
+    Enter, Backspace, Delete,
+    Left, Right, Home, End, Up, Down,
+    // Ctrl-K, Ctrl-U, Ctrl-W: удаление до конца строки, до начала строки, слова перед курсором
+    KillEnd, KillStart, KillWord,
+    // Ctrl-C и Ctrl-D
+    Interrupt, Eof,
+    Other
+}
+
+impl Editor {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = path.as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|s| s.lines().filter(|l| !l.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+        let n = history.len().saturating_sub(MAX_HISTORY);
+        history.drain(..n);
+        Self { history, path }
+    }
+
+    // Добавляет строку в историю и дописывает ее в файл истории
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.history.last().is_some_and(|l| l == line) { return }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY { self.history.remove(0); }
+        if let Some(path) = &self.path {
+            // ошибка записи истории не мешает вычислениям
+            let _ = OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut f| writeln!(f, "{line}"));
+        }
+    }
+
+    // Читает строку; None - конец ввода (Ctrl-D на пустой строке),
+    // ошибка Interrupted - ввод строки прерван по Ctrl-C
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut out = io::stdout();
+        write!(out, "{prompt}")?;
+        out.flush()?;
+        let Some(_raw) = RawMode::enter() else {
+            let mut s = String::new();
+            if io::stdin().read_line(&mut s)? == 0 { return Ok(None) }
+            return Ok(Some(s.trim_end_matches(['\n', '\r']).to_string()))
+        };
+        let mut input = io::stdin().lock();
+        let mut line = Line { prompt, buf: vec![], pos: 0 };
+        // номер строки истории; history.len() - новая строка, ее текст хранится в draft
+        let mut k = self.history.len();
+        let mut draft = vec![];
+        loop {
+            let Some(pressed) = key(&mut input)? else { return Ok(None) };
+            match pressed {
+                Key::Enter => {
+                    writeln!(out)?;
+                    return Ok(Some(line.buf.iter().collect()))
+                },
+                Key::Interrupt => {
+                    writeln!(out, "^C")?;
+                    return Err(io::ErrorKind::Interrupted.into())
+                },
+                Key::Eof if line.buf.is_empty() => {
+                    writeln!(out)?;
+                    return Ok(None)
+                },
+                Key::Char(c) => {
+                    line.buf.insert(line.pos, c);
+                    line.pos += 1;
+                },
+                Key::Backspace if line.pos > 0 => {
+                    line.pos -= 1;
+                    line.buf.remove(line.pos);
+                },
+                Key::Delete | Key::Eof if line.pos < line.buf.len() => { line.buf.remove(line.pos); },
+                Key::Left if line.pos > 0 => line.pos -= 1,
+                Key::Right if line.pos < line.buf.len() => line.pos += 1,
+                Key::Home => line.pos = 0,
+                Key::End => line.pos = line.buf.len(),
+                Key::KillEnd => line.buf.truncate(line.pos),
+                Key::KillStart => {
+                    line.buf.drain(..line.pos);
+                    line.pos = 0;
+                },
+                Key::KillWord => {
+                    let mut i = line.pos;
+                    while i > 0 && line.buf[i - 1] == ' ' { i -= 1; }
+                    while i > 0 && line.buf[i - 1] != ' ' { i -= 1; }
+                    line.buf.drain(i..line.pos);
+                    line.pos = i;
+                },
+                Key::Up if k > 0 => {
+                    if k == self.history.len() { draft = line.buf.clone(); }
+                    k -= 1;
+                    line.set(self.history[k].chars().collect());
+                },
+                Key::Down if k < self.history.len() => {
+                    k += 1;
+                    line.set(match self.history.get(k) {
+                        Some(s) => s.chars().collect(),
+                        None => draft.clone()
+                    });
+                },
+                _ => ()
+            }
+            line.redraw(&mut out)?;
+        }
+    }
+}
+
+// Редактируемая строка и положение курсора в ней
+struct Line<'a> {
+    prompt: &'a str,
+    buf: Vec<char>,
+    pos: usize
+}
+
+impl Line<'_> {
+    fn set(&mut self, buf: Vec<char>) {
+        self.pos = buf.len();
+        self.buf = buf;
+    }
+
+    // Строка печатается заново, курсор возвращается на место
+    fn redraw(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "\r{}{}\x1b[K", self.prompt, self.buf.iter().collect::<String>())?;
+        let back = self.buf.len() - self.pos;
+        if back > 0 { write!(out, "\x1b[{back}D")?; }
+        out.flush()
+    }
+}
+
+// Следующая клавиша; None - конец ввода
+fn key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(c) = next_char(input)? else { return Ok(None) };
+    Ok(Some(match c {
+        '\r' | '\n' => Key::Enter,
+        '\x7f' | '\x08' => Key::Backspace,
+        '\x01' => Key::Home,
+        '\x02' => Key::Left,
+        '\x03' => Key::Interrupt,
+        '\x04' => Key::Eof,
+        '\x05' => Key::End,
+        '\x06' => Key::Right,
+        '\x0b' => Key::KillEnd,
+        '\x0e' => Key::Down,
+        '\x10' => Key::Up,
+        '\x15' => Key::KillStart,
+        '\x17' => Key::KillWord,
+        // управляющая последовательность: ESC [ параметры буква или ESC O буква
+        '\x1b' => {
+            if !matches!(next_char(input)?, Some('[' | 'O')) { return Ok(Some(Key::Other)) }
+            let mut seq = String::new();
+            while let Some(c) = next_char(input)? {
+                seq.push(c);
+                if c.is_ascii_alphabetic() || c == '~' { break }
+            }
+            match seq.as_str() {
+                "A" => Key::Up,
+                "B" => Key::Down,
+                "C" => Key::Right,
+                "D" => Key::Left,
+                "H" | "1~" | "7~" => Key::Home,
+                "F" | "4~" | "8~" => Key::End,
+                "3~" => Key::Delete,
+                _ => Key::Other
+            }
+        },
+        c if c.is_control() => Key::Other,
+        c => Key::Char(c)
+    }))
+}
+
+// Символ UTF-8 из потока байтов
+fn next_char(input: &mut impl Read) -> io::Result<Option<char>> {
+    let mut bytes = [0u8; 4];
+    if input.read(&mut bytes[..1])? == 0 { return Ok(None) }
+    let n = match bytes[0].leading_ones() {
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        _ => 1
+    };
+    input.read_exact(&mut bytes[1..n])?;
+    Ok(Some(std::str::from_utf8(&bytes[..n]).ok().and_then(|s| s.chars().next()).unwrap_or('\u{fffd}')))
+}
+
+// Посимвольный режим терминала; прежние настройки восстанавливаются при удалении
+struct RawMode(String);
+
+impl RawMode {
+    fn enter() -> Option<Self> {
+        let saved = stty(&["-g"])?;
+        // Ctrl-C обрабатывает редактор, чтобы настройки терминала были восстановлены
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Some(Self(saved))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) { stty(&[&self.0]); }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+    if !out.status.success() { return None }
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
@@ -0,0 +1,128 @@
+// Окружение вычисления: значения именованных переменных,
+// функции, определенные пользователем, и реестр встроенных функций.
+// Значение, вычисленное в другом числовом режиме, хранится в числах режима
+// и, если возможно, приближенно в f64 - для остальных режимов
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use super::{Expr, Mode, Registry, Value};
+
+// Предельная глубина вложенных вызовов пользовательских функций
+pub const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunc {
+    pub params: Vec<String>,
+    pub body: Expr
+}
+
+// Значение в числах режима mode и его запись для :vars
+#[derive(Clone)]
+struct Exact {
+    mode: Option<Mode>,
+    value: Rc<dyn Any>,
+    text: String
+}
+
+impl Debug for Exact {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { f.write_str(&self.text) }
+}
+
+#[derive(Debug, Clone)]
+pub struct Env {
+    vars: HashMap<String, Value>,
+    exact: HashMap<String, Exact>,
+    funcs: HashMap<String, UserFunc>,
+    registry: Registry,
+    max_depth: usize
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::with_registry(Registry::new())
+    }
+}
+
+impl Env {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn with_registry(registry: Registry) -> Self {
+        Self { vars: HashMap::new(), exact: HashMap::new(), funcs: HashMap::new(), registry, max_depth: MAX_DEPTH }
+    }
+
+    // Числовое значение переменной; у логической переменной его нет
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn get(&self, name: &str) -> Option<f64> { self.value(name)?.num().ok() }
+
+    pub fn value(&self, name: &str) -> Option<Value> { self.vars.get(name).cloned() }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set(&mut self, name: &str, x: f64) { self.set_value(name, Value::Num(x)) }
+
+    pub fn set_value(&mut self, name: &str, x: Value) {
+        self.exact.remove(name);
+        self.vars.insert(name.to_string(), x);
+    }
+
+    // Значение переменной, вычисленное в режиме mode, в числах этого режима
+    pub(crate) fn exact<T: 'static>(&self, name: &str, mode: Option<Mode>) -> Option<&T> {
+        self.exact.get(name).filter(|x| x.mode == mode)?.value.downcast_ref()
+    }
+
+    // Сохраняет значение x режима mode; approx - его приближение для других режимов
+    pub(crate) fn set_exact<T: Display + 'static>(&mut self, name: &str, mode: Option<Mode>, x: T, approx: Option<Value>) {
+        match approx {
+            Some(v) => self.vars.insert(name.to_string(), v),
+            None => self.vars.remove(name)
+        };
+        let text = x.to_string();
+        self.exact.insert(name.to_string(), Exact { mode, value: Rc::new(x), text });
+    }
+
+    // Переменные в алфавитном порядке
+    pub fn vars(&self) -> Vec<(&str, Value)> {
+        let mut xs: Vec<_> = self.vars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+        xs.sort_by(|a, b| a.0.cmp(b.0));
+        xs
+    }
+
+    // Записи значений переменных в алфавитном порядке; значение
+    // другого числового режима - в числах этого режима
+    pub fn shown(&self) -> Vec<(&str, String)> {
+        let exact = self.exact.iter().map(|(k, x)| (k.as_str(), x.text.clone()));
+        let vars = self.vars.iter().filter(|(k, _)| !self.exact.contains_key(*k))
+            .map(|(k, v)| (k.as_str(), v.to_string()));
+        let mut xs: Vec<_> = exact.chain(vars).collect();
+        xs.sort_by(|a, b| a.0.cmp(b.0));
+        xs
+    }
+
+    pub fn func(&self, fname: &str) -> Option<&UserFunc> { self.funcs.get(fname) }
+
+    pub fn define(&mut self, fname: &str, params: Vec<String>, body: Expr) {
+        self.funcs.insert(fname.to_ascii_lowercase(), UserFunc { params, body });
+    }
+
+    // Имена пользовательских функций в алфавитном порядке
+    pub fn funcs(&self) -> Vec<&str> {
+        let mut xs: Vec<_> = self.funcs.keys().map(|k| k.as_str()).collect();
+        xs.sort();
+        xs
+    }
+
+    // Форма вычислителя fname (diff, if, solve, integrate, range, функции матриц)
+    // не перекрыта ни пользовательской функцией, ни функцией реестра
+    pub(crate) fn form(&self, fname: &str) -> bool {
+        self.func(fname).is_none() && self.registry.get(fname).is_none()
+    }
+
+    pub fn registry(&self) -> &Registry { &self.registry }
+
+    pub fn registry_mut(&mut self) -> &mut Registry { &mut self.registry }
+
+    pub fn max_depth(&self) -> usize { self.max_depth }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_max_depth(&mut self, n: usize) { self.max_depth = n; }
+}
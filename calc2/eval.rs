@@ -0,0 +1,382 @@
+// Вычисление значения формулы по дереву разбора
+use std::ops::Range;
+use super::expr::{BinOp, Expr, Stmt, UnOp};
+use super::backend::float_index;
+use super::diff::expand_diff;
+use super::funcs::out1;
+use super::integrate::integral_of;
+use super::matrix::{self, Matrix};
+use super::simplify::simplify;
+use super::solve::{root_of, Start};
+use super::value::{spread, truth};
+use super::{Arity, CalcError, Env, ErrorKind, Value};
+
+// Наибольшее число слагаемых в sum и множителей в prod
+pub(crate) const MAX_TERMS: usize = 1_000_000;
+
+impl Expr {
+    // Числовое значение формулы
+    pub fn eval(&self, env: &Env) -> Result<f64, CalcError> {
+        self.value(env)?.num().map_err(|k| CalcError::new(k, self.span()))
+    }
+
+    // Значение формулы: число, логическое значение или список
+    pub fn value(&self, env: &Env) -> Result<Value, CalcError> {
+        self.eval_at(env, &[], 0)
+    }
+
+    // Числовое значение с параметрами пользовательской функции locals
+    pub(crate) fn eval_local(&self, env: &Env, locals: &[(&str, Value)], depth: usize) -> Result<f64, CalcError> {
+        self.eval_at(env, locals, depth)?.num().map_err(|k| CalcError::new(k, self.span()))
+    }
+
+    // locals - параметры текущей пользовательской функции,
+    // depth - глубина вложенных вызовов пользовательских функций
+    fn eval_at(&self, env: &Env, locals: &[(&str, Value)], depth: usize) -> Result<Value, CalcError> {
+        // значение операнда нужного типа; ошибка относится к операции в положении span
+        let num = |e: &Expr, span: &Range<usize>| e.eval_at(env, locals, depth)?.num()
+            .map_err(|k| CalcError::new(k, span.clone()));
+        let cond = |e: &Expr, span: &Range<usize>| e.eval_at(env, locals, depth)?.bool()
+            .map_err(|k| CalcError::new(k, span.clone()));
+        match self {
+            Expr::Num(x) => Ok(Value::Num(*x)),
+            Expr::Int(n) => Ok(Value::Num(*n as f64)),
+            Expr::Dec(x, _) => Ok(Value::Num(*x)),
+            Expr::Var { name, span } => locals.iter()
+                .find(|(k, _)| k == name).map(|(_, v)| v.clone())
+                .or_else(|| env.value(name))
+                .or_else(|| truth(name).map(Value::Bool))
+                .ok_or_else(|| CalcError::new(ErrorKind::UnknownVariable(name.clone()), span.clone())),
+            Expr::Unary(UnOp::LNot, a, span) => Ok(Value::Bool(!cond(a, span)?)),
+            Expr::Unary(op, a, span) => match a.eval_at(env, locals, depth)? {
+                v @ (Value::List(_) | Value::Matrix(_)) => Ok(v.map(|x| op.apply(x))),
+                v @ Value::Expr(_) => Value::symbolic(|mut xs| Expr::Unary(*op, Box::new(xs.remove(0)), 0..0), vec![v])
+                    .map_err(|k| CalcError::new(k, span.clone())),
+                v => Ok(Value::Num(op.apply(v.num().map_err(|k| CalcError::new(k, span.clone()))?)))
+            },
+            // второй операнд and, or вычисляется, только если от него зависит результат
+            Expr::Binary(op, a, b, span) if op.is_logical() => {
+                let x = cond(a, span)?;
+                if x == (*op == BinOp::LOr) { return Ok(Value::Bool(x)) }
+                Ok(Value::Bool(cond(b, span)?))
+            },
+            Expr::Binary(op, a, b, span) if op.is_comparison() => {
+                let (x, y) = (a.eval_at(env, locals, depth)?, b.eval_at(env, locals, depth)?);
+                x.compare(*op, y, |op, x, y| Ok(op.compare(x.partial_cmp(&y))))
+                    .map(Value::Bool).map_err(|k| CalcError::new(k, span.clone()))
+            },
+            // произведение и степень матриц - матричные, остальное над списками
+            // и матрицами - поэлементно
+            Expr::Binary(op, a, b, span) => {
+                let (x, y) = (a.eval_at(env, locals, depth)?, b.eval_at(env, locals, depth)?);
+                match (op, x) {
+                    // с формулой - формула: diff(x^3, x) * 2
+                    (op, x) if matches!(x, Value::Expr(_)) || matches!(y, Value::Expr(_)) =>
+                        Value::symbolic(|xs| Expr::binary(*op, xs[0].clone(), xs[1].clone()), vec![x, y]),
+                    (BinOp::Mul, x) => matrix::product(x, y),
+                    (BinOp::Pow, Value::Matrix(m)) => y.num().and_then(|n| m.pow(n)).map(Value::Matrix),
+                    (op, x) => x.zip(y, |x, y| Ok(op.apply(x, y)))
+                }.map_err(|k| CalcError::new(k, span.clone()))
+            },
+            // список из списков - матрица по строкам
+            Expr::List(xs, span) => {
+                let xs = xs.iter().map(|x| x.eval_at(env, locals, depth)).collect::<Result<Vec<_>, _>>()?;
+                let rows = xs.iter().any(|x| matches!(x, Value::List(_)));
+                let r = if rows {
+                    xs.into_iter().map(|x| match x {
+                        Value::List(r) => Ok(r),
+                        x => Err(ErrorKind::Type(format!("expected a matrix row, found {}", x.kind())))
+                    }).collect::<Result<_, _>>().and_then(Matrix::from_rows).map(Value::Matrix)
+                } else {
+                    xs.into_iter().map(|x| x.num()).collect::<Result<_, _>>().map(Value::List)
+                };
+                r.map_err(|k| CalcError::new(k, span.clone()))
+            },
+            Expr::Index(a, i, span) => {
+                let xs = a.eval_at(env, locals, depth)?;
+                let i = num(i, span)?;
+                let Some(k) = float_index(i) else {
+                    return Err(CalcError::new(ErrorKind::Domain(format!("invalid index {i}")), span.clone()))
+                };
+                xs.at(k).map_err(|k| CalcError::new(k, span.clone()))
+            },
+            // diff(expr, x[, at]) - аргументы не вычисляются, выражение для производной
+            // вычисляется в текущем окружении; diff(expr, x) по x без значения -
+            // производная как упрощенная формула: diff(x^3, x) = 3*x^2
+            Expr::Call { fname, args, span } if fname == "diff" && env.form(fname) => {
+                let d = expand_diff(args, span, Some(env))?;
+                match &args[..] {
+                    [_, Expr::Var { name, .. }] if !locals.iter().any(|(k, _)| k == name)
+                        && env.value(name).is_none() && truth(name).is_none() => Ok(Value::Expr(simplify(&d))),
+                    _ => d.eval_at(env, locals, depth)
+                }
+            },
+            // if(cond, a, b) - вычисляется только выбранная ветвь
+            Expr::Call { fname, args, span } if fname == "if" && env.form(fname) => {
+                if args.len() != 3 {
+                    return Err(out1(fname, Arity::Exact(3), args.len(), span.clone()))
+                }
+                let branch = if cond(&args[0], span)? { &args[1] } else { &args[2] };
+                branch.eval_at(env, locals, depth)
+            },
+            // solve(expr, x, guess), solve(expr, x, a, b) - выражение вычисляется
+            // при разных значениях x, остальные аргументы - один раз
+            Expr::Call { fname, args, span } if fname == "solve" && env.form(fname) => {
+                if !Arity::Between(3, 4).accepts(args.len()) {
+                    return Err(out1(fname, Arity::Between(3, 4), args.len(), span.clone()))
+                }
+                let Expr::Var { name, .. } = &args[1] else {
+                    return Err(CalcError::new(ErrorKind::ExpectedVariable, span.clone()))
+                };
+                let start = match &args[2..] {
+                    [a, b] => Start::Between(num(a, span)?, num(b, span)?),
+                    _ => Start::Guess(num(&args[2], span)?)
+                };
+                root_of(&args[0], name, start, env, locals, depth, span).map(Value::Num)
+            },
+            // integrate(expr, x, a, b), sum(expr, k, from, to), prod(expr, k, from, to):
+            // выражение вычисляется при разных значениях переменной, пределы - один раз.
+            // sum и prod есть и в реестре, ряд от них отличает только запись
+            Expr::Call { fname, args, span } if fname == "integrate" && env.form(fname)
+                || is_series(fname, args) && env.func(fname).is_none() => {
+                if args.len() != 4 {
+                    return Err(out1(fname, Arity::Exact(4), args.len(), span.clone()))
+                }
+                let Expr::Var { name, .. } = &args[1] else {
+                    return Err(CalcError::new(ErrorKind::ExpectedVariable, span.clone()))
+                };
+                let (a, b) = (num(&args[2], span)?, num(&args[3], span)?);
+                if fname == "integrate" {
+                    let r = integral_of(&args[0], name, (a, b), env, locals, depth, span)?;
+                    if !r.converged() {
+                        return Err(CalcError::new(ErrorKind::NoConvergence(fname.clone()), span.clone()))
+                    }
+                    return Ok(Value::Num(r.value))
+                }
+                // переменная пробегает значения from, from + 1, ... не больше to
+                if !(a.is_finite() && b.is_finite()) || b - a >= MAX_TERMS as f64 {
+                    let kind = ErrorKind::Domain(format!("too many terms in '{fname}'"));
+                    return Err(CalcError::new(kind, span.clone()))
+                }
+                // счетчик целый: при больших from шаг k + 1 может не менять k
+                let mut r = if fname == "sum" { 0. } else { 1. };
+                for i in 0..=(b - a).floor() as i64 {
+                    let mut xs = vec![(name.as_str(), Value::Num(a + i as f64))];
+                    xs.extend_from_slice(locals);
+                    let y = args[0].eval_local(env, &xs, depth)?;
+                    if fname == "sum" { r += y } else { r *= y }
+                }
+                Ok(Value::Num(r))
+            },
+            Expr::Call { fname, args, span } => {
+                let args = args.iter().map(|a| a.eval_at(env, locals, depth))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // пользовательские функции перекрывают встроенные
+                match env.func(fname) {
+                    Some(f) => {
+                        if f.params.len() != args.len() {
+                            return Err(out1(fname, Arity::Exact(f.params.len()), args.len(), span.clone()))
+                        }
+                        if depth >= env.max_depth() {
+                            return Err(CalcError::new(ErrorKind::RecursionLimit(fname.clone()), span.clone()))
+                        }
+                        let locals: Vec<_> = f.params.iter().map(|p| p.as_str()).zip(args).collect();
+                        f.body.eval_at(env, &locals, depth + 1)
+                    },
+                    // функции реестра перекрывают range и функции матриц
+                    None if env.form(fname) && matrix::FUNCS.contains(&fname.as_str()) =>
+                        matrix::call(fname, args).map_err(|k| CalcError::new(k, span.clone())),
+                    None if env.form(fname) && fname == "range" => {
+                        let args = args.into_iter().map(|x| x.num())
+                            .collect::<Result<Vec<_>, _>>().map_err(|k| CalcError::new(k, span.clone()))?;
+                        range(&args).map(Value::List).map_err(|k| CalcError::new(k, span.clone()))
+                    },
+                    None => {
+                        // встроенные функции принимают числа и списки чисел
+                        let Some(f) = env.registry().get(fname) else {
+                            return Err(CalcError::new(ErrorKind::UnknownFunction(fname.clone()), span.clone()))
+                        };
+                        let variadic = matches!(f.arity, Arity::AtLeast(_));
+                        if !variadic && !f.arity.accepts(args.len()) {
+                            return Err(out1(fname, f.arity, args.len(), span.clone()))
+                        }
+                        if args.iter().any(|a| matches!(a, Value::Expr(_))) {
+                            return Value::symbolic(|xs| Expr::call(fname, xs, 0..0), args)
+                                .map_err(|k| CalcError::new(k, span.clone()))
+                        }
+                        spread(args, variadic, |xs| {
+                            if f.arity.accepts(xs.len()) { return Ok(f.call(xs)) }
+                            Err(ErrorKind::WrongArity { fname: fname.clone(), expected: f.arity, got: xs.len() })
+                        }).map_err(|k| CalcError::new(k, span.clone()))
+                    }
+                }
+            }
+        }
+    }
+}
+
+// sum(expr, k, from, to) и prod(expr, k, from, to) с переменной k вторым из четырех
+// аргументов - сумма и произведение ряда, даже если expr от k не зависит: sum(2, k, 1, 10);
+// иначе sum и prod - функции от значений: sum(1, 2), sum([1, 2]).
+// Решается только по записи, чтобы вычислитель, байт-код и вывод формулы не расходились;
+// если k - еще и переменная, диагностика предупреждает об этом (ErrorKind::Shadowed)
+pub(crate) fn is_series(fname: &str, args: &[Expr]) -> bool {
+    matches!(fname, "sum" | "prod") && matches!(args, [_, Expr::Var { .. }, _, _])
+}
+
+// range(from, to[, step]) - числа from, from + step, ... не дальше to
+fn range(args: &[f64]) -> Result<Vec<f64>, ErrorKind> {
+    if !Arity::Between(2, 3).accepts(args.len()) {
+        return Err(ErrorKind::WrongArity { fname: "range".to_string(), expected: Arity::Between(2, 3), got: args.len() })
+    }
+    let (from, to, step) = (args[0], args[1], args.get(2).copied().unwrap_or(1.));
+    let n = ((to - from) / step).floor();
+    if !(n.is_finite() && step != 0.) || n >= MAX_TERMS as f64 {
+        return Err(ErrorKind::Domain(format!("invalid range from {from} to {to} with the step {step}")))
+    }
+    Ok((0..=n.max(-1.) as i64).map(|k| from + k as f64 * step).collect())
+}
+
+impl UnOp {
+    // ~x для нецелого x дает NaN; логические значения в байт-коде - 1 и 0
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            UnOp::Neg => -x,
+            UnOp::Not => int_of(x).map_or(f64::NAN, |a| !a as f64),
+            UnOp::LNot => f64::from(x == 0.)
+        }
+    }
+}
+
+impl BinOp {
+    // Операция над f64; побитовые операции над нецелыми числами дают NaN,
+    // сравнения и логические операции - 1 или 0
+    pub fn apply(&self, x: f64, y: f64) -> f64 {
+        match self {
+            op if op.is_comparison() => f64::from(op.compare(x.partial_cmp(&y))),
+            BinOp::LAnd => f64::from(x != 0. && y != 0.),
+            BinOp::LOr => f64::from(x != 0. || y != 0.),
+            BinOp::Add => x + y,
+            BinOp::Sub => x - y,
+            BinOp::Mul => x * y,
+            BinOp::Div => x / y,
+            BinOp::Pow => x.powf(y),
+            BinOp::Mod => x % y,
+            _ => {
+                let (Some(a), Some(b)) = (int_of(x), int_of(y)) else { return f64::NAN };
+                (match self {
+                    BinOp::And => a & b,
+                    BinOp::Or => a | b,
+                    BinOp::Xor => a ^ b,
+                    // сдвиг на 64 и более разрядов: все биты выдвинуты
+                    BinOp::Shl if b >= 0 => if b < 64 { a << b } else { 0 },
+                    BinOp::Shr if b >= 0 => a >> b.min(63),
+                    _ => return f64::NAN
+                }) as f64
+            }
+        }
+    }
+}
+
+// Целое значение f64 в пределах i64
+fn int_of(x: f64) -> Option<i64> {
+    if x.fract() == 0. && x.abs() < 9.2e18 { Some(x as i64) } else { None }
+}
+
+impl Stmt {
+    // Значение оператора; у определения функции значения нет
+    pub fn eval(&self, env: &mut Env) -> Result<Option<Value>, CalcError> {
+        match self {
+            Stmt::Assign { name, expr, .. } => {
+                let x = expr.value(env)?;
+                env.set_value(name, x.clone());
+                Ok(Some(x))
+            },
+            Stmt::Def { fname, params, body, .. } => {
+                env.define(fname, params.clone(), body.clone());
+                Ok(None)
+            },
+            Stmt::Expr(expr) => Ok(Some(expr.value(env)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Calc;
+    use super::*;
+
+    fn calc(s: &str) -> Result<f64, ErrorKind> {
+        Calc::new(s).and_then(|c| c.calc_with(&mut Env::new())).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn registry_overrides_the_forms() {
+        let mut env = Env::new();
+        env.registry_mut().register("det", Arity::Exact(1), "det(x) - twice x", |a| 2. * a[0]);
+        env.registry_mut().register("range", Arity::Exact(2), "range(a, b) - b - a", |a| a[1] - a[0]);
+        let mut calc = |s: &str| Calc::new(s).and_then(|c| c.calc_with(&mut env)).map_err(|e| e.kind);
+        assert_eq!(calc("det(3)"), Ok(6.));
+        assert_eq!(calc("range(2, 7)"), Ok(5.));
+        assert_eq!(calc("dot([1, 2], [3, 4])"), Ok(11.));
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let mut env = Env::new();
+        env.set_max_depth(10);
+        let mut calc = |s: &str| Calc::new(s).and_then(|c| c.calc_with(&mut env)).map_err(|e| e.kind);
+        assert_eq!(calc("f(n) = if(n > 0, f(n - 1) + 1, 0); f(9)"), Ok(9.));
+        assert_eq!(calc("f(10)"), Err(ErrorKind::RecursionLimit("f".to_string())));
+        assert_eq!(calc("g(n) = g(n + 1); g(0)"), Err(ErrorKind::RecursionLimit("g".to_string())));
+    }
+
+    #[test]
+    fn series_sum_and_product() {
+        assert_eq!(calc("sum(k^2, k, 1, 10)"), Ok(385.));
+        assert_eq!(calc("prod(k, k, 1, 5)"), Ok(120.));
+        assert_eq!(calc("sum(k, k, 3, 1)"), Ok(0.));
+        assert_eq!(calc("sum(k, k, 0.5, 3)"), Ok(4.5));
+        assert!(matches!(calc("sum(k, k, 1, 1e9)"), Err(ErrorKind::Domain(_))));
+    }
+
+    #[test]
+    fn series_with_large_bounds_terminates() {
+        // 1e17 + 1 == 1e17 в f64: число слагаемых задает целый счетчик
+        let s = calc("sum(k, k, 1e17, 1e17 + 100)").unwrap();
+        assert!((9e18..=1.02e19).contains(&s));
+    }
+
+    #[test]
+    fn series_is_decided_by_syntax() {
+        assert_eq!(calc("sum(2, k, 1, 10)"), Ok(20.));
+        assert_eq!(calc("k = 100; sum(2, k, 1, 10)"), Ok(20.));
+        assert_eq!(calc("prod(2, k, 1, 3)"), Ok(8.));
+        assert_eq!(calc("sum(1, 2, 3, 4)"), Ok(10.));
+        // b - и переменная, и индекс ряда: ряд, о чем предупреждает диагностика;
+        // сумма значений - в списке
+        assert_eq!(calc("a = 1; b = 2; sum(a, b, 3, 4)"), Ok(2.));
+        assert_eq!(calc("a = 1; b = 2; sum([a, b, 3, 4])"), Ok(10.));
+        assert_eq!(calc("a = 1; sum(a, 2, 3)"), Ok(6.));
+    }
+
+    #[test]
+    fn derivatives_by_an_unknown_variable_are_formulas() {
+        let value = |s: &str| Calc::new(s).and_then(|c| c.calc_value(&mut Env::new())).map(|v| v.to_string());
+        assert_eq!(value("diff(x^3, x)").as_deref(), Ok("3*x^2"));
+        assert_eq!(value("d = diff(x^3, x); d*2 + 1").as_deref(), Ok("6*x^2 + 1"));
+        assert_eq!(value("diff(diff(x^3, x), x)").as_deref(), Ok("6*x"));
+        assert_eq!(value("sin(diff(x^2, x))").as_deref(), Ok("sin(2*x)"));
+        // при значении x и в точке - число
+        assert_eq!(calc("x = 2; diff(x^3, x)"), Ok(12.));
+        assert_eq!(calc("diff(x^3, x, 2)"), Ok(12.));
+        assert!(matches!(calc("diff(x^3, x)"), Err(ErrorKind::Type(_))));
+        assert!(matches!(calc("diff(x^3, x) > 1"), Err(ErrorKind::Type(_))));
+    }
+
+    #[test]
+    fn lazy_bodies() {
+        assert_eq!(calc("if(1 > 0, 1, 1/0)"), Ok(1.));
+        assert!((calc("integrate(x^2, x, 0, 3)").unwrap() - 9.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,241 @@
+// Дерево разбора формулы
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    // побитовое отрицание ~
+    Not,
+    // логическое отрицание not
+    LNot
+}
+
+impl UnOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnOp::Neg => "-",
+            UnOp::Not => "~",
+            UnOp::LNot => "not "
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add, Sub, Mul, Div, Pow,
+    // остаток от деления %
+    Mod,
+    // побитовые операции & | xor << >>
+    And, Or, Xor, Shl, Shr,
+    // сравнения < <= > >= == !=
+    Lt, Le, Gt, Ge, Eq, Ne,
+    // логические операции and, or с вычислением по короткой схеме
+    LAnd, LOr
+}
+
+impl BinOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Pow => "^",
+            BinOp::Mod => "%",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "xor",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::LAnd => "and",
+            BinOp::LOr => "or"
+        }
+    }
+
+    // Побитовые операции определены только для целых чисел
+    pub fn is_bitwise(&self) -> bool {
+        matches!(self, BinOp::And | BinOp::Or | BinOp::Xor | BinOp::Shl | BinOp::Shr)
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne)
+    }
+
+    // Операции над логическими значениями: and, or
+    pub fn is_logical(&self) -> bool { matches!(self, BinOp::LAnd | BinOp::LOr) }
+
+    // Результат сравнения по порядку значений; None - значения не упорядочены (NaN)
+    pub fn compare(&self, o: Option<Ordering>) -> bool {
+        match self {
+            BinOp::Lt => o == Some(Ordering::Less),
+            BinOp::Le => matches!(o, Some(Ordering::Less | Ordering::Equal)),
+            BinOp::Gt => o == Some(Ordering::Greater),
+            BinOp::Ge => matches!(o, Some(Ordering::Greater | Ordering::Equal)),
+            BinOp::Eq => o == Some(Ordering::Equal),
+            _ => o != Some(Ordering::Equal)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    // целый литерал, не представимый точно в f64 (больше 2^53)
+    Int(u64),
+    // десятичный литерал больше чем из 15 значащих цифр: приближение и запись,
+    // по которой точные режимы восстанавливают число без округления
+    Dec(f64, String),
+    Var { name: String, span: Range<usize> },
+    // span - положение знака операции в исходной строке
+    Unary(UnOp, Box<Expr>, Range<usize>),
+    Binary(BinOp, Box<Expr>, Box<Expr>, Range<usize>),
+    // span - положение имени функции в исходной строке
+    Call { fname: String, args: Vec<Expr>, span: Range<usize> },
+    // список [a, b, ...]; span - положение '['
+    List(Vec<Expr>, Range<usize>),
+    // элемент списка a[i]; span - положение '['
+    Index(Box<Expr>, Box<Expr>, Range<usize>)
+}
+
+impl Expr {
+    pub fn num(x: f64) -> Self { Expr::Num(x) }
+    pub fn var(name: &str, span: Range<usize>) -> Self {
+        Expr::Var { name: name.to_string(), span }
+    }
+    pub fn neg(a: Expr) -> Self { Expr::Unary(UnOp::Neg, Box::new(a), 0..0) }
+    pub fn binary(op: BinOp, a: Expr, b: Expr) -> Self {
+        Expr::Binary(op, Box::new(a), Box::new(b), 0..0)
+    }
+    pub fn call(fname: &str, args: Vec<Expr>, span: Range<usize>) -> Self {
+        Expr::Call { fname: fname.to_string(), args, span }
+    }
+
+    // Положение узла в исходной строке; у чисел положение не хранится
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) => 0..0,
+            Expr::Var { span, .. } | Expr::Unary(.., span) | Expr::Binary(.., span)
+            | Expr::Call { span, .. } | Expr::List(_, span) | Expr::Index(.., span) => span.clone()
+        }
+    }
+
+    // Приоритет узла при печати: чем больше, тем сильнее связывает
+    pub(crate) fn prec(&self) -> u8 {
+        match self {
+            Expr::Binary(BinOp::LOr, ..) => 1,
+            Expr::Binary(BinOp::LAnd, ..) => 2,
+            Expr::Unary(UnOp::LNot, ..) => 3,
+            Expr::Binary(op, ..) if op.is_comparison() => 4,
+            Expr::Binary(BinOp::Or, ..) => 6,
+            Expr::Binary(BinOp::Xor, ..) => 7,
+            Expr::Binary(BinOp::And, ..) => 8,
+            Expr::Binary(BinOp::Shl | BinOp::Shr, ..) => 9,
+            Expr::Binary(BinOp::Add | BinOp::Sub, ..) => 10,
+            Expr::Binary(BinOp::Mul | BinOp::Div | BinOp::Mod, ..) => 11,
+            // унарный минус связывает слабее '^': -2^2 = -(2^2)
+            Expr::Unary(..) => 12,
+            // отрицательное число печатается как унарный минус
+            Expr::Num(x) if x.is_sign_negative() => 12,
+            Expr::Binary(BinOp::Pow, ..) => 13,
+            _ => 14
+        }
+    }
+
+    // Наименьшие приоритеты левого и правого операнда, при которых скобки не нужны
+    pub(crate) fn operand_prec(&self) -> (u8, u8) {
+        match self {
+            Expr::Unary(UnOp::LNot, ..) => (4, 4),
+            Expr::Unary(..) => (13, 13),
+            Expr::Index(..) => (14, 0),
+            Expr::Binary(op, _, b, _) => {
+                // левоассоциативным операциям справа скобки нужны уже при равном приоритете,
+                // кроме ассоциативных + * & | xor and or; '^' правоассоциативна
+                let p = self.prec();
+                let (left, right) = match op {
+                    BinOp::Add | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor
+                    | BinOp::LAnd | BinOp::LOr => (p, p),
+                    BinOp::Pow => (p + 1, p),
+                    _ => (p, p + 1)
+                };
+                // унарный минус справа от операции берем в скобки
+                (left, if b.prec() == 12 { 14 } else { right })
+            },
+            _ => (0, 0)
+        }
+    }
+}
+
+// Печать формулы с минимумом скобок: результат снова разбирается в то же дерево
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // операнд в скобках, если он связывает слабее min
+        let sub = |f: &mut Formatter<'_>, e: &Expr, min: u8| {
+            if e.prec() < min { write!(f, "({e})") } else { write!(f, "{e}") }
+        };
+        match self {
+            Expr::Num(x) => write!(f, "{x}"),
+            Expr::Int(n) => write!(f, "{n:#x}"),
+            Expr::Dec(_, s) => write!(f, "{s}"),
+            Expr::Var { name, .. } => write!(f, "{name}"),
+            Expr::Unary(op, a, _) => {
+                write!(f, "{}", op.symbol())?;
+                sub(f, a, self.operand_prec().0)
+            },
+            Expr::Binary(op, a, b, _) => {
+                let (left, right) = self.operand_prec();
+                sub(f, a, left)?;
+                match op {
+                    BinOp::Mul | BinOp::Div | BinOp::Pow => write!(f, "{}", op.symbol())?,
+                    _ => write!(f, " {} ", op.symbol())?
+                }
+                sub(f, b, right)
+            },
+            Expr::Call { fname, args, .. } => {
+                write!(f, "{fname}(")?;
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{a}")?;
+                }
+                write!(f, ")")
+            },
+            Expr::List(xs, _) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{x}")?;
+                }
+                write!(f, "]")
+            },
+            Expr::Index(a, i, _) => {
+                sub(f, a, self.operand_prec().0)?;
+                write!(f, "[{i}]")
+            }
+        }
+    }
+}
+
+// Оператор программы: присваивание, определение функции или выражение
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Assign { name: String, expr: Expr, span: Range<usize> },
+    Def { fname: String, params: Vec<String>, body: Expr, span: Range<usize> },
+    Expr(Expr)
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Assign { name, expr, .. } => write!(f, "{name} = {expr}"),
+            Stmt::Def { fname, params, body, .. } => write!(f, "{fname}({}) = {body}", params.join(", ")),
+            Stmt::Expr(expr) => write!(f, "{expr}")
+        }
+    }
+}
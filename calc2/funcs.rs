@@ -0,0 +1,310 @@
+// Реестр встроенных функций: имя -> (арность, описание, реализация).
+// Встроенные функции можно переопределять и удалять,
+// приложение может регистрировать собственные.
+// Мера углов реестра действует на все тригонометрические функции.
+// Формы вычислителя (diff, if, solve, ...) не входят в реестр,
+// но функция реестра с тем же именем их перекрывает
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use std::rc::Rc;
+use super::{Arity, CalcError, ErrorKind};
+
+pub type NativeFn = Rc<dyn Fn(&[f64]) -> f64>;
+
+#[derive(Clone)]
+pub struct Builtin {
+    pub arity: Arity,
+    pub doc: String,
+    f: NativeFn
+}
+
+impl Builtin {
+    pub fn call(&self, args: &[f64]) -> f64 { (self.f)(args) }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn native(&self) -> NativeFn { self.f.clone() }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Builtin({:?}, {:?})", self.arity, self.doc)
+    }
+}
+
+// Мера углов: аргументы sin, cos, tan и значения обратных функций
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Angle {
+    #[default]
+    Radians,
+    Degrees,
+    // 400 градов в полном обороте
+    Gradians
+}
+
+impl Angle {
+    // Радиан в единице меры
+    pub fn factor(self) -> f64 {
+        match self {
+            Angle::Radians => 1.,
+            Angle::Degrees => PI / 180.,
+            Angle::Gradians => PI / 200.
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Angle::Radians => "rad",
+            Angle::Degrees => "deg",
+            Angle::Gradians => "grad"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rad" | "radians" => Some(Angle::Radians),
+            "deg" | "degrees" => Some(Angle::Degrees),
+            "grad" | "gradians" => Some(Angle::Gradians),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Registry {
+    funcs: HashMap<String, Builtin>,
+    // имена, зарегистрированные или удаленные приложением
+    custom: HashSet<String>,
+    angle: Angle
+}
+
+// Формы вычислителя: аргументы не сводятся к числам,
+// поэтому они разбираются самим вычислителем
+pub(crate) const FORMS: [(&str, &str); 11] = [
+    ("if", "if(c, a, b) - a when c is not zero, otherwise b; only one branch is computed"),
+    ("diff", "diff(f, x[, a]) - derivative of f by x, at x = a if given"),
+    ("solve", "solve(f, x, a[, b]) - root of f = 0 near a, or between a and b"),
+    ("integrate", "integrate(f, x, a, b) - definite integral of f by x from a to b"),
+    ("range", "range(a, b[, step]) - list of numbers from a to b"),
+    ("det", "det(m) - determinant of a square matrix"),
+    ("inv", "inv(m) - inverse of a square matrix"),
+    ("transpose", "transpose(m) - transposed matrix"),
+    ("dot", "dot(u, v) - scalar product of vectors"),
+    ("cross", "cross(u, v) - vector product of 3D vectors"),
+    ("norm", "norm(v[, p]) - p-norm of a vector, euclidean by default"),
+];
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut r = Self::empty();
+        // --> Функции с произвольным количеством аргументов
+        r.builtin("min", Arity::AtLeast(1), "min(x, ...) - smallest argument",
+                   |a| a.iter().copied().fold(a[0], f64::min));
+        r.builtin("max", Arity::AtLeast(1), "max(x, ...) - largest argument",
+                   |a| a.iter().copied().fold(a[0], f64::max));
+        r.builtin("avg", Arity::AtLeast(1), "avg(x, ...) - arithmetic mean",
+                   |a| a.iter().sum::<f64>() / a.len() as f64);
+        // --> Статистика; списки в аргументах раскрываются: median([1, 5, 2]) = median(1, 5, 2)
+        r.builtin("sum", Arity::AtLeast(1), "sum(x, ...), sum(f, k, a, b) - sum of the values, or of f for k from a to b", |a| a.iter().sum());
+        r.builtin("prod", Arity::AtLeast(1), "prod(x, ...), prod(f, k, a, b) - product of the values, or of f for k from a to b", |a| a.iter().product());
+        r.builtin("count", Arity::AtLeast(0), "count(x, ...) - number of the values", |a| a.len() as f64);
+        r.builtin("median", Arity::AtLeast(1), "median(x, ...) - middle value", |a| percentile(a, 50.));
+        r.builtin("mode", Arity::AtLeast(1),
+                   "mode(x, ...) - most frequent value, the smallest of equally frequent", mode);
+        r.builtin("variance", Arity::AtLeast(2), "variance(x, ...) - sample variance", variance);
+        r.builtin("stddev", Arity::AtLeast(2), "stddev(x, ...) - sample standard deviation",
+                   |a| variance(a).sqrt());
+        r.builtin("percentile", Arity::AtLeast(2),
+                   "percentile(x, ..., p) - p-th percentile of the values, 0 <= p <= 100",
+                   |a| percentile(&a[..a.len() - 1], a[a.len() - 1]));
+        r.builtin("gcd", Arity::AtLeast(1), "gcd(n, ...) - greatest common divisor of integers",
+                   |a| a.iter().copied().fold(0., gcd));
+        r.builtin("lcm", Arity::AtLeast(1), "lcm(n, ...) - least common multiple of integers",
+                   |a| a.iter().copied().fold(1., lcm));
+        // --> Функции с двумя аргументами
+        r.builtin("pow", Arity::Exact(2), "pow(x, y) - x raised to the power y",
+                   |a| a[0].powf(a[1]));
+        // --> Функции с одним аргументом
+        r.builtin("sqr", Arity::Exact(1), "sqr(x) - square root", |a| a[0].powf(0.5));
+        r.builtin("exp", Arity::Exact(1), "exp(x) - e raised to the power x", |a| a[0].exp());
+        r.builtin("ln", Arity::Exact(1), "ln(x) - natural logarithm", |a| a[0].ln());
+        r.builtin("log", Arity::Between(1, 2), "log(x[, b]) - logarithm to the base b, decimal by default",
+                   |a| if a.len() == 1 { a[0].log10() } else { a[0].ln() / a[1].ln() });
+        r.builtin("sinh", Arity::Exact(1), "sinh(x) - hyperbolic sine", |a| a[0].sinh());
+        r.builtin("cosh", Arity::Exact(1), "cosh(x) - hyperbolic cosine", |a| a[0].cosh());
+        r.builtin("tanh", Arity::Exact(1), "tanh(x) - hyperbolic tangent", |a| a[0].tanh());
+        r.builtin("asinh", Arity::Exact(1), "asinh(x) - inverse hyperbolic sine", |a| a[0].asinh());
+        r.builtin("acosh", Arity::Exact(1), "acosh(x) - inverse hyperbolic cosine", |a| a[0].acosh());
+        r.builtin("atanh", Arity::Exact(1), "atanh(x) - inverse hyperbolic tangent", |a| a[0].atanh());
+        r.builtin("abs", Arity::Exact(1), "abs(x) - absolute value", |a| a[0].abs());
+        r.builtin("sign", Arity::Exact(1), "sign(x) - -1, 0 or 1 by the sign of x",
+                   |a| if a[0] == 0. { 0. } else { a[0].signum() });
+        r.builtin("floor", Arity::Exact(1), "floor(x) - largest integer not greater than x", |a| a[0].floor());
+        r.builtin("ceil", Arity::Exact(1), "ceil(x) - smallest integer not less than x", |a| a[0].ceil());
+        r.builtin("round", Arity::Exact(1), "round(x) - nearest integer, halves away from zero", |a| a[0].round());
+        r.builtin("trunc", Arity::Exact(1), "trunc(x) - integer part of x", |a| a[0].trunc());
+        r.builtin("fact", Arity::Exact(1), "fact(x), x! - factorial, gamma(x + 1) for a non-integer x",
+                   |a| gamma(a[0] + 1.));
+        r.builtin("gamma", Arity::Exact(1), "gamma(x) - gamma function, gamma(n + 1) = n!", |a| gamma(a[0]));
+        r.trig();
+        // --> Функции без аргументов
+        r.builtin("pi", Arity::Exact(0), "pi() - the constant 3.14159...",
+                   |_| std::f64::consts::PI);
+        r.builtin("e", Arity::Exact(0), "e() - the constant 2.71828...",
+                   |_| std::f64::consts::E);
+        r
+    }
+}
+
+impl Registry {
+    // Реестр со встроенными функциями
+    pub fn new() -> Self { Self::default() }
+
+    // Пустой реестр
+    pub fn empty() -> Self { Self { funcs: HashMap::new(), custom: HashSet::new(), angle: Angle::Radians } }
+
+    pub fn angle(&self) -> Angle { self.angle }
+
+    // Меняет меру углов; тригонометрические функции регистрируются заново,
+    // кроме переопределенных или удаленных приложением
+    pub fn set_angle(&mut self, angle: Angle) {
+        self.angle = angle;
+        self.trig();
+    }
+
+    // Тригонометрические функции в мере углов реестра
+    fn trig(&mut self) {
+        let k = self.angle.factor();
+        self.builtin("sin", Arity::Exact(1), "sin(x) - sine", move |a| (a[0] * k).sin());
+        self.builtin("cos", Arity::Exact(1), "cos(x) - cosine", move |a| (a[0] * k).cos());
+        self.builtin("tan", Arity::Exact(1), "tan(x) - tangent", move |a| (a[0] * k).tan());
+        self.builtin("asin", Arity::Exact(1), "asin(x) - inverse sine", move |a| a[0].asin() / k);
+        self.builtin("acos", Arity::Exact(1), "acos(x) - inverse cosine", move |a| a[0].acos() / k);
+        self.builtin("atan", Arity::Exact(1), "atan(x) - inverse tangent", move |a| a[0].atan() / k);
+        self.builtin("atan2", Arity::Exact(2), "atan2(y, x) - angle of the point (x, y)",
+                      move |a| a[0].atan2(a[1]) / k);
+    }
+
+    // Встроенная функция: меру углов можно менять, пока приложение ее не заменило
+    fn builtin<F>(&mut self, fname: &str, arity: Arity, doc: &str, f: F)
+        where F: Fn(&[f64]) -> f64 + 'static {
+        if !self.custom.contains(fname) {
+            self.funcs.insert(fname.to_string(), Builtin { arity, doc: doc.to_string(), f: Rc::new(f) });
+        }
+    }
+
+    // Регистрирует функцию; функция с тем же именем заменяется
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn register<F>(&mut self, fname: &str, arity: Arity, doc: &str, f: F)
+        where F: Fn(&[f64]) -> f64 + 'static {
+        let fname = fname.to_ascii_lowercase();
+        self.custom.insert(fname.clone());
+        self.funcs.insert(fname, Builtin { arity, doc: doc.to_string(), f: Rc::new(f) });
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn remove(&mut self, fname: &str) -> Option<Builtin> {
+        let fname = fname.to_ascii_lowercase();
+        self.custom.insert(fname.clone());
+        self.funcs.remove(&fname)
+    }
+
+    pub fn get(&self, fname: &str) -> Option<&Builtin> { self.funcs.get(fname) }
+
+    // Функции в алфавитном порядке
+    pub fn list(&self) -> Vec<(&str, &Builtin)> {
+        let mut xs: Vec<_> = self.funcs.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        xs.sort_by(|a, b| a.0.cmp(b.0));
+        xs
+    }
+}
+
+// Ошибка: неверное количество аргументов функции
+pub(crate) fn out1(fname: &str, expected: Arity, got: usize, span: Range<usize>) -> CalcError {
+    CalcError::new(ErrorKind::WrongArity { fname: fname.to_string(), expected, got }, span)
+}
+
+// Процентиль с линейной интерполяцией между соседними по величине значениями
+fn percentile(a: &[f64], p: f64) -> f64 {
+    if a.is_empty() || !(0. ..=100.).contains(&p) || a.iter().any(|x| x.is_nan()) { return f64::NAN }
+    let mut xs = a.to_vec();
+    xs.sort_by(f64::total_cmp);
+    let k = p / 100. * (xs.len() - 1) as f64;
+    let (i, t) = (k.floor() as usize, k.fract());
+    if t == 0. { xs[i] } else { xs[i] + (xs[i + 1] - xs[i]) * t }
+}
+
+fn mode(a: &[f64]) -> f64 {
+    let mut xs = a.to_vec();
+    xs.sort_by(f64::total_cmp);
+    let (mut best, mut best_n, mut i) = (f64::NAN, 0, 0);
+    while i < xs.len() {
+        let n = xs[i..].iter().take_while(|&&x| x == xs[i]).count().max(1);
+        if n > best_n { (best, best_n) = (xs[i], n); }
+        i += n;
+    }
+    best
+}
+
+// Наибольший общий делитель целых чисел; для дробных - NaN
+fn gcd(a: f64, b: f64) -> f64 {
+    if a.fract() != 0. || b.fract() != 0. || !(a.is_finite() && b.is_finite()) { return f64::NAN }
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0. { (a, b) = (b, a % b); }
+    a
+}
+
+fn lcm(a: f64, b: f64) -> f64 {
+    let d = gcd(a, b);
+    if d == 0. { d } else { (a / d * b).abs() }
+}
+
+// Гамма-функция: приближение Ланцоша, для x < 1/2 - формула отражения
+fn gamma(x: f64) -> f64 {
+    const G: [f64; 9] = [
+        0.9999999999998099, 676.5203681218851, -1259.1392167224028, 771.3234287776531,
+        -176.6150291621406, 12.507343278686905, -0.13857109526572012, 9.984369578019572e-6,
+        1.5056327351493116e-7
+    ];
+    if x <= 0. && x.fract() == 0. { return f64::NAN }
+    if x < 0.5 { return PI / ((PI * x).sin() * gamma(1. - x)) }
+    // целые аргументы - точно, как у факториала
+    if x.fract() == 0. && x <= 171. { return (1..x as u32).map(f64::from).product() }
+    let x = x - 1.;
+    let t = x + 7.5;
+    let s = G[1..].iter().enumerate().fold(G[0], |s, (i, g)| s + g / (x + i as f64 + 1.));
+    (2. * PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * s
+}
+
+// Выборочная дисперсия: сумма квадратов отклонений от среднего, деленная на n - 1
+fn variance(a: &[f64]) -> f64 {
+    let m = a.iter().sum::<f64>() / a.len() as f64;
+    a.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (a.len() - 1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angle_keeps_the_host_functions() {
+        let mut r = Registry::new();
+        r.register("sin", Arity::Exact(1), "sin(x) - sine in turns", |a| (a[0] * 2. * PI).sin());
+        r.remove("cos");
+        r.set_angle(Angle::Degrees);
+        assert_eq!(r.get("sin").unwrap().doc, "sin(x) - sine in turns");
+        assert!(r.get("cos").is_none());
+        assert!((r.get("tan").unwrap().call(&[45.]) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn factorial() {
+        let fact = |x: f64| Registry::new().get("fact").unwrap().call(&[x]);
+        assert_eq!(fact(0.), 1.);
+        assert_eq!(fact(5.), 120.);
+        assert!((fact(5.5) - 287.885_277_815_045).abs() < 1e-9);
+        assert!((fact(0.5) - PI.sqrt() / 2.).abs() < 1e-12);
+        assert!(fact(-1.).is_nan());
+    }
+}
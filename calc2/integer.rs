@@ -0,0 +1,241 @@
+// Целочисленный режим программиста: разрядность 8/16/32/64,
+// знаковые или беззнаковые числа, переполнение с заворачиванием
+// по модулю 2^bits или с ошибкой. Значения хранятся в i128
+use super::backend::{fold, Backend};
+use super::expr::BinOp;
+use super::{Arity, ErrorKind, Mode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntMode {
+    pub bits: u32,
+    pub signed: bool,
+    // переполнение - ошибка, иначе результат заворачивается
+    pub checked: bool,
+    // основание системы счисления при печати, 2..=36
+    pub base: u32
+}
+
+impl Default for IntMode {
+    fn default() -> Self { Self { bits: 64, signed: true, checked: false, base: 10 } }
+}
+
+impl IntMode {
+    pub fn min(&self) -> i128 { if self.signed { -(1 << (self.bits - 1)) } else { 0 } }
+
+    pub fn max(&self) -> i128 { if self.signed { (1 << (self.bits - 1)) - 1 } else { (1 << self.bits) - 1 } }
+
+    // Младшие bits разрядов числа в дополнительном коде
+    pub fn wrap(&self, x: i128) -> i128 {
+        let m = x & ((1 << self.bits) - 1);
+        if m > self.max() { m - (1 << self.bits) } else { m }
+    }
+
+    // Приведение результата операции к разрядности режима
+    pub fn fit(&self, x: i128) -> Result<i128, ErrorKind> {
+        if !self.checked { return Ok(self.wrap(x)) }
+        if x < self.min() || x > self.max() { return Err(ErrorKind::Overflow) }
+        Ok(x)
+    }
+
+    // Печать в системе счисления base с префиксом 0x, 0o, 0b;
+    // отрицательные числа в недесятичной записи печатаются в дополнительном коде
+    pub fn format(&self, x: i128) -> String {
+        if self.base == 10 { return x.to_string() }
+        let mut n = if x < 0 { (x + (1 << self.bits)) as u128 } else { x as u128 };
+        let mut digits = vec![];
+        loop {
+            digits.push(std::char::from_digit((n % self.base as u128) as u32, self.base).unwrap());
+            n /= self.base as u128;
+            if n == 0 { break }
+        }
+        let prefix = match self.base {
+            16 => "0x",
+            8 => "0o",
+            2 => "0b",
+            _ => ""
+        };
+        format!("{prefix}{}", digits.iter().rev().collect::<String>())
+    }
+}
+
+pub struct IntBackend {
+    pub mode: IntMode
+}
+
+impl IntBackend {
+    // Литерал задает набор разрядов: 0xff в 8-битном знаковом режиме равно -1
+    fn bits(&self, x: i128) -> Result<i128, ErrorKind> {
+        if self.mode.checked && (x < self.mode.min() || x > 0 && x >> self.mode.bits != 0) {
+            return Err(ErrorKind::Overflow)
+        }
+        Ok(self.mode.wrap(x))
+    }
+
+    fn mul(&self, a: i128, b: i128) -> Result<i128, ErrorKind> {
+        match a.checked_mul(b) {
+            Some(x) => self.mode.fit(x),
+            None if self.mode.checked => Err(ErrorKind::Overflow),
+            None => Ok(self.mode.wrap(a.wrapping_mul(b)))
+        }
+    }
+
+    fn pow(&self, a: i128, n: i128) -> Result<i128, ErrorKind> {
+        if n < 0 { return Err(ErrorKind::Domain("a negative power of an integer".to_string())) }
+        let (mut r, mut x, mut k) = (self.mode.fit(1)?, a, n);
+        loop {
+            if k & 1 == 1 { r = self.mul(r, x)?; }
+            k >>= 1;
+            if k == 0 { return Ok(r) }
+            x = self.mul(x, x)?;
+        }
+    }
+
+    fn shift(&self, op: BinOp, a: i128, n: i128) -> Result<i128, ErrorKind> {
+        if n < 0 { return Err(ErrorKind::Domain("a negative shift count".to_string())) }
+        if op == BinOp::Shr { return Ok(a >> n.min(127)) }
+        // все разряды выдвинуты
+        if n >= self.mode.bits as i128 {
+            return if a == 0 || !self.mode.checked { Ok(0) } else { Err(ErrorKind::Overflow) }
+        }
+        self.mode.fit(a << n)
+    }
+}
+
+// Целое число из f64
+fn whole(x: f64) -> Result<i128, ErrorKind> {
+    if x.fract() != 0. || !x.is_finite() {
+        return Err(ErrorKind::Domain(format!("{x} is not an integer")))
+    }
+    if x.abs() >= 2f64.powi(64) { return Err(ErrorKind::Overflow) }
+    Ok(x as i128)
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 { (a, b) = (b, a % b); }
+    a
+}
+
+impl Backend for IntBackend {
+    type Value = i128;
+
+    // Значение переменной - число, а не набор разрядов: -5 в 8-битном режиме равно -5
+    fn num(&self, x: f64) -> Result<i128, ErrorKind> { self.mode.fit(whole(x)?) }
+
+    fn literal(&self, x: f64) -> Result<i128, ErrorKind> { self.bits(whole(x)?) }
+
+    fn int(&self, n: u64) -> Result<i128, ErrorKind> { self.bits(n as i128) }
+
+    fn neg(&self, a: &i128) -> Result<i128, ErrorKind> { self.mode.fit(-a) }
+
+    fn not(&self, a: &i128) -> Result<i128, ErrorKind> { Ok(self.mode.wrap(!a)) }
+
+    fn binary(&self, op: BinOp, a: &i128, b: &i128) -> Result<i128, ErrorKind> {
+        let (a, b) = (*a, *b);
+        match op {
+            BinOp::Add => self.mode.fit(a + b),
+            BinOp::Sub => self.mode.fit(a - b),
+            BinOp::Mul => self.mul(a, b),
+            // деление с округлением к нулю
+            BinOp::Div if b == 0 => Err(ErrorKind::DivisionByZero),
+            BinOp::Div => self.mode.fit(a / b),
+            BinOp::Mod if b == 0 => Err(ErrorKind::DivisionByZero),
+            BinOp::Mod => self.mode.fit(a % b),
+            BinOp::Pow => self.pow(a, b),
+            // побитовые операции не переполняются
+            BinOp::And => Ok(self.mode.wrap(a & b)),
+            BinOp::Or => Ok(self.mode.wrap(a | b)),
+            BinOp::Xor => Ok(self.mode.wrap(a ^ b)),
+            BinOp::Shl | BinOp::Shr => self.shift(op, a, b),
+            _ => Err(ErrorKind::Unsupported(op.symbol().to_string()))
+        }
+    }
+
+    fn compare(&self, op: BinOp, a: &i128, b: &i128) -> Result<bool, ErrorKind> {
+        Ok(op.compare(Some(a.cmp(b))))
+    }
+
+    fn index(&self, a: &i128) -> Option<i64> { i64::try_from(*a).ok() }
+
+    fn mode(&self) -> Option<Mode> { Some(Mode::Integer(self.mode)) }
+
+    fn float(&self, a: &i128) -> Option<f64> { Some(*a as f64) }
+
+    fn arity(&self, fname: &str) -> Option<Arity> {
+        match fname {
+            "min" | "max" | "sum" | "prod" | "gcd" | "lcm" => Some(Arity::AtLeast(1)),
+            "pow" => Some(Arity::Exact(2)),
+            "abs" | "sign" => Some(Arity::Exact(1)),
+            _ => None
+        }
+    }
+
+    fn call(&self, fname: &str, args: &[i128]) -> Result<i128, ErrorKind> {
+        match fname {
+            "min" => Ok(*args.iter().min().unwrap()),
+            "max" => Ok(*args.iter().max().unwrap()),
+            "sum" | "prod" => fold(self, fname, args),
+            "sign" => Ok(args[0].signum()),
+            "gcd" => self.mode.fit(args.iter().fold(0, |a, &b| gcd(a, b))),
+            "lcm" => args.iter().try_fold(1, |a, &b| {
+                if a == 0 || b == 0 { return Ok(0) }
+                self.mul(a / gcd(a, b), b).map(i128::abs)
+            }),
+            "pow" => self.pow(args[0], args[1]),
+            _ => self.mode.fit(args[0].abs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Calc, Env, Mode};
+    use super::*;
+
+    fn i8(signed: bool, checked: bool) -> IntMode { IntMode { bits: 8, signed, checked, base: 10 } }
+
+    fn calc(s: &str, m: IntMode, env: &mut Env) -> Result<String, ErrorKind> {
+        Calc::new(s).and_then(|c| c.calc_mode(Mode::Integer(m), env)).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn literals_are_bit_patterns() {
+        let mut env = Env::new();
+        assert_eq!(calc("0xff", i8(true, true), &mut env), Ok("-1".to_string()));
+        assert_eq!(calc("0xff", i8(false, true), &mut env), Ok("255".to_string()));
+        assert_eq!(calc("256", i8(false, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("256", i8(false, false), &mut env), Ok("0".to_string()));
+        assert_eq!(calc("-128", i8(true, true), &mut env), Ok("-128".to_string()));
+        assert_eq!(calc("-129", i8(true, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("-1", i8(false, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("-1", i8(false, false), &mut env), Ok("255".to_string()));
+    }
+
+    #[test]
+    fn variables_are_range_checked() {
+        let mut env = Env::new();
+        env.set("a", -5.);
+        env.set("b", 200.);
+        env.set("c", 0.5);
+        assert_eq!(calc("a", i8(true, true), &mut env), Ok("-5".to_string()));
+        assert_eq!(calc("a * 2", i8(true, true), &mut env), Ok("-10".to_string()));
+        assert_eq!(calc("b", i8(true, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("b", i8(true, false), &mut env), Ok("-56".to_string()));
+        assert_eq!(calc("b", i8(false, true), &mut env), Ok("200".to_string()));
+        assert_eq!(calc("a", i8(false, true), &mut env), Err(ErrorKind::Overflow));
+        assert!(matches!(calc("c", i8(true, true), &mut env), Err(ErrorKind::Domain(_))));
+    }
+
+    #[test]
+    fn checked_arithmetic_edges() {
+        let mut env = Env::new();
+        assert_eq!(calc("127 + 1", i8(true, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("127 + 1", i8(true, false), &mut env), Ok("-128".to_string()));
+        assert_eq!(calc("-128 / -1", i8(true, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("16 * 16", i8(false, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("1 << 8", i8(false, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("1 << 8", i8(false, false), &mut env), Ok("0".to_string()));
+        assert_eq!(calc("2 ^ 7", i8(true, true), &mut env), Err(ErrorKind::Overflow));
+        assert_eq!(calc("0 - 1", i8(false, false), &mut env), Ok("255".to_string()));
+    }
+}
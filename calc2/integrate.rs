@@ -0,0 +1,111 @@
+// Численное интегрирование: адаптивная квадратура Гаусса - Кронрода по 7 и 15 точкам.
+// Разность двух формул на отрезке - оценка погрешности; отрезок с наибольшей
+// погрешностью делится пополам, пока сумма оценок не станет меньше допуска
+use std::ops::Range;
+use super::expr::Expr;
+use super::{CalcError, Env, ErrorKind, Value};
+
+// Допустимая погрешность: абсолютная и относительная
+const ABS_TOL: f64 = 1e-10;
+const REL_TOL: f64 = 1e-10;
+// Наибольшее число отрезков разбиения
+const MAX_SEGMENTS: usize = 2000;
+
+// Узлы формулы Кронрода на [-1, 1]; узлы с нечетными номерами - узлы формулы Гаусса
+const XGK: [f64; 8] = [
+    0.9914553711208126, 0.9491079123427585, 0.8648644233597691, 0.7415311855993945,
+    0.5860872354676911, 0.4058451513773972, 0.20778495500789848, 0.
+];
+const WGK: [f64; 8] = [
+    0.022935322010529224, 0.06309209262997856, 0.10479001032225019, 0.14065325971552592,
+    0.1690047266392679, 0.19035057806478542, 0.20443294007529889, 0.20948214108472782
+];
+const WG: [f64; 4] = [
+    0.1294849661688697, 0.27970539148927664, 0.3818300505051189, 0.4179591836734694
+];
+
+// Значение интеграла и оценка его погрешности
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Integral {
+    pub value: f64,
+    pub error: f64
+}
+
+impl Integral {
+    // Достигнута ли заданная точность
+    pub fn converged(&self) -> bool { self.error <= ABS_TOL.max(REL_TOL * self.value.abs()) }
+}
+
+// Интеграл выражения e по переменной x от a до b
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn integrate(e: &Expr, x: &str, a: f64, b: f64, env: &Env) -> Result<Integral, CalcError> {
+    integral_of(e, x, (a, b), env, &[], 0, &e.span())
+}
+
+// (a, b) - пределы интегрирования; locals и depth - как при вычислении формулы,
+// в которой вызван integrate
+pub(crate) fn integral_of(e: &Expr, x: &str, (a, b): (f64, f64), env: &Env, locals: &[(&str, Value)],
+                          depth: usize, span: &Range<usize>) -> Result<Integral, CalcError> {
+    quad(|t| {
+        let mut xs = vec![(x, Value::Num(t))];
+        xs.extend_from_slice(locals);
+        e.eval_local(env, &xs, depth)
+    }, a, b, span)
+}
+
+pub(crate) fn quad<F>(mut f: F, a: f64, b: f64, span: &Range<usize>) -> Result<Integral, CalcError>
+    where F: FnMut(f64) -> Result<f64, CalcError> {
+    if !(a.is_finite() && b.is_finite()) {
+        let kind = ErrorKind::Domain("the limits of integration must be finite".to_string());
+        return Err(CalcError::new(kind, span.clone()))
+    }
+    // отрезки разбиения: концы, интеграл и погрешность
+    let mut segments = vec![kronrod(&mut f, a, b, span)?];
+    loop {
+        let value: f64 = segments.iter().map(|s| s.2).sum();
+        let error: f64 = segments.iter().map(|s| s.3).sum();
+        let r = Integral { value, error };
+        if r.converged() || segments.len() >= MAX_SEGMENTS { return Ok(r) }
+        let (k, &(lo, hi, ..)) = segments.iter().enumerate()
+            .max_by(|x, y| x.1.3.total_cmp(&y.1.3)).unwrap();
+        let mid = (lo + hi) / 2.;
+        // отрезок не делится в пределах точности f64
+        if mid == lo || mid == hi { return Ok(r) }
+        segments[k] = kronrod(&mut f, lo, mid, span)?;
+        segments.push(kronrod(&mut f, mid, hi, span)?);
+    }
+}
+
+// Интеграл по отрезку [a, b] по формуле Кронрода и его погрешность
+fn kronrod<F>(f: &mut F, a: f64, b: f64, span: &Range<usize>) -> Result<(f64, f64, f64, f64), CalcError>
+    where F: FnMut(f64) -> Result<f64, CalcError> {
+    let mut at = |t: f64| {
+        let y = f(t)?;
+        if y.is_finite() { return Ok(y) }
+        Err(CalcError::new(ErrorKind::Domain(format!("the integrand is not finite at {t}")), span.clone()))
+    };
+    let (c, h) = ((a + b) / 2., (b - a) / 2.);
+    let fc = at(c)?;
+    let (mut k, mut g) = (WGK[7] * fc, WG[3] * fc);
+    for i in 0..7 {
+        let s = at(c - h * XGK[i])? + at(c + h * XGK[i])?;
+        k += WGK[i] * s;
+        if i % 2 == 1 { g += WG[i / 2] * s; }
+    }
+    Ok((a, b, k * h, ((k - g) * h).abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Env};
+    use super::*;
+
+    #[test]
+    fn integrates() {
+        let env = Env::new();
+        let r = integrate(&parse("x^2").unwrap(), "x", 0., 3., &env).unwrap();
+        assert!(r.converged() && (r.value - 9.).abs() < 1e-9);
+        let r = integrate(&parse("sin(x)").unwrap(), "x", 0., std::f64::consts::PI, &env).unwrap();
+        assert!((r.value - 2.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,254 @@
+// Матрицы в формулах: литерал [[1, 2], [3, 4]] - список строк одинаковой длины.
+// A * B - произведение матриц, A * v и v * A - произведение матрицы и вектора (списка),
+// A^n - степень квадратной матрицы; остальные операции поэлементные
+use std::fmt::{Display, Formatter};
+use super::{Arity, ErrorKind, Value};
+
+// Функции над матрицами и векторами
+pub(crate) const FUNCS: [&str; 6] = ["det", "inv", "transpose", "dot", "cross", "norm"];
+
+// Матрица rows x cols; элементы хранятся по строкам
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix<T = f64> {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<T>
+}
+
+impl<T> Matrix<T> {
+    // Матрица из строк одинаковой длины
+    pub fn from_rows(xs: Vec<Vec<T>>) -> Result<Self, ErrorKind> {
+        let cols = xs.first().map_or(0, |r| r.len());
+        if cols == 0 { return Err(ErrorKind::Dimension("an empty matrix".to_string())) }
+        if let Some(r) = xs.iter().find(|r| r.len() != cols) {
+            return Err(ErrorKind::Dimension(format!("matrix rows of lengths {cols} and {}", r.len())))
+        }
+        Ok(Self { rows: xs.len(), cols, data: xs.into_iter().flatten().collect() })
+    }
+
+    // Строка с номером i
+    pub fn row(&self, i: usize) -> &[T] { &self.data[i * self.cols..(i + 1) * self.cols] }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Matrix<U> {
+        Matrix { rows: self.rows, cols: self.cols, data: self.data.into_iter().map(f).collect() }
+    }
+}
+
+impl Matrix {
+    pub fn identity(n: usize) -> Self {
+        Self { rows: n, cols: n, data: (0..n * n).map(|k| if k % (n + 1) == 0 { 1. } else { 0. }).collect() }
+    }
+
+    fn at(&self, i: usize, j: usize) -> f64 { self.data[i * self.cols + j] }
+
+    pub fn transpose(&self) -> Self {
+        let data = (0..self.cols * self.rows).map(|k| self.at(k % self.rows, k / self.rows)).collect();
+        Self { rows: self.cols, cols: self.rows, data }
+    }
+
+    pub fn mul(&self, y: &Self) -> Result<Self, ErrorKind> {
+        if self.cols != y.rows {
+            return Err(ErrorKind::Dimension(format!("cannot multiply {} by {}", self.shape(), y.shape())))
+        }
+        let data = (0..self.rows * y.cols)
+            .map(|k| (0..self.cols).map(|t| self.at(k / y.cols, t) * y.at(t, k % y.cols)).sum())
+            .collect();
+        Ok(Self { rows: self.rows, cols: y.cols, data })
+    }
+
+    // Определитель методом Гаусса с выбором главного элемента
+    pub fn det(&self) -> Result<f64, ErrorKind> {
+        self.square("det")?;
+        let (mut a, n) = (self.clone(), self.rows);
+        let mut d = 1.;
+        for j in 0..n {
+            let p = (j..n).max_by(|&x, &y| a.at(x, j).abs().total_cmp(&a.at(y, j).abs())).unwrap();
+            if a.at(p, j) == 0. { return Ok(0.) }
+            if p != j {
+                a.swap_rows(p, j);
+                d = -d;
+            }
+            d *= a.at(j, j);
+            for i in j + 1..n {
+                let k = a.at(i, j) / a.at(j, j);
+                for t in j..n { a.data[i * n + t] -= k * a.at(j, t); }
+            }
+        }
+        Ok(d)
+    }
+
+    // Обратная матрица методом Гаусса - Жордана
+    pub fn inv(&self) -> Result<Self, ErrorKind> {
+        self.square("inv")?;
+        let (mut a, n) = (self.clone(), self.rows);
+        let mut r = Self::identity(n);
+        // ведущий элемент меньше eps считается нулем
+        let scale = self.data.iter().fold(0., |m: f64, x| m.max(x.abs()));
+        let eps = scale * n as f64 * f64::EPSILON;
+        for j in 0..n {
+            let p = (j..n).max_by(|&x, &y| a.at(x, j).abs().total_cmp(&a.at(y, j).abs())).unwrap();
+            if a.at(p, j).abs() <= eps || !a.at(p, j).is_finite() {
+                return Err(ErrorKind::Domain("the matrix is singular".to_string()))
+            }
+            a.swap_rows(p, j);
+            r.swap_rows(p, j);
+            let k = a.at(j, j);
+            for t in 0..n {
+                a.data[j * n + t] /= k;
+                r.data[j * n + t] /= k;
+            }
+            for i in (0..n).filter(|&i| i != j) {
+                let k = a.at(i, j);
+                for t in 0..n {
+                    a.data[i * n + t] -= k * a.at(j, t);
+                    r.data[i * n + t] -= k * r.at(j, t);
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    // Целая степень квадратной матрицы; отрицательная - степень обратной
+    pub fn pow(&self, n: f64) -> Result<Self, ErrorKind> {
+        self.square("^")?;
+        if n.fract() != 0. || n.abs() > 1e9 {
+            return Err(ErrorKind::Domain(format!("a matrix power must be an integer, found {n}")))
+        }
+        let (mut b, mut k) = (if n < 0. { self.inv()? } else { self.clone() }, n.abs() as u64);
+        let mut r = Self::identity(self.rows);
+        while k > 0 {
+            if k % 2 == 1 { r = r.mul(&b)?; }
+            b = b.mul(&b)?;
+            k /= 2;
+        }
+        Ok(r)
+    }
+
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        for t in 0..self.cols { self.data.swap(i * self.cols + t, j * self.cols + t); }
+    }
+
+    fn square(&self, op: &str) -> Result<(), ErrorKind> {
+        if self.rows == self.cols { return Ok(()) }
+        Err(ErrorKind::Dimension(format!("'{op}' needs a square matrix, found {}", self.shape())))
+    }
+
+    fn shape(&self) -> String { format!("a {}x{} matrix", self.rows, self.cols) }
+}
+
+// Произведение: матрица на матрицу, матрица на вектор-столбец, вектор-строка на матрицу;
+// с числом - поэлементно
+pub(crate) fn product(x: Value, y: Value) -> Result<Value, ErrorKind> {
+    match (x, y) {
+        (Value::Matrix(a), Value::Matrix(b)) => a.mul(&b).map(Value::Matrix),
+        (Value::Matrix(a), Value::List(v)) => Ok(Value::List(a.mul(&column(v))?.data)),
+        (Value::List(v), Value::Matrix(b)) => Ok(Value::List(column(v).transpose().mul(&b)?.data)),
+        (x, y) => x.zip(y, |a, b| Ok(a * b))
+    }
+}
+
+fn column(v: Vec<f64>) -> Matrix { Matrix { rows: v.len(), cols: 1, data: v } }
+
+// Количество аргументов функции из FUNCS
+pub(crate) fn arity(fname: &str) -> Arity {
+    match fname {
+        "dot" | "cross" => Arity::Exact(2),
+        "norm" => Arity::Between(1, 2),
+        _ => Arity::Exact(1)
+    }
+}
+
+// det(A), inv(A), transpose(A), dot(u, v), cross(u, v), norm(x[, p])
+pub(crate) fn call(fname: &str, args: Vec<Value>) -> Result<Value, ErrorKind> {
+    let expected = arity(fname);
+    if !expected.accepts(args.len()) {
+        return Err(ErrorKind::WrongArity { fname: fname.to_string(), expected, got: args.len() })
+    }
+    let mut args = args.into_iter();
+    let x = args.next().unwrap();
+    match fname {
+        "det" => matrix(x).and_then(|a| a.det()).map(Value::Num),
+        "inv" => matrix(x).and_then(|a| a.inv()).map(Value::Matrix),
+        // список - вектор-строка; транспонированный - матрица из одного столбца
+        "transpose" => match x {
+            Value::List(v) => Ok(Value::Matrix(column(v))),
+            x => matrix(x).map(|a| Value::Matrix(a.transpose()))
+        },
+        "dot" => {
+            let (u, v) = vectors(x, args.next().unwrap())?;
+            Ok(Value::Num(u.iter().zip(&v).map(|(a, b)| a * b).sum()))
+        },
+        "cross" => {
+            let (u, v) = vectors(x, args.next().unwrap())?;
+            if u.len() != 3 {
+                return Err(ErrorKind::Dimension(format!("'cross' needs vectors of length 3, found {}", u.len())))
+            }
+            Ok(Value::List(vec![u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]]))
+        },
+        // евклидова норма вектора, норма Фробениуса матрицы; p - норма по всем элементам
+        _ => {
+            let p = args.next().map_or(Ok(2.), |p| p.num())?;
+            if p.is_nan() || p < 1. {
+                return Err(ErrorKind::Domain(format!("the order of 'norm' must be at least 1, found {p}")))
+            }
+            let xs = x.items()?;
+            if p.is_infinite() { return Ok(Value::Num(xs.iter().fold(0., |m: f64, x| m.max(x.abs())))) }
+            Ok(Value::Num(xs.iter().map(|x| x.abs().powf(p)).sum::<f64>().powf(1. / p)))
+        }
+    }
+}
+
+fn matrix(x: Value) -> Result<Matrix, ErrorKind> {
+    match x {
+        Value::Matrix(a) => Ok(a),
+        x => Err(ErrorKind::Type(format!("expected a matrix, found {}", x.kind())))
+    }
+}
+
+// Два вектора одной длины
+fn vectors(x: Value, y: Value) -> Result<(Vec<f64>, Vec<f64>), ErrorKind> {
+    let vector = |x: Value| match x {
+        Value::List(v) => Ok(v),
+        x => Err(ErrorKind::Type(format!("expected a list, found {}", x.kind())))
+    };
+    let (u, v) = (vector(x)?, vector(y)?);
+    if u.len() != v.len() {
+        return Err(ErrorKind::Dimension(format!("lists of lengths {} and {}", u.len(), v.len())))
+    }
+    Ok((u, v))
+}
+
+impl<T: Display> Display for Matrix<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for i in 0..self.rows {
+            if i > 0 { write!(f, ", ")?; }
+            write!(f, "[")?;
+            for (j, x) in self.row(i).iter().enumerate() {
+                if j > 0 { write!(f, ", ")?; }
+                write!(f, "{x}")?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(xs: &[&[f64]]) -> Matrix<f64> { Matrix::from_rows(xs.iter().map(|r| r.to_vec()).collect()).unwrap() }
+
+    #[test]
+    fn algebra() {
+        let a = m(&[&[1., 2.], &[3., 4.]]);
+        assert_eq!(a.det(), Ok(-2.));
+        assert_eq!(a.mul(&m(&[&[0., 1.], &[1., 0.]])), Ok(m(&[&[2., 1.], &[4., 3.]])));
+        let e = a.mul(&a.inv().unwrap()).unwrap();
+        assert!(e.data.iter().zip(Matrix::identity(2).data).all(|(x, y)| (x - y).abs() < 1e-12));
+        assert_eq!(a.pow(2.), Ok(m(&[&[7., 10.], &[15., 22.]])));
+        assert!(m(&[&[1., 2.], &[2., 4.]]).inv().is_err());
+        assert!(a.mul(&m(&[&[1., 2., 3.]])).is_err());
+    }
+}
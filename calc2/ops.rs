@@ -0,0 +1,205 @@
+// Таблица операций для разбора методом Пратта: символ, приоритет,
+// ассоциативность и вид операции (префиксная, инфиксная, постфиксная)
+use super::expr::{BinOp, Expr, UnOp};
+use super::{CalcError, ErrorKind};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc { Left, Right }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fixity {
+    Prefix,
+    Infix(Assoc),
+    Postfix
+}
+
+// Во что разбирается операция: узел дерева или вызов функции
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpAction {
+    Unary(UnOp),
+    Binary(BinOp),
+    Call(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpDef {
+    pub symbol: String,
+    // чем больше, тем сильнее связывает
+    pub prec: u8,
+    pub fixity: Fixity,
+    pub action: OpAction
+}
+
+impl OpDef {
+    pub fn prefix(symbol: &str, prec: u8, action: OpAction) -> Self {
+        Self { symbol: symbol.to_string(), prec, fixity: Fixity::Prefix, action }
+    }
+
+    pub fn infix(symbol: &str, prec: u8, assoc: Assoc, action: OpAction) -> Self {
+        Self { symbol: symbol.to_string(), prec, fixity: Fixity::Infix(assoc), action }
+    }
+
+    pub fn postfix(symbol: &str, prec: u8, action: OpAction) -> Self {
+        Self { symbol: symbol.to_string(), prec, fixity: Fixity::Postfix, action }
+    }
+
+    // Узел дерева для операции с одним или двумя операндами
+    pub(crate) fn build(&self, mut args: Vec<Expr>, span: Range<usize>) -> Expr {
+        match &self.action {
+            OpAction::Unary(op) => Expr::Unary(*op, Box::new(args.remove(0)), span),
+            OpAction::Binary(op) => {
+                let b = args.pop().unwrap();
+                Expr::Binary(*op, Box::new(args.pop().unwrap()), Box::new(b), span)
+            },
+            OpAction::Call(fname) => Expr::call(fname, args, span)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpTable {
+    ops: Vec<OpDef>,
+    // неявное умножение: 2x, 3(x + 1), (a + b)(a - b)
+    implicit: bool,
+    // имя единицы измерения после числа: 3 km, 2 m^2
+    units: bool
+}
+
+// Стандартные операции: '^' правоассоциативна, унарный минус связывает слабее '^'
+// (-2^2 = -4), постфиксный '!' - факториал; сравнения связывают слабее
+// арифметических и побитовых операций, логические операции - слабее сравнений
+impl Default for OpTable {
+    fn default() -> Self {
+        use OpAction::*;
+        let (l, r) = (Assoc::Left, Assoc::Right);
+        Self { ops: vec![
+            OpDef::infix("or", 1, l, Binary(BinOp::LOr)),
+            OpDef::infix("and", 2, l, Binary(BinOp::LAnd)),
+            OpDef::prefix("not", 3, Unary(UnOp::LNot)),
+            OpDef::infix("<", 4, l, Binary(BinOp::Lt)),
+            OpDef::infix("<=", 4, l, Binary(BinOp::Le)),
+            OpDef::infix(">", 4, l, Binary(BinOp::Gt)),
+            OpDef::infix(">=", 4, l, Binary(BinOp::Ge)),
+            OpDef::infix("==", 4, l, Binary(BinOp::Eq)),
+            OpDef::infix("!=", 4, l, Binary(BinOp::Ne)),
+            OpDef::infix("to", 5, l, Call("to".to_string())),
+            OpDef::infix("|", 6, l, Binary(BinOp::Or)),
+            OpDef::infix("xor", 7, l, Binary(BinOp::Xor)),
+            OpDef::infix("&", 8, l, Binary(BinOp::And)),
+            OpDef::infix("<<", 9, l, Binary(BinOp::Shl)),
+            OpDef::infix(">>", 9, l, Binary(BinOp::Shr)),
+            OpDef::infix("+", 10, l, Binary(BinOp::Add)),
+            OpDef::infix("-", 10, l, Binary(BinOp::Sub)),
+            OpDef::infix("*", 11, l, Binary(BinOp::Mul)),
+            OpDef::infix("/", 11, l, Binary(BinOp::Div)),
+            OpDef::infix("%", 11, l, Binary(BinOp::Mod)),
+            OpDef::prefix("-", 12, Unary(UnOp::Neg)),
+            OpDef::prefix("~", 12, Unary(UnOp::Not)),
+            OpDef::infix("^", 13, r, Binary(BinOp::Pow)),
+            OpDef::postfix("!", 14, Call("fact".to_string()))
+        ], implicit: false, units: false }
+    }
+}
+
+impl OpTable {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn new() -> Self { Self::default() }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn empty() -> Self { Self { ops: vec![], implicit: false, units: false } }
+
+    // Добавляет операцию или заменяет операцию с тем же символом и того же вида.
+    // Символ - слово из букв (как xor) или знаки без букв, цифр, пробелов и ( ) , ; .
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn add(&mut self, def: OpDef) -> Result<(), CalcError> {
+        let word = def.symbol.chars().all(|c| c.is_alphabetic());
+        let sign = def.symbol.chars()
+            .all(|c| !c.is_alphanumeric() && !c.is_whitespace() && !"(),;._".contains(c));
+        if def.symbol.is_empty() || !(word || sign) {
+            return Err(CalcError::new(ErrorKind::BadOperator(def.symbol), 0..0))
+        }
+        self.remove(&def.symbol, def.fixity);
+        self.ops.push(def);
+        Ok(())
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn remove(&mut self, symbol: &str, fixity: Fixity) {
+        self.ops.retain(|d| d.symbol != symbol || !same_kind(d.fixity, fixity));
+    }
+
+    pub fn prefix(&self, symbol: &str) -> Option<&OpDef> { self.find(symbol, Fixity::Prefix) }
+
+    pub fn infix(&self, symbol: &str) -> Option<&OpDef> { self.find(symbol, Fixity::Infix(Assoc::Left)) }
+
+    pub fn postfix(&self, symbol: &str) -> Option<&OpDef> { self.find(symbol, Fixity::Postfix) }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn list(&self) -> &[OpDef] { &self.ops }
+
+    // Включает неявное умножение: операнд, за которым без знака операции следует
+    // имя или '(', умножается на них. Неявное умножение связывает так же, как '*':
+    // 2x^2 = 2*(x^2), -2x = (-2)*x, 1/2x = (1/2)*x, a/b(c) = (a/b)*c
+    pub fn set_implicit_mul(&mut self, on: bool) { self.implicit = on; }
+
+    // Приоритет неявного умножения - приоритет операции '*'; None - оно выключено
+    pub fn implicit_mul(&self) -> Option<u8> {
+        if !self.implicit { return None }
+        self.ops.iter().find(|d| matches!(d.fixity, Fixity::Infix(_)) && d.action == OpAction::Binary(BinOp::Mul))
+            .map(|d| d.prec)
+    }
+
+    // Включает разбор единиц измерения после чисел для режима Mode::Units
+    // (см. Calc::with_mode): 3 km - произведение, связывающее сильнее '*' и '/'
+    pub fn set_units(&mut self, on: bool) { self.units = on; }
+
+    pub fn units(&self) -> bool { self.units }
+
+    fn find(&self, symbol: &str, fixity: Fixity) -> Option<&OpDef> {
+        self.ops.iter().find(|d| d.symbol == symbol && same_kind(d.fixity, fixity))
+    }
+
+    // Символ-знак операции, с которого начинается s; из подходящих берется самый длинный
+    pub(crate) fn match_sign(&self, s: &str) -> Option<&str> {
+        self.ops.iter().map(|d| d.symbol.as_str())
+            .filter(|x| !x.starts_with(char::is_alphabetic) && s.starts_with(x))
+            .max_by_key(|x| x.len())
+    }
+}
+
+fn same_kind(a: Fixity, b: Fixity) -> bool {
+    matches!((a, b), (Fixity::Prefix, Fixity::Prefix) | (Fixity::Infix(_), Fixity::Infix(_))
+        | (Fixity::Postfix, Fixity::Postfix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Calc, Env};
+    use super::*;
+
+    fn calc(s: &str, ops: &OpTable) -> Result<f64, ErrorKind> {
+        Calc::with_ops(s, ops).and_then(|c| c.calc_with(&mut Env::new())).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn custom_operators() {
+        let mut ops = OpTable::new();
+        ops.add(OpDef::infix("**", 13, Assoc::Right, OpAction::Binary(BinOp::Pow))).unwrap();
+        ops.add(OpDef::prefix("sqrt", 12, OpAction::Call("sqr".to_string()))).unwrap();
+        assert_eq!(calc("2 ** 3 ** 2", &ops), Ok(512.));
+        assert_eq!(calc("sqrt 16 + 1", &ops), Ok(5.));
+        ops.remove("**", Fixity::Infix(Assoc::Left));
+        assert!(calc("2 ** 3", &ops).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_symbols() {
+        let mut ops = OpTable::empty();
+        for s in ["", "x1", "a b", "(+"] {
+            let e = ops.add(OpDef::infix(s, 10, Assoc::Left, OpAction::Binary(BinOp::Add))).unwrap_err();
+            assert_eq!(e.kind, ErrorKind::BadOperator(s.to_string()));
+        }
+        assert!(ops.list().is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+// График формулы в терминале: значения формулы в равноотстоящих точках отрезка
+// рисуются символами Брайля (2 x 4 точки в знакоместе) или звездочками,
+// с осями и подписями. Точки можно сохранить в CSV
+use super::expr::Expr;
+use super::{CalcError, Env, ErrorKind, Value};
+
+// Точки знакоместа (столбец, строка) в порядке битов символа Брайля
+const BRAILLE_DOTS: [(usize, usize); 8] = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Style {
+    // символы Брайля и линии Unicode
+    #[default]
+    Braille,
+    // только ASCII: '*', '-', '|', '+'
+    Ascii
+}
+
+// Точки графика (x, y); y - NaN там, где формула не определена
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plot {
+    pub points: Vec<(f64, f64)>
+}
+
+impl Plot {
+    // Значения выражения e в n точках отрезка [from, to] по переменной x
+    pub fn sample(e: &Expr, x: &str, (from, to): (f64, f64), n: usize, env: &Env) -> Result<Self, CalcError> {
+        let span = e.span();
+        if !(from.is_finite() && to.is_finite()) || from >= to || n < 2 {
+            let kind = ErrorKind::Domain(format!("invalid plot range [{from}, {to}]"));
+            return Err(CalcError::new(kind, span))
+        }
+        let mut points = Vec::with_capacity(n);
+        for i in 0..n {
+            let t = from + (to - from) * i as f64 / (n - 1) as f64;
+            let y = e.eval_local(env, &[(x, Value::Num(t))], 0)?;
+            points.push((t, if y.is_finite() { y } else { f64::NAN }));
+        }
+        if points.iter().all(|p| p.1.is_nan()) {
+            let kind = ErrorKind::Domain(format!("the formula is not defined on [{from}, {to}]"));
+            return Err(CalcError::new(kind, span))
+        }
+        Ok(Self { points })
+    }
+
+    // Точки в формате CSV с заголовком x,y; неопределенное значение - пустое поле
+    pub fn to_csv(&self) -> String {
+        let mut s = String::from("x,y\n");
+        for (x, y) in &self.points {
+            if y.is_nan() { s += &format!("{x},\n") } else { s += &format!("{x},{y}\n") }
+        }
+        s
+    }
+
+    // График шириной width и высотой height знакомест без учета подписей
+    pub fn render(&self, width: usize, height: usize, style: Style) -> String {
+        let (width, height) = (width.max(2), height.max(2));
+        // точек в знакоместе по горизонтали и вертикали
+        let (dx, dy) = if style == Style::Braille { (2, 4) } else { (1, 1) };
+        let (cols, rows) = (width * dx, height * dy);
+        let (x0, x1) = (self.points[0].0, self.points[self.points.len() - 1].0);
+        let ys = self.points.iter().map(|p| p.1).filter(|y| !y.is_nan());
+        let (mut lo, mut hi) = ys.fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), y| (a.min(y), b.max(y)));
+        if hi - lo <= f64::EPSILON * hi.abs().max(1.) {
+            let d = (hi.abs() * 0.1).max(1.);
+            (lo, hi) = (lo - d, hi + d);
+        }
+        let col = |x: f64| ((x - x0) / (x1 - x0) * (cols - 1) as f64).round() as i64;
+        let row = |y: f64| ((hi - y) / (hi - lo) * (rows - 1) as f64).round() as i64;
+        // отмеченные точки; соседние значения соединяются отрезком, кроме скачка
+        // через полюс, как у 1/x: смена знака с перепадом больше половины высоты
+        let mut dots = vec![vec![false; cols]; rows];
+        let mut prev: Option<(i64, i64, f64)> = None;
+        for &(x, y) in &self.points {
+            if y.is_nan() {
+                prev = None;
+                continue
+            }
+            let (c, r) = (col(x), row(y));
+            let (c0, r0) = match prev {
+                Some((c0, r0, y0)) if y0.signum() == y.signum() || (r - r0).abs() <= rows as i64 / 2 => (c0, r0),
+                _ => (c, r)
+            };
+            let steps = (c - c0).abs().max((r - r0).abs()).max(1);
+            for i in 0..=steps {
+                let cc = c0 + (c - c0) * i / steps;
+                let rr = r0 + (r - r0) * i / steps;
+                dots[rr as usize][cc as usize] = true;
+            }
+            prev = Some((c, r, y));
+        }
+        // оси в знакоместах, если ноль попадает в диапазон
+        let axis_row = (lo <= 0. && 0. <= hi).then(|| row(0.) as usize / dy);
+        let axis_col = (x0 <= 0. && 0. <= x1).then(|| col(0.) as usize / dx);
+        let (h_line, v_line, cross, tick, corner) = match style {
+            Style::Braille => ('─', '│', '┼', '┤', '└'),
+            Style::Ascii => ('-', '|', '+', '+', '+')
+        };
+        let labels: Vec<String> = (0..height).map(|r| match r {
+            0 => label(hi),
+            r if r == height - 1 => label(lo),
+            r if Some(r) == axis_row => "0".to_string(),
+            _ => String::new()
+        }).collect();
+        let margin = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let mut lines = vec![];
+        for (r, l) in labels.iter().enumerate() {
+            let mut line = format!("{l:>margin$} {}", if l.is_empty() { v_line } else { tick });
+            for c in 0..width {
+                let mut bits = 0u32;
+                for (i, (x, y)) in BRAILLE_DOTS.into_iter().enumerate() {
+                    if x < dx && y < dy && dots[r * dy + y][c * dx + x] { bits |= 1 << i; }
+                }
+                line.push(match (bits, Some(r) == axis_row, Some(c) == axis_col) {
+                    (0, true, true) => cross,
+                    (0, true, false) => h_line,
+                    (0, false, true) => v_line,
+                    (0, false, false) => ' ',
+                    _ if style == Style::Ascii => '*',
+                    (bits, ..) => char::from_u32(0x2800 + bits).unwrap_or('*')
+                });
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        lines.push(format!("{} {corner}{}", " ".repeat(margin), h_line.to_string().repeat(width)));
+        let (from, to) = (label(x0), label(x1));
+        let gap = (width + 1).saturating_sub(from.chars().count() + to.chars().count()).max(1);
+        lines.push(format!("{} {from}{}{to}", " ".repeat(margin), " ".repeat(gap)));
+        lines.join("\n")
+    }
+}
+
+// Подпись оси: не больше четырех знаков после точки, очень большие и малые числа - с порядком
+fn label(x: f64) -> String {
+    if x == 0. { return "0".to_string() }
+    if x.abs() >= 1e5 || x.abs() < 1e-3 { return format!("{x:.2e}") }
+    let s = format!("{x:.4}");
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    fn sample(s: &str, (from, to): (f64, f64), n: usize) -> Result<Plot, ErrorKind> {
+        Plot::sample(&parse(s).unwrap(), "x", (from, to), n, &Env::new()).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn samples_and_csv() {
+        let p = sample("x^2", (0., 1.), 3).unwrap();
+        assert_eq!(p.points, [(0., 0.), (0.5, 0.25), (1., 1.)]);
+        assert_eq!(p.to_csv(), "x,y\n0,0\n0.5,0.25\n1,1\n");
+        // полюс 1/x - неопределенное значение
+        let p = sample("1/x", (-1., 1.), 3).unwrap();
+        assert!(p.points[1].1.is_nan());
+        assert_eq!(p.to_csv(), "x,y\n-1,-1\n0,\n1,1\n");
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert!(matches!(sample("x", (1., 0.), 10), Err(ErrorKind::Domain(_))));
+        assert!(matches!(sample("x", (0., f64::INFINITY), 10), Err(ErrorKind::Domain(_))));
+        assert!(matches!(sample("sqr(x)", (-2., -1.), 10), Err(ErrorKind::Domain(_))));
+        assert_eq!(sample("x + y", (0., 1.), 10), Err(ErrorKind::UnknownVariable("y".to_string())));
+    }
+
+    #[test]
+    fn renders_axes_and_labels() {
+        let p = sample("x", (-1., 1.), 5).unwrap();
+        assert_eq!(p.render(5, 3, Style::Ascii), " 1 +  | *\n 0 +--**-\n-1 +**|\n   +-----\n   -1   1");
+        let braille = p.render(5, 3, Style::Braille);
+        assert_eq!(braille.lines().count(), 5);
+        assert!(braille.starts_with(" 1 ┤") && braille.contains("└─────"));
+        assert!(braille.chars().any(|c| ('\u{2801}'..='\u{28ff}').contains(&c)));
+    }
+}
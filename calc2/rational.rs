@@ -0,0 +1,160 @@
+// Точные рациональные числа: несократимая дробь со знаменателем > 0
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use super::bigint::BigInt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt
+}
+
+impl Rational {
+    // None, если знаменатель равен нулю
+    pub fn new(num: BigInt, den: BigInt) -> Option<Self> {
+        if den.is_zero() { return None }
+        let g = num.gcd(&den);
+        let (mut num, mut den) = if g.is_zero() { (num, den) } else { (num.divrem(&g).0, den.divrem(&g).0) };
+        if den.is_neg() { (num, den) = (num.neg(), den.neg()); }
+        Some(Self { num, den })
+    }
+
+    pub fn from_int(n: BigInt) -> Self { Self { num: n, den: BigInt::from_u64(1) } }
+
+    // Десятичная запись вида [-]123.456 с необязательным показателем: 6.02e23, 1E-3
+    pub fn parse(s: &str) -> Option<Self> {
+        let (s, exp) = match s.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i32>().ok()?),
+            None => (s, 0)
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let num = BigInt::parse(&format!("{int}{frac}"))?;
+        let x = Self::new(num, BigInt::pow10(frac.len()))?;
+        let p = Self::from_int(BigInt::pow10(exp.unsigned_abs() as usize));
+        if exp < 0 { x.div(&p) } else { Some(x.mul(&p)) }
+    }
+
+    // Кратчайшая десятичная запись f64 восстанавливает введенное число
+    // до 15 значащих цифр, 0.1 -> 1/10; более длинные литералы разбираются по записи
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() { return None }
+        Self::parse(&format!("{x}"))
+    }
+
+    pub fn num(&self) -> &BigInt { &self.num }
+
+    pub fn den(&self) -> &BigInt { &self.den }
+
+    pub fn is_integer(&self) -> bool { self.den == BigInt::from_u64(1) }
+
+    pub fn is_neg(&self) -> bool { self.num.is_neg() }
+
+    pub fn to_f64(&self) -> f64 {
+        let (n, d) = (self.num.to_f64(), self.den.to_f64());
+        if n.is_finite() && d.is_finite() { return n / d }
+        // для очень больших чисел делим с сохранением 20 значащих цифр
+        let k = self.den.to_string().len().saturating_sub(20);
+        let q = self.num.divrem(&self.den.divrem(&BigInt::pow10(k)).0).0;
+        q.to_f64() / 10f64.powi(k as i32)
+    }
+
+    pub fn neg(&self) -> Self { Self { num: self.num.neg(), den: self.den.clone() } }
+
+    pub fn add(&self, y: &Self) -> Self {
+        Self::new(self.num.mul(&y.den).add(&y.num.mul(&self.den)), self.den.mul(&y.den)).unwrap()
+    }
+
+    pub fn sub(&self, y: &Self) -> Self { self.add(&y.neg()) }
+
+    pub fn mul(&self, y: &Self) -> Self {
+        Self::new(self.num.mul(&y.num), self.den.mul(&y.den)).unwrap()
+    }
+
+    // None при делении на ноль
+    pub fn div(&self, y: &Self) -> Option<Self> {
+        Self::new(self.num.mul(&y.den), self.den.mul(&y.num))
+    }
+
+    // Целая степень; None при возведении нуля в отрицательную степень
+    pub fn powi(&self, n: i64) -> Option<Self> {
+        let k = u32::try_from(n.unsigned_abs()).ok()?;
+        let r = Self { num: self.num.pow(k), den: self.den.pow(k) };
+        if n < 0 { Self::from_int(BigInt::from_u64(1)).div(&r) } else { Some(r) }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, y: &Self) -> Ordering { self.num.mul(&y.den).cmp(&y.num.mul(&self.den)) }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, y: &Self) -> Option<Ordering> { Some(self.cmp(y)) }
+}
+
+// Конечная десятичная дробь печатается как 0.3, остальные - как 1/3
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_integer() { return write!(f, "{}", self.num) }
+        // знаменатель вида 2^a * 5^b: дробь конечна, знаков после точки max(a, b)
+        let mut d = self.den.clone();
+        let mut k = 0;
+        for p in [2u64, 5] {
+            let p = BigInt::from_u64(p);
+            let mut n = 0;
+            loop {
+                let (q, r) = d.divrem(&p);
+                if !r.is_zero() { break }
+                d = q;
+                n += 1;
+            }
+            k = k.max(n);
+        }
+        if d != BigInt::from_u64(1) { return write!(f, "{}/{}", self.num, self.den) }
+        let digits = self.num.abs().mul(&BigInt::pow10(k)).divrem(&self.den).0.to_string();
+        let digits = format!("{digits:0>width$}", width = k + 1);
+        let (int, frac) = digits.split_at(digits.len() - k);
+        write!(f, "{}{int}.{frac}", if self.is_neg() { "-" } else { "" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Calc, Env, Expr, Mode};
+    use super::*;
+
+    fn calc(s: &str, mode: Mode) -> String {
+        Calc::new(s).and_then(|c| c.calc_mode(mode, &mut Env::new())).unwrap()
+    }
+
+    #[test]
+    fn parses_decimal_notation() {
+        let r = |n: i64, d: i64| Rational::new(BigInt::from_i64(n), BigInt::from_i64(d));
+        assert_eq!(Rational::parse("-1.25"), r(-5, 4));
+        assert_eq!(Rational::parse(".5"), r(1, 2));
+        assert_eq!(Rational::parse("1.5e3"), r(1500, 1));
+        assert_eq!(Rational::parse("25E-2"), r(1, 4));
+        assert_eq!(Rational::parse("1e+2"), r(100, 1));
+        assert_eq!(Rational::parse("1e"), None);
+        assert_eq!(Rational::from_f64(0.1), r(1, 10));
+    }
+
+    #[test]
+    fn long_literals_are_exact() {
+        assert_eq!(calc("12345678901234567890123", Mode::Rational), "12345678901234567890123");
+        assert_eq!(calc("12345678901234567890123 + 1", Mode::Rational), "12345678901234567890124");
+        assert_eq!(calc("18446744073709551615", Mode::Rational), "18446744073709551615");
+        assert_eq!(calc("0.1234567890123456789 * 10", Mode::Rational), "1.234567890123456789");
+        assert_eq!(calc("1.2345678901234567890e3", Mode::Rational), "1234.567890123456789");
+        assert_eq!(calc("0.1234567890123456789012345", Mode::Decimal(30)), "0.1234567890123456789012345");
+        assert_eq!(calc("99999999999999999999 + 1", Mode::Decimal(2)), "100000000000000000000");
+    }
+
+    #[test]
+    fn long_literals_print_as_written() {
+        let e = parse("12345678901234567890123 * x").unwrap();
+        assert_eq!(e.to_string(), "12345678901234567890123*x");
+        assert_eq!(parse(&e.to_string()).unwrap().to_string(), e.to_string());
+        // короткая запись восстанавливается из f64
+        assert_eq!(parse("0.1"), Ok(Expr::num(0.1)));
+    }
+}
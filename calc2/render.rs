@@ -0,0 +1,484 @@
+// Печать формулы в нотациях LaTeX и MathML и псевдографикой в несколько строк.
+// Скобки ставятся по приоритетам операций, как при печати строкой (Display);
+// дробь, корень и показатель степени группируют операнды сами и скобок не требуют
+use std::fmt::{Display, Formatter};
+use super::eval::is_series;
+use super::expr::{BinOp, Expr, UnOp};
+
+// Греческие буквы: имя переменной и символ
+const GREEK: [(&str, char); 24] = [
+    ("alpha", 'α'), ("beta", 'β'), ("gamma", 'γ'), ("delta", 'δ'), ("epsilon", 'ε'), ("zeta", 'ζ'),
+    ("eta", 'η'), ("theta", 'θ'), ("iota", 'ι'), ("kappa", 'κ'), ("lambda", 'λ'), ("mu", 'μ'),
+    ("nu", 'ν'), ("xi", 'ξ'), ("pi", 'π'), ("rho", 'ρ'), ("sigma", 'σ'), ("tau", 'τ'),
+    ("phi", 'φ'), ("chi", 'χ'), ("psi", 'ψ'), ("omega", 'ω'), ("Gamma", 'Γ'), ("Omega", 'Ω')
+];
+
+// Функции, для которых в LaTeX есть свои команды; asin, acos, atan - \arcsin, \arccos, \arctan
+const LATEX_FUNCS: [&str; 14] = [
+    "sin", "cos", "tan", "sinh", "cosh", "tanh", "exp", "ln", "log", "min", "max", "arg", "gcd", "det"
+];
+
+impl Expr {
+    pub fn to_latex(&self) -> String { render(&Latex, self) }
+
+    pub fn to_mathml(&self) -> String {
+        format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>", render(&MathMl, self))
+    }
+
+    // Дроби и степени в несколько строк
+    pub fn to_pretty(&self) -> String { render(&Pretty, self).to_string() }
+}
+
+// Нотация: из каких частей складывается изображение формулы
+trait Notation {
+    type Out;
+    fn num(&self, x: &str) -> Self::Out;
+    fn name(&self, name: &str) -> Self::Out;
+    fn unop(&self, op: UnOp) -> Self::Out;
+    fn binop(&self, op: BinOp) -> Self::Out;
+    // знак препинания: запятая, '!', '=', '|', скобки списка, 'd' перед переменной интегрирования
+    fn sym(&self, s: &str) -> Self::Out;
+    fn func(&self, fname: &str) -> Self::Out;
+    fn row(&self, xs: Vec<Self::Out>) -> Self::Out;
+    fn parens(&self, x: Self::Out) -> Self::Out;
+    fn frac(&self, a: Self::Out, b: Self::Out) -> Self::Out;
+    fn sup(&self, a: Self::Out, b: Self::Out) -> Self::Out;
+    fn sub(&self, a: Self::Out, b: Self::Out) -> Self::Out;
+    fn sqrt(&self, a: Self::Out) -> Self::Out;
+    fn abs(&self, a: Self::Out) -> Self::Out;
+    // знак суммы, произведения или интеграла (op - имя функции) с пределами
+    fn big(&self, op: &str, lower: Self::Out, upper: Self::Out) -> Self::Out;
+}
+
+fn render<N: Notation>(n: &N, e: &Expr) -> N::Out {
+    match e {
+        // отрицательное число печатается как унарный минус
+        Expr::Num(x) if x.is_sign_negative() => n.row(vec![n.unop(UnOp::Neg), n.num(&(-x).to_string())]),
+        Expr::Num(x) => n.num(&x.to_string()),
+        Expr::Int(k) => n.num(&k.to_string()),
+        Expr::Dec(_, s) => n.num(s),
+        Expr::Var { name, .. } => n.name(name),
+        Expr::Unary(op, a, _) => n.row(vec![n.unop(*op), operand(n, a, e.operand_prec().0)]),
+        Expr::Binary(BinOp::Div, a, b, _) => n.frac(render(n, a), render(n, b)),
+        Expr::Binary(BinOp::Pow, a, b, _) => power(n, a, b),
+        Expr::Binary(op, a, b, _) => {
+            let (left, right) = e.operand_prec();
+            n.row(vec![operand(n, a, left), n.binop(*op), operand(n, b, right)])
+        },
+        Expr::Call { fname, args, .. } => call(n, fname, args),
+        Expr::List(xs, _) => n.row(vec![n.sym("["), items(n, xs), n.sym("]")]),
+        // элемент списка - нижний индекс
+        Expr::Index(a, i, _) => n.sub(operand(n, a, 14), render(n, i))
+    }
+}
+
+// Выражения через запятую
+fn items<N: Notation>(n: &N, xs: &[Expr]) -> N::Out {
+    let mut r = vec![];
+    for (i, x) in xs.iter().enumerate() {
+        if i > 0 { r.push(n.sym(",")); }
+        r.push(render(n, x));
+    }
+    n.row(r)
+}
+
+// Операнд в скобках, если он связывает слабее min; дробь - цельный операнд
+fn operand<N: Notation>(n: &N, a: &Expr, min: u8) -> N::Out {
+    let p = if matches!(a, Expr::Binary(BinOp::Div, ..)) { 14 } else { a.prec() };
+    if p < min { n.parens(render(n, a)) } else { render(n, a) }
+}
+
+// Основание степени в скобках, если это не число, имя или вызов функции
+fn power<N: Notation>(n: &N, a: &Expr, b: &Expr) -> N::Out {
+    let base = if a.prec() < 14 { n.parens(render(n, a)) } else { render(n, a) };
+    n.sup(base, render(n, b))
+}
+
+fn call<N: Notation>(n: &N, fname: &str, args: &[Expr]) -> N::Out {
+    match (fname, args) {
+        ("sqr", [a]) => n.sqrt(render(n, a)),
+        ("abs", [a]) => n.abs(render(n, a)),
+        ("fact", [a]) => n.row(vec![operand(n, a, 14), n.sym("!")]),
+        ("pow", [a, b]) => power(n, a, b),
+        ("pi" | "e", []) => n.name(fname),
+        // sum(f, k, a, b) - сумма f по k от a до b, integrate(f, x, a, b) - интеграл f dx
+        ("sum" | "prod", [f, k @ Expr::Var { .. }, a, b]) if is_series(fname, args) => {
+            let lower = n.row(vec![render(n, k), n.sym("="), render(n, a)]);
+            n.row(vec![n.big(fname, lower, render(n, b)), operand(n, f, 11)])
+        },
+        ("integrate", [f, x @ Expr::Var { .. }, a, b]) =>
+            n.row(vec![n.big(fname, render(n, a), render(n, b)), operand(n, f, 11), n.sym("d"), render(n, x)]),
+        // diff(f, x) - d/dx (f), diff(f, x, a) - значение производной в точке a
+        ("diff", [f, Expr::Var { name, .. }, at @ ..]) if at.len() <= 1 => {
+            let dx = n.row(vec![n.name("d"), n.name(name)]);
+            let d = n.row(vec![n.frac(n.name("d"), dx), n.parens(render(n, f))]);
+            match at {
+                [a] => n.sub(n.row(vec![d, n.sym("|")]), n.row(vec![n.name(name), n.sym("="), render(n, a)])),
+                _ => d
+            }
+        },
+        _ => n.row(vec![n.func(fname), n.parens(items(n, args))])
+    }
+}
+
+fn greek(name: &str) -> Option<char> { GREEK.iter().find(|(g, _)| *g == name).map(|(_, c)| *c) }
+
+struct Latex;
+
+impl Notation for Latex {
+    type Out = String;
+
+    fn num(&self, x: &str) -> String {
+        match x {
+            "inf" => "\\infty".to_string(),
+            "NaN" => "\\mathrm{NaN}".to_string(),
+            x => x.to_string()
+        }
+    }
+
+    // x_1 - имя с индексом; длинные имена прямым шрифтом
+    fn name(&self, name: &str) -> String {
+        if greek(name).is_some() { return format!("\\{name}") }
+        match name.split_once('_') {
+            Some((a, i)) if !a.is_empty() && !i.is_empty() => format!("{}_{{{}}}", self.name(a), self.name(i)),
+            _ if name.chars().count() == 1 || name.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+            _ => format!("\\mathrm{{{name}}}")
+        }
+    }
+
+    fn unop(&self, op: UnOp) -> String {
+        match op {
+            UnOp::Neg => "-",
+            UnOp::Not => "\\sim ",
+            UnOp::LNot => "\\lnot "
+        }.to_string()
+    }
+
+    fn binop(&self, op: BinOp) -> String {
+        let s = match op {
+            BinOp::Mul => "\\cdot",
+            BinOp::Mod => "\\bmod",
+            BinOp::And => "\\mathbin{\\&}",
+            BinOp::Or => "\\mathbin{|}",
+            BinOp::Xor => "\\oplus",
+            BinOp::Shl => "\\ll",
+            BinOp::Shr => "\\gg",
+            BinOp::Le => "\\le",
+            BinOp::Ge => "\\ge",
+            BinOp::Eq => "=",
+            BinOp::Ne => "\\ne",
+            BinOp::LAnd => "\\land",
+            BinOp::LOr => "\\lor",
+            op => op.symbol()
+        };
+        format!(" {s} ")
+    }
+
+    fn sym(&self, s: &str) -> String {
+        match s {
+            "," => ", ",
+            "|" => "\\Big|",
+            "d" => "\\,d",
+            s => s
+        }.to_string()
+    }
+
+    fn func(&self, fname: &str) -> String {
+        if LATEX_FUNCS.contains(&fname) { return format!("\\{fname}") }
+        if let Some(f @ ("sin" | "cos" | "tan")) = fname.strip_prefix('a') { return format!("\\arc{f}") }
+        if fname.chars().count() == 1 { fname.to_string() } else { format!("\\operatorname{{{fname}}}") }
+    }
+
+    fn row(&self, xs: Vec<String>) -> String { xs.concat() }
+
+    fn parens(&self, x: String) -> String { format!("\\left({x}\\right)") }
+
+    fn frac(&self, a: String, b: String) -> String { format!("\\frac{{{a}}}{{{b}}}") }
+
+    fn sup(&self, a: String, b: String) -> String { format!("{{{a}}}^{{{b}}}") }
+
+    fn sub(&self, a: String, b: String) -> String { format!("{{{a}}}_{{{b}}}") }
+
+    fn sqrt(&self, a: String) -> String { format!("\\sqrt{{{a}}}") }
+
+    fn abs(&self, a: String) -> String { format!("\\left|{a}\\right|") }
+
+    fn big(&self, op: &str, lower: String, upper: String) -> String {
+        let sym = match op {
+            "sum" => "\\sum",
+            "prod" => "\\prod",
+            _ => "\\int"
+        };
+        format!("{sym}_{{{lower}}}^{{{upper}}} ")
+    }
+}
+
+struct MathMl;
+
+// Символы, которые в XML нужно заменять ссылками
+fn escape(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;") }
+
+impl Notation for MathMl {
+    type Out = String;
+
+    fn num(&self, x: &str) -> String {
+        match x {
+            "inf" => "<mi>\u{221e}</mi>".to_string(),
+            "NaN" => "<mi>NaN</mi>".to_string(),
+            x => format!("<mn>{x}</mn>")
+        }
+    }
+
+    fn name(&self, name: &str) -> String {
+        if let Some(c) = greek(name) { return format!("<mi>{c}</mi>") }
+        match name.split_once('_') {
+            Some((a, i)) if !a.is_empty() && !i.is_empty() => format!("<msub>{}{}</msub>", self.name(a), self.name(i)),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => format!("<mn>{name}</mn>"),
+            _ => format!("<mi>{}</mi>", escape(name))
+        }
+    }
+
+    fn unop(&self, op: UnOp) -> String {
+        format!("<mo>{}</mo>", match op {
+            UnOp::Neg => "\u{2212}",
+            UnOp::Not => "~",
+            UnOp::LNot => "\u{ac}"
+        })
+    }
+
+    fn binop(&self, op: BinOp) -> String {
+        format!("<mo>{}</mo>", match op {
+            BinOp::Sub => "\u{2212}",
+            BinOp::Mul => "\u{22c5}",
+            BinOp::Mod => "mod",
+            BinOp::Xor => "\u{2295}",
+            BinOp::Shl => "\u{226a}",
+            BinOp::Shr => "\u{226b}",
+            BinOp::Le => "\u{2264}",
+            BinOp::Ge => "\u{2265}",
+            BinOp::Eq => "=",
+            BinOp::Ne => "\u{2260}",
+            BinOp::LAnd => "\u{2227}",
+            BinOp::LOr => "\u{2228}",
+            op => return format!("<mo>{}</mo>", escape(op.symbol()))
+        })
+    }
+
+    fn sym(&self, s: &str) -> String { format!("<mo>{}</mo>", escape(s)) }
+
+    // имя функции и знак применения функции
+    fn func(&self, fname: &str) -> String { format!("<mi>{}</mi><mo>\u{2061}</mo>", escape(fname)) }
+
+    fn row(&self, xs: Vec<String>) -> String { format!("<mrow>{}</mrow>", xs.concat()) }
+
+    fn parens(&self, x: String) -> String { format!("<mrow><mo>(</mo>{x}<mo>)</mo></mrow>") }
+
+    fn frac(&self, a: String, b: String) -> String { format!("<mfrac>{a}{b}</mfrac>") }
+
+    fn sup(&self, a: String, b: String) -> String { format!("<msup>{a}{b}</msup>") }
+
+    fn sub(&self, a: String, b: String) -> String { format!("<msub>{a}{b}</msub>") }
+
+    fn sqrt(&self, a: String) -> String { format!("<msqrt>{a}</msqrt>") }
+
+    fn abs(&self, a: String) -> String { format!("<mrow><mo>|</mo>{a}<mo>|</mo></mrow>") }
+
+    fn big(&self, op: &str, lower: String, upper: String) -> String {
+        match op {
+            "sum" => format!("<munderover><mo>\u{2211}</mo>{lower}{upper}</munderover>"),
+            "prod" => format!("<munderover><mo>\u{220f}</mo>{lower}{upper}</munderover>"),
+            _ => format!("<msubsup><mo>\u{222b}</mo>{lower}{upper}</msubsup>")
+        }
+    }
+}
+
+// Прямоугольник псевдографики: строки одной ширины,
+// base - номер строки, на которой стоят знаки операций
+#[derive(Debug, Clone)]
+struct Block {
+    lines: Vec<String>,
+    width: usize,
+    base: usize
+}
+
+impl Block {
+    // Строки дополняются пробелами до общей ширины
+    fn new(lines: Vec<String>, base: usize) -> Self {
+        let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let lines = lines.into_iter().map(|l| {
+            let pad = width - l.chars().count();
+            l + &" ".repeat(pad)
+        }).collect();
+        Self { lines, width, base }
+    }
+
+    fn text(s: &str) -> Self { Self::new(vec![s.to_string()], 0) }
+
+    fn height(&self) -> usize { self.lines.len() }
+
+    // Строка k блока, выровненного по базовой строке с above строками над ней
+    fn line(&self, k: usize, above: usize) -> String {
+        match (k + self.base).checked_sub(above).and_then(|i| self.lines.get(i)) {
+            Some(l) => l.clone(),
+            None => " ".repeat(self.width)
+        }
+    }
+
+    // Строки, выровненные по центру ширины width
+    fn centered(&self, width: usize) -> Vec<String> {
+        let left = (width - self.width) / 2;
+        self.lines.iter().map(|l| format!("{}{l}{}", " ".repeat(left), " ".repeat(width - self.width - left))).collect()
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<&str> = self.lines.iter().map(|l| l.trim_end()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+struct Pretty;
+
+impl Notation for Pretty {
+    type Out = Block;
+
+    fn num(&self, x: &str) -> Block { Block::text(x) }
+
+    fn name(&self, name: &str) -> Block { Block::text(name) }
+
+    fn unop(&self, op: UnOp) -> Block { Block::text(op.symbol()) }
+
+    fn binop(&self, op: BinOp) -> Block {
+        match op {
+            BinOp::Mul | BinOp::Div | BinOp::Pow => Block::text(op.symbol()),
+            op => Block::text(&format!(" {} ", op.symbol()))
+        }
+    }
+
+    fn sym(&self, s: &str) -> Block {
+        Block::text(match s {
+            "," => ", ",
+            "d" => " d",
+            s => s
+        })
+    }
+
+    fn func(&self, fname: &str) -> Block { Block::text(fname) }
+
+    // Блоки рядом, базовые строки на одном уровне
+    fn row(&self, xs: Vec<Block>) -> Block {
+        let above = xs.iter().map(|x| x.base).max().unwrap_or(0);
+        let below = xs.iter().map(|x| x.height() - x.base - 1).max().unwrap_or(0);
+        let lines = (0..above + below + 1).map(|k| xs.iter().map(|x| x.line(k, above)).collect()).collect();
+        Block::new(lines, above)
+    }
+
+    fn parens(&self, x: Block) -> Block {
+        if x.height() == 1 { return self.row(vec![Block::text("("), x, Block::text(")")]) }
+        let (h, base) = (x.height(), x.base);
+        // высокие скобки: / | \ слева и \ | / справа
+        let side = |top: &str, bottom: &str| Block::new((0..h).map(|k| match k {
+            0 => top,
+            k if k == h - 1 => bottom,
+            _ => "|"
+        }.to_string()).collect(), base);
+        self.row(vec![side("/", "\\"), x, side("\\", "/")])
+    }
+
+    fn frac(&self, a: Block, b: Block) -> Block {
+        let width = a.width.max(b.width) + 2;
+        let mut lines = a.centered(width);
+        lines.push("-".repeat(width));
+        lines.extend(b.centered(width));
+        Block::new(lines, a.height())
+    }
+
+    // Показатель над строкой основания справа
+    fn sup(&self, a: Block, b: Block) -> Block {
+        let mut lines: Vec<String> = b.lines.iter().map(|l| " ".repeat(a.width) + l).collect();
+        lines.extend(a.lines.iter().cloned());
+        Block::new(lines, b.height() + a.base)
+    }
+
+    // Индекс под строкой основания справа
+    fn sub(&self, a: Block, b: Block) -> Block {
+        let mut lines = a.lines.clone();
+        lines.extend(b.lines.iter().map(|l| " ".repeat(a.width) + l));
+        Block::new(lines, a.base)
+    }
+
+    fn sqrt(&self, a: Block) -> Block {
+        let mut lines = vec![format!("  {}", "_".repeat(a.width))];
+        let last = a.height() - 1;
+        lines.extend(a.lines.iter().enumerate().map(|(k, l)| format!("{}{l}", if k == last { "\\/" } else { " |" })));
+        Block::new(lines, a.base + 1)
+    }
+
+    fn abs(&self, a: Block) -> Block {
+        let bar = Block::new(vec!["|".to_string(); a.height()], a.base);
+        self.row(vec![bar.clone(), a, bar])
+    }
+
+    // Верхний предел над знаком, нижний под ним; базовая строка - середина знака
+    fn big(&self, op: &str, lower: Block, upper: Block) -> Block {
+        let sym = match op {
+            "sum" => vec!["___", "\\", "/__"],
+            "prod" => vec!["____", "|  |", "|  |"],
+            _ => vec!["  /", " | ", "/  "]
+        };
+        let sym = Block::new(sym.into_iter().map(String::from).collect(), 1);
+        let width = upper.width.max(sym.width).max(lower.width);
+        let mut lines = upper.centered(width);
+        lines.extend(sym.centered(width));
+        lines.extend(lower.centered(width));
+        // пробел между знаком и выражением под ним
+        Block::new(lines.into_iter().map(|l| l + " ").collect(), upper.height() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+
+    fn latex(s: &str) -> String { parse(s).unwrap().to_latex() }
+
+    fn mathml(s: &str) -> String { parse(s).unwrap().to_mathml() }
+
+    fn pretty(s: &str) -> String { parse(s).unwrap().to_pretty() }
+
+    #[test]
+    fn latex_groups_by_precedence() {
+        assert_eq!(latex("1/(x + 1) - 2"), "\\frac{1}{x + 1} - 2");
+        assert_eq!(latex("(a + b)^(n - 1)"), "{\\left(a + b\\right)}^{n - 1}");
+        assert_eq!(latex("sqr(x)^2"), "{\\sqrt{x}}^{2}");
+        assert_eq!(latex("-x^2 + alpha"), "-{x}^{2} + \\alpha");
+        assert_eq!(latex("sin(x)*x < 1"), "\\sin\\left(x\\right) \\cdot x < 1");
+        assert_eq!(latex("a < b and not c"), "a < b \\land \\lnot c");
+    }
+
+    #[test]
+    fn mathml_escapes_symbols() {
+        let m = mathml("a/(b/c)");
+        assert!(m.starts_with("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">"));
+        assert!(m.contains("<mfrac><mi>a</mi><mfrac><mi>b</mi><mi>c</mi></mfrac></mfrac>"));
+        assert!(mathml("x < 1").contains("<mo>&lt;</mo>"));
+        assert!(mathml("sqr(x + 1)").contains("<msqrt><mrow><mi>x</mi><mo>+</mo><mn>1</mn></mrow></msqrt>"));
+        assert!(mathml("alpha^2").contains("<msup><mi>α</mi><mn>2</mn></msup>"));
+    }
+
+    #[test]
+    fn pretty_draws_fractions_powers_and_roots() {
+        assert_eq!(pretty("1/(x + 1) - 2"), "   1\n------- - 2\n x + 1");
+        assert_eq!(pretty("(a + b)^(n - 1)"), "       n - 1\n(a + b)");
+        assert_eq!(pretty("sqr(x + 1)"), "  _____\n\\/x + 1");
+        assert_eq!(pretty("a < b and not c"), "a < b and not c");
+    }
+
+    #[test]
+    fn series_follow_the_syntax() {
+        assert_eq!(latex("sum(2, k, 1, 10)"), "\\sum_{k=1}^{10} 2");
+        assert!(latex("prod(k, k, 1, n)").starts_with("\\prod_{k=1}^{n}"));
+        assert!(!latex("sum(1, 2, 3, 4)").contains("\\sum"));
+    }
+}
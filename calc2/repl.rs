@@ -0,0 +1,409 @@
+// Интерактивный вычислитель: формулы вводятся построчно, переменные
+// и функции сохраняются между строками, ans - результат предыдущей формулы.
+// Если стандартный ввод не терминал, формулы читаются из него по одной в строке
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use super::diagnostics::{check_with, report, report_syntax};
+use super::editor::Editor;
+use super::funcs::FORMS;
+use super::{parse_with, Angle, Calc, CalcError, Env, Expr, Mode, OpTable, Plot, Stmt, Style};
+
+const HELP: &str = "\
+Formulas:
+  2 + 3*4, 2^10, 5!, 7 % 3      arithmetic; ^ is right-associative
+  x = 2; f(t) = t^2 + 1         variables and functions, ';' separates statements
+  x > 1 and not x == 2          comparisons and booleans: true, false
+  if(x > 0, 1/x, 0)             only the chosen branch is evaluated
+  diff(x^3, x, 2)               derivative, optionally at a point;
+                                without a point and a value of x - the formula 3*x^2
+  solve(x^2 == 2, x, 1)         root near 1; solve(f, x, a, b) - root in [a, b]
+  integrate(x^2, x, 0, 1)       definite integral
+  sum(k^2, k, 1, 10)            sum and product over k = 1, 2, ..., 10; also prod
+  xs = [3, 1, 2]; xs[0], xs[-1] lists, indexed from 0 and from the end
+  xs*2 + [1, 2, 3], sin(xs)     element-wise arithmetic and functions
+  range(1, 10, 2)               the list 1, 3, ..., 9; the step defaults to 1
+  sum(xs), count, median, mode, variance, stddev, percentile(xs, 90)   statistics
+  A = [[1, 2], [3, 4]]; inv(A)*[5, 6]   matrices: A*B, A^n, det, inv, transpose
+  dot(u, v), cross(u, v), norm(v)       vectors; norm(A) - the Frobenius norm
+  asin, atan2(y, x), sinh, abs, sign, floor, ceil, round, trunc, gamma, gcd, lcm
+  log(x, b)                     logarithm to the base b; :funcs lists all functions
+  ans                           the result of the previous formula
+  2x, 3(x + 1), (a + b)(a - b)  implicit multiplication after :implicit on; binds like '*'
+  An unclosed '(', '[' or a trailing '\\' continues the formula on the next line;
+  in input from a file or a pipe only a trailing '\\' does.
+Commands:
+  :vars    variables          :funcs   functions
+  :help    this text          :quit    exit
+  :angle deg, :angle rad, :angle grad   degrees, radians or gradians in trigonometry
+  :implicit on, :implicit off   implicit multiplication; 1/2x is (1/2)*x with a warning
+  :mode float, rational, decimal 50, complex, units   number mode: exact fractions, decimals
+                            with 50 digits, complex numbers with i, quantities: 3 km / 20 min to mph
+  :mode int 32 unsigned checked hex   integers: 8/16/32/64 bits, signed or unsigned,
+                            checked or wrapping overflow, dec/hex/oct/bin output
+                            a variable keeps its exact value in its mode, an approximation in others
+  :latex f, :mathml f, :pretty f   the formula f as LaTeX, MathML or text in several lines
+  :plot f, x, a, b          chart of f for x from a to b; :asciiplot - the same in ASCII
+  :csv file f, x, a, b      write the points of the chart to a CSV file
+Keys:
+  Left, Right, Home, End, Ctrl-A, Ctrl-E   move the cursor
+  Up, Down                                 history
+  Ctrl-K, Ctrl-U, Ctrl-W                   delete to the end, to the start, a word
+  Ctrl-C cancels the input, Ctrl-D on an empty line exits";
+
+// Размер графика в знакоместах и число точек в CSV
+const PLOT_WIDTH: usize = 64;
+const PLOT_HEIGHT: usize = 16;
+const CSV_POINTS: usize = 201;
+
+pub struct Repl {
+    env: Env,
+    ops: OpTable,
+    mode: Mode,
+    // начало формулы, продолжение которой на следующей строке
+    pending: String,
+    // продолжает ли незакрытая скобка формулу на следующей строке
+    brackets: bool,
+    // предупреждения последней вычисленной формулы
+    warnings: Vec<String>
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self {
+            env: Env::new(), ops: OpTable::default(), mode: Mode::Float,
+            pending: String::new(), brackets: true, warnings: vec![]
+        }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self { Self::default() }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn env_mut(&mut self) -> &mut Env { &mut self.env }
+
+    // Включает продолжение формулы после незакрытой скобки; без него
+    // формулу на следующей строке продолжает только '\' в конце строки
+    pub fn set_bracket_continuation(&mut self, on: bool) { self.brackets = on; }
+
+    // Добавляет строку ввода; формула возвращается целиком, когда она закончена:
+    // скобки закрыты и строка не оканчивается на '\'
+    pub fn feed(&mut self, line: &str) -> Option<String> {
+        let line = line.trim_end();
+        let more = line.ends_with('\\');
+        if !self.pending.is_empty() { self.pending.push(' '); }
+        self.pending.push_str(line.trim_end_matches('\\'));
+        let depth = self.pending.chars()
+            .fold(0, |d, c| d + matches!(c, '(' | '[') as i32 - matches!(c, ')' | ']') as i32);
+        if more || self.brackets && depth > 0 { return None }
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    // Ввод формулы начат, но не закончен
+    pub fn pending(&self) -> bool { !self.pending.is_empty() }
+
+    pub fn cancel(&mut self) { self.pending.clear(); }
+
+    // Предупреждения последней формулы; вызывающая сторона выводит их отдельно от результата
+    pub fn take_warnings(&mut self) -> Vec<String> { std::mem::take(&mut self.warnings) }
+
+    // Выполняет команду или формулу; Ok(None) - печатать нечего
+    pub fn eval(&mut self, s: &str) -> Result<Option<String>, String> {
+        let s = s.trim();
+        if s.is_empty() { return Ok(None) }
+        if let Some(cmd) = s.strip_prefix(':') { return self.command(cmd.trim()) }
+        // ошибка выводится вместе с остальными ошибками формулы; функции и константы
+        // других режимов отличаются от обычных, в них проверяется только запись формулы
+        let float = self.mode == Mode::Float;
+        let fail = |e: CalcError, env: &Env| {
+            if float { report(s, &self.ops, env, &e) } else { report_syntax(s, &self.ops, &e) }
+        };
+        let calc = Calc::with_ops(s, &self.ops).map_err(|e| fail(e, &self.env))?;
+        self.warnings = check_with(s, &self.ops, &self.env).into_iter()
+            .filter(|d| d.is_warning()).map(|d| d.render(s)).collect();
+        // только определения функций: значения нет
+        let defs = calc.stmts().iter().all(|stmt| matches!(stmt, Stmt::Def { .. }));
+        let ans = (!defs).then_some("ans");
+        let x = calc.calc_mode_as(self.mode, &mut self.env, ans).map_err(|e| fail(e, &self.env))?;
+        Ok((!defs).then_some(x))
+    }
+
+    fn command(&mut self, cmd: &str) -> Result<Option<String>, String> {
+        // команда и ее аргумент - формула
+        let (cmd, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+        let formula = || {
+            if arg.trim().is_empty() { return Err(format!("Error: the command ':{cmd}' needs a formula")) }
+            parse_with(arg, &self.ops).map_err(|e| e.to_string())
+        };
+        let lines: Vec<String> = match cmd {
+            "help" | "h" | "?" => vec![HELP.to_string()],
+            "vars" => self.env.shown().iter().map(|(name, x)| format!("{name} = {x}")).collect(),
+            "funcs" => {
+                let user = self.env.funcs().into_iter().filter_map(|fname| {
+                    let f = self.env.func(fname)?;
+                    Some(format!("{fname}({}) = {}", f.params.join(", "), f.body))
+                });
+                // функции реестра и не перекрытые ими формы вычислителя
+                let registry = self.env.registry();
+                let mut builtin: Vec<_> = registry.list().into_iter().map(|(fname, f)| (fname, f.doc.as_str()))
+                    .chain(FORMS.into_iter().filter(|(fname, _)| registry.get(fname).is_none()))
+                    .collect();
+                builtin.sort_by(|a, b| a.0.cmp(b.0));
+                user.chain(builtin.into_iter().map(|(_, doc)| doc.to_string())).collect()
+            },
+            // мера углов тригонометрических функций
+            "angle" if arg.trim().is_empty() => vec![format!("angle: {}", self.env.registry().angle().name())],
+            "angle" => {
+                let Some(angle) = Angle::parse(arg.trim()) else {
+                    return Err(format!("Error: unknown angle unit '{}', expected rad, deg or grad", arg.trim()))
+                };
+                self.env.registry_mut().set_angle(angle);
+                vec![]
+            },
+            // числовой режим; единицы после чисел разбираются только в режиме units
+            "mode" if arg.trim().is_empty() => vec![format!("mode: {}", self.mode.name())],
+            "mode" => {
+                let Some(mode) = Mode::parse(arg) else {
+                    return Err(format!("Error: unknown mode '{}', type :help for the list of modes", arg.trim()))
+                };
+                self.mode = mode;
+                self.ops.set_units(mode == Mode::Units);
+                vec![]
+            },
+            // неявное умножение: 2x, 3(x + 1)
+            "implicit" => match arg.trim() {
+                "" => vec![format!("implicit: {}", if self.ops.implicit_mul().is_some() { "on" } else { "off" })],
+                "on" | "off" => {
+                    self.ops.set_implicit_mul(arg.trim() == "on");
+                    vec![]
+                },
+                x => return Err(format!("Error: expected on or off, found '{x}'"))
+            },
+            "latex" => vec![formula()?.to_latex()],
+            "mathml" => vec![formula()?.to_mathml()],
+            "pretty" => vec![formula()?.to_pretty()],
+            "plot" => vec![self.plot(arg, PLOT_WIDTH * 2 + 1)?.render(PLOT_WIDTH, PLOT_HEIGHT, Style::Braille)],
+            "asciiplot" => vec![self.plot(arg, PLOT_WIDTH + 1)?.render(PLOT_WIDTH, PLOT_HEIGHT, Style::Ascii)],
+            "csv" => {
+                let (file, arg) = arg.trim().split_once(char::is_whitespace).unwrap_or((arg.trim(), ""));
+                let csv = self.plot(arg, CSV_POINTS)?.to_csv();
+                fs::write(file, csv).map_err(|e| format!("Error: cannot write '{file}': {e}"))?;
+                vec![]
+            },
+            _ => return Err(format!("Error: unknown command ':{cmd}', type :help for the list of commands"))
+        };
+        Ok((!lines.is_empty()).then(|| lines.join("\n")))
+    }
+}
+
+impl Repl {
+    // Точки графика по аргументам f, x, a, b команды :plot
+    fn plot(&self, arg: &str, n: usize) -> Result<Plot, String> {
+        let args = split_args(arg);
+        if args.len() != 4 {
+            return Err("Error: expected a formula, a variable and the range: f, x, a, b".to_string())
+        }
+        let e = parse_with(args[0], &self.ops).map_err(|e| e.to_string())?;
+        let Ok(Expr::Var { name, .. }) = parse_with(args[1], &self.ops) else {
+            return Err(format!("Error: '{}' is not a variable name", args[1].trim()))
+        };
+        let bound = |s: &str| parse_with(s, &self.ops).and_then(|e| e.eval(&self.env)).map_err(|e| e.to_string());
+        Plot::sample(&e, &name, (bound(args[2])?, bound(args[3])?), n, &self.env).map_err(|e| e.to_string())
+    }
+}
+
+// Аргументы через запятую; запятые внутри скобок не разделяют аргументы
+fn split_args(s: &str) -> Vec<&str> {
+    let (mut args, mut depth, mut start) = (vec![], 0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    args.push(&s[start..]);
+    args
+}
+
+fn is_quit(s: &str) -> bool { matches!(s.trim(), ":quit" | ":q" | ":exit") }
+
+// Файл истории: переменная окружения CALC2_HISTORY или ~/.calc2_history
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("CALC2_HISTORY") { return Some(path.into()) }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".calc2_history"))
+}
+
+// Точка входа программы calc: диалог в терминале или вычисление потока формул
+pub fn run() -> ExitCode {
+    let mut repl = Repl::new();
+    if io::stdin().is_terminal() { interactive(&mut repl) } else { batch(&mut repl, io::stdin().lock()) }
+}
+
+fn interactive(repl: &mut Repl) -> ExitCode {
+    let mut editor = Editor::new(history_path());
+    println!("calc2 - type :help for help, :quit to exit");
+    loop {
+        let prompt = if repl.pending() { "... " } else { "> " };
+        let line = match editor.read_line(prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) => return ExitCode::SUCCESS,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                repl.cancel();
+                continue
+            },
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return ExitCode::FAILURE
+            }
+        };
+        let Some(s) = repl.feed(&line) else { continue };
+        editor.add(&s);
+        if is_quit(&s) { return ExitCode::SUCCESS }
+        let r = repl.eval(&s);
+        for w in repl.take_warnings() { println!("{w}"); }
+        match r {
+            Ok(Some(r)) => println!("{r}"),
+            Ok(None) => (),
+            Err(e) => println!("{e}")
+        }
+    }
+}
+
+// Формулы по одной в строке; результаты в stdout, ошибки в stderr.
+// Незакрытая скобка - ошибка своей строки, а не продолжение формулы.
+// Код возврата - ошибка, если хотя бы одна формула не вычислена
+pub fn batch(repl: &mut Repl, input: impl BufRead) -> ExitCode {
+    repl.set_bracket_continuation(false);
+    let mut code = ExitCode::SUCCESS;
+    let mut lines = input.lines();
+    loop {
+        let s = match lines.next() {
+            Some(Ok(line)) => match repl.feed(&line) {
+                Some(s) => s,
+                None => continue
+            },
+            Some(Err(e)) => {
+                eprintln!("Error: {e}");
+                return ExitCode::FAILURE
+            },
+            // незаконченная формула в конце ввода вычисляется как есть
+            None if repl.pending() => std::mem::take(&mut repl.pending),
+            None => return code
+        };
+        if is_quit(&s) { return code }
+        let r = repl.eval(&s);
+        for w in repl.take_warnings() { eprintln!("{w}"); }
+        match r {
+            Ok(Some(r)) => println!("{r}"),
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("{e}");
+                code = ExitCode::FAILURE;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Arity;
+    use super::*;
+
+    #[test]
+    fn mode_command_switches_number_modes() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval(":mode"), Ok(Some("mode: float".to_string())));
+        assert!(repl.eval("3 km").is_err());
+        repl.eval(":mode units").unwrap();
+        assert_eq!(repl.eval("3 km / 20 min to kmh"), Ok(Some("9 kmh".to_string())));
+        repl.eval(":mode rational").unwrap();
+        assert_eq!(repl.eval("1/3 + 1/3"), Ok(Some("2/3".to_string())));
+        repl.eval(":mode int 8 unsigned checked hex").unwrap();
+        assert_eq!(repl.eval(":mode"), Ok(Some("mode: int 8 unsigned checked hex".to_string())));
+        assert_eq!(repl.eval("0xf0 | 3"), Ok(Some("0xf3".to_string())));
+        assert!(repl.eval("0xff + 1").is_err());
+        assert!(repl.eval(":mode int 7").is_err());
+    }
+
+    #[test]
+    fn warns_about_a_series_index_that_hides_a_variable() {
+        let mut repl = Repl::new();
+        repl.eval("b = 2").unwrap();
+        assert!(repl.take_warnings().is_empty());
+        assert_eq!(repl.eval("sum(b, b, 3, 4)"), Ok(Some("7".to_string())));
+        let ws = repl.take_warnings();
+        assert_eq!(ws.len(), 1);
+        assert!(ws[0].starts_with("Warning: the series index 'b' hides the variable 'b'"), "{}", ws[0]);
+    }
+
+    #[test]
+    fn continues_formulas_on_the_next_line() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.feed("max(1,"), None);
+        assert_eq!(repl.feed("2) \\"), None);
+        assert_eq!(repl.feed("+ 1").as_deref(), Some("max(1, 2)  + 1"));
+        // во вводе из файла незакрытая скобка не поглощает следующие строки
+        repl.set_bracket_continuation(false);
+        let s = repl.feed("sin(1").unwrap();
+        assert!(repl.eval(&s).unwrap_err().contains("unbalanced bracket"));
+        assert_eq!(repl.feed("2 + \\"), None);
+        assert_eq!(repl.feed("2").map(|s| repl.eval(&s)), Some(Ok(Some("4".to_string()))));
+    }
+
+    #[test]
+    fn lists_the_forms_with_the_functions() {
+        let mut repl = Repl::new();
+        let funcs = repl.eval(":funcs").unwrap().unwrap();
+        for f in ["det(m)", "if(c, a, b)", "diff(f, x[, a])", "range(a, b[, step])", "sin(x)"] {
+            assert!(funcs.lines().any(|l| l.starts_with(f)), "{f}");
+        }
+        repl.env_mut().registry_mut().register("det", Arity::Exact(1), "det(x) - host", |a| a[0]);
+        let funcs = repl.eval(":funcs").unwrap().unwrap();
+        assert_eq!(funcs.lines().filter(|l| l.starts_with("det(")).collect::<Vec<_>>(), ["det(x) - host"]);
+    }
+
+    #[test]
+    fn modes_keep_exact_variables_and_ans() {
+        let mut repl = Repl::new();
+        let mut eval = |s: &str| repl.eval(s).unwrap().unwrap_or_default();
+        eval(":mode rational");
+        assert_eq!(eval("x = 1/3"), "1/3");
+        assert_eq!(eval("x * 3"), "1");
+        assert_eq!(eval("ans + 1/6"), "7/6");
+        eval(":mode decimal 20");
+        assert_eq!(eval("y = 2/3"), "0.66666666666666666667");
+        assert_eq!(eval("y * 3"), "2");
+        // в других режимах - приближение в f64
+        eval(":mode float");
+        assert_eq!(eval("x * 3"), "1");
+        eval(":mode complex");
+        assert_eq!(eval("z = 1 + 2*i"), "1 + 2i");
+        assert_eq!(eval("z * z"), "-3 + 4i");
+        assert!(eval(":vars").lines().any(|l| l == "z = 1 + 2i"));
+        // у комплексного числа приближения в f64 нет
+        eval(":mode float");
+        assert!(repl.eval("z").is_err());
+        assert_eq!(repl.eval("x = 2"), Ok(Some("2".to_string())));
+        repl.eval(":mode rational").unwrap();
+        assert_eq!(repl.eval("x / 3"), Ok(Some("2/3".to_string())));
+    }
+
+    #[test]
+    fn float_mode_keeps_variables_and_ans() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("x = 2"), Ok(Some("2".to_string())));
+        assert_eq!(repl.eval("f(t) = t^2"), Ok(None));
+        assert_eq!(repl.eval("f(x) + 1"), Ok(Some("5".to_string())));
+        assert_eq!(repl.eval("ans * 2"), Ok(Some("10".to_string())));
+        assert_eq!(repl.eval("diff(t^3, t)"), Ok(Some("3*t^2".to_string())));
+        assert_eq!(repl.eval("ans / 3"), Ok(Some("t^2".to_string())));
+    }
+}
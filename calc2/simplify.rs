@@ -0,0 +1,274 @@
+// Алгебраическое упрощение дерева разбора:
+// свертка констант, удаление тождеств (x*1, x + 0, x^1, ...),
+// приведение подобных слагаемых и множителей.
+// Значения переменных считаются числами: множители переставляются, поэтому
+// для матриц в переменных A*B*A упрощается в неверное A^2*B. Произведения
+// со списками и матрицами, записанными в формуле, не переставляются
+use super::expr::{BinOp, Expr, UnOp};
+
+pub fn simplify(e: &Expr) -> Expr {
+    match e {
+        Expr::Num(_) | Expr::Int(_) | Expr::Dec(..) | Expr::Var { .. } => e.clone(),
+        Expr::Call { fname, args, span } =>
+            Expr::call(fname, args.iter().map(simplify).collect(), span.clone()),
+        Expr::List(xs, span) => Expr::List(xs.iter().map(simplify).collect(), span.clone()),
+        Expr::Index(a, i, span) => Expr::Index(Box::new(simplify(a)), Box::new(simplify(i)), span.clone()),
+        Expr::Unary(UnOp::Neg, _, _) | Expr::Binary(BinOp::Add | BinOp::Sub, ..) => simplify_sum(e),
+        Expr::Binary(BinOp::Mul | BinOp::Div, ..) => {
+            let (c, t) = simplify_product(e);
+            with_coef(c, t)
+        },
+        Expr::Binary(BinOp::Pow, a, b, _) => simplify_pow(simplify(a), simplify(b)),
+        // значения сравнений и логических операций не числа: их не сворачиваем
+        Expr::Unary(UnOp::LNot, a, span) => Expr::Unary(UnOp::LNot, Box::new(simplify(a)), span.clone()),
+        Expr::Binary(op, a, b, span) if op.is_comparison() || op.is_logical() =>
+            Expr::Binary(*op, Box::new(simplify(a)), Box::new(simplify(b)), span.clone()),
+        // остаток и побитовые операции: только свертка констант
+        Expr::Unary(op, a, span) => match simplify(a) {
+            Expr::Num(x) if op.apply(x).is_finite() => Expr::num(op.apply(x)),
+            a => Expr::Unary(*op, Box::new(a), span.clone())
+        },
+        Expr::Binary(op, a, b, span) => match (simplify(a), simplify(b)) {
+            (Expr::Num(x), Expr::Num(y)) if op.apply(x, y).is_finite() => Expr::num(op.apply(x, y)),
+            (a, b) => Expr::Binary(*op, Box::new(a), Box::new(b), span.clone())
+        }
+    }
+}
+
+// Сравнение деревьев без учета положения в исходной строке
+fn same(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Num(x), Expr::Num(y)) => x == y,
+        (Expr::Int(x), Expr::Int(y)) => x == y,
+        (Expr::Dec(_, x), Expr::Dec(_, y)) => x == y,
+        (Expr::Var { name: x, .. }, Expr::Var { name: y, .. }) => x == y,
+        (Expr::Unary(p, x, _), Expr::Unary(q, y, _)) => p == q && same(x, y),
+        (Expr::Binary(p, x1, x2, _), Expr::Binary(q, y1, y2, _)) => p == q && same(x1, y1) && same(x2, y2),
+        (Expr::Call { fname: f, args: xs, .. }, Expr::Call { fname: g, args: ys, .. }) =>
+            f == g && xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same(x, y)),
+        (Expr::List(xs, _), Expr::List(ys, _)) => xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same(x, y)),
+        (Expr::Index(x, i, _), Expr::Index(y, j, _)) => same(x, y) && same(i, j),
+        _ => false
+    }
+}
+
+// Сумма: раскладываем на слагаемые вида c*t, складываем
+// коэффициенты при одинаковых t; константы - слагаемые с t = None
+fn simplify_sum(e: &Expr) -> Expr {
+    let mut terms: Vec<(f64, Option<Expr>)> = vec![];
+    sum_terms(e, 1., &mut terms);
+    let mut acc: Option<Expr> = None;
+    for (c, t) in terms {
+        if c == 0. { continue }
+        acc = Some(match acc {
+            None => with_coef(c, t),
+            Some(a) if c < 0. => Expr::binary(BinOp::Sub, a, with_coef(-c, t)),
+            Some(a) => Expr::binary(BinOp::Add, a, with_coef(c, t))
+        });
+    }
+    acc.unwrap_or(Expr::num(0.))
+}
+
+fn sum_terms(e: &Expr, sign: f64, terms: &mut Vec<(f64, Option<Expr>)>) {
+    let (c, t) = match e {
+        Expr::Binary(BinOp::Add, a, b, _) => {
+            sum_terms(a, sign, terms);
+            return sum_terms(b, sign, terms)
+        },
+        Expr::Binary(BinOp::Sub, a, b, _) => {
+            sum_terms(a, sign, terms);
+            return sum_terms(b, -sign, terms)
+        },
+        Expr::Unary(UnOp::Neg, a, _) => return sum_terms(a, -sign, terms),
+        Expr::Binary(BinOp::Mul | BinOp::Div, ..) => simplify_product(e),
+        _ => match simplify(e) {
+            Expr::Num(x) => (x, None),
+            // степень могла упроститься до суммы или произведения
+            s @ (Expr::Unary(UnOp::Neg, ..) | Expr::Binary(BinOp::Add | BinOp::Sub, ..)) =>
+                return sum_terms(&s, sign, terms),
+            s @ Expr::Binary(BinOp::Mul | BinOp::Div, ..) => simplify_product(&s),
+            s => (1., Some(s))
+        }
+    };
+    let c = sign * c;
+    match terms.iter_mut().find(|(_, u)| match (u, &t) {
+        (None, None) => true,
+        (Some(u), Some(t)) => same(u, t),
+        _ => false
+    }) {
+        Some(term) => term.0 += c,
+        None => terms.push((c, t))
+    }
+}
+
+// Произведение: раскладываем на числовой коэффициент и множители b^n,
+// показатели при одинаковых основаниях складываем
+fn simplify_product(e: &Expr) -> (f64, Option<Expr>) {
+    match e {
+        Expr::Binary(op, a, b, span) if has_list(e) =>
+            return (1., Some(Expr::Binary(*op, Box::new(simplify(a)), Box::new(simplify(b)), span.clone()))),
+        _ => ()
+    }
+    let mut coef = 1.;
+    let mut fs: Vec<(Expr, f64)> = vec![];
+    factors(e, 1., &mut coef, &mut fs);
+    if coef == 0. { return (0., None) }
+    let pow_of = |b: &Expr, n: f64| if n == 1. { b.clone() } else {
+        Expr::binary(BinOp::Pow, b.clone(), Expr::num(n))
+    };
+    let mut num: Option<Expr> = None;
+    let mut den: Option<Expr> = None;
+    for (b, n) in fs {
+        if n > 0. {
+            let x = pow_of(&b, n);
+            num = Some(match num { None => x, Some(a) => Expr::binary(BinOp::Mul, a, x) });
+        } else if n < 0. {
+            let x = pow_of(&b, -n);
+            den = Some(match den { None => x, Some(a) => Expr::binary(BinOp::Mul, a, x) });
+        }
+    }
+    let t = match (num, den) {
+        (None, None) => None,
+        (Some(a), None) => Some(a),
+        (a, Some(d)) => Some(Expr::binary(BinOp::Div, a.unwrap_or(Expr::num(1.)), d))
+    };
+    (coef, t)
+}
+
+fn factors(e: &Expr, exp: f64, coef: &mut f64, fs: &mut Vec<(Expr, f64)>) {
+    let (b, n) = match e {
+        Expr::Binary(BinOp::Mul, a, b, _) => {
+            factors(a, exp, coef, fs);
+            return factors(b, exp, coef, fs)
+        },
+        Expr::Binary(BinOp::Div, a, b, _) => {
+            factors(a, exp, coef, fs);
+            return factors(b, -exp, coef, fs)
+        },
+        Expr::Unary(UnOp::Neg, a, _) => {
+            *coef = -*coef;
+            return factors(a, exp, coef, fs)
+        },
+        _ => match simplify(e) {
+            // деление на ноль не сворачиваем
+            Expr::Num(x) if x != 0. || exp > 0. => {
+                *coef *= x.powf(exp);
+                return
+            },
+            Expr::Unary(UnOp::Neg, a, _) => {
+                *coef = -*coef;
+                (*a, exp)
+            },
+            Expr::Binary(BinOp::Pow, b, p, _) => match *p {
+                Expr::Num(n) if merges(n, exp) => (*b, n * exp),
+                p => (Expr::binary(BinOp::Pow, *b, p), exp)
+            },
+            s @ Expr::Binary(BinOp::Mul | BinOp::Div, ..) => {
+                // произведение с коэффициентом, полученное после упрощения суммы
+                let (c, t) = simplify_product(&s);
+                *coef *= c.powf(exp);
+                match t {
+                    Some(t) => (t, exp),
+                    None => return
+                }
+            },
+            s => (s, exp)
+        }
+    };
+    match fs.iter_mut().find(|(u, _)| same(u, &b)) {
+        Some(f) => f.1 += n,
+        None => fs.push((b, n))
+    }
+}
+
+fn simplify_pow(a: Expr, b: Expr) -> Expr {
+    match (&a, &b) {
+        (Expr::Num(x), Expr::Num(y)) if x.powf(*y).is_finite() => Expr::num(x.powf(*y)),
+        (_, Expr::Num(y)) if *y == 1. => a,
+        (_, Expr::Num(y)) if *y == 0. => Expr::num(1.),
+        (Expr::Num(x), _) if *x == 1. => Expr::num(1.),
+        (Expr::Binary(BinOp::Pow, x, m, _), Expr::Num(n)) => match m.as_ref() {
+            Expr::Num(m) if merges(*m, *n) => simplify_pow(x.as_ref().clone(), Expr::num(m * n)),
+            _ => Expr::binary(BinOp::Pow, a, b)
+        },
+        _ => Expr::binary(BinOp::Pow, a, b)
+    }
+}
+
+// (x^m)^n = x^(m*n) при целом n или нечетном целом m; иначе нет: (x^2)^0.5 = |x|.
+// (x^3)^(1/3) = x и там, где дробная степень отрицательного числа не определена
+fn merges(m: f64, n: f64) -> bool {
+    n.fract() == 0. || m.fract() == 0. && m % 2. != 0.
+}
+
+// Множитель произведения - список или матрица
+fn has_list(e: &Expr) -> bool {
+    match e {
+        Expr::List(..) => true,
+        Expr::Binary(BinOp::Mul | BinOp::Div, a, b, _) => has_list(a) || has_list(b),
+        Expr::Unary(UnOp::Neg, a, _) => has_list(a),
+        _ => false
+    }
+}
+
+// Слагаемое c*t
+fn with_coef(c: f64, t: Option<Expr>) -> Expr {
+    let Some(t) = t else { return Expr::num(c) };
+    if c == 1. { return t }
+    if c == -1. { return Expr::neg(t) }
+    match t {
+        // c*(1/d) = c/d
+        Expr::Binary(BinOp::Div, a, d, _) if matches!(*a, Expr::Num(x) if x == 1.) =>
+            Expr::binary(BinOp::Div, Expr::num(c), *d),
+        t => Expr::binary(BinOp::Mul, Expr::num(c), t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Env};
+    use super::*;
+
+    fn simplified(s: &str) -> String { simplify(&parse(s).unwrap()).to_string() }
+
+    // Упрощенная формула имеет те же значения, что и исходная, там, где исходная определена
+    fn sound(s: &str) {
+        let (e, t) = (parse(s).unwrap(), simplify(&parse(s).unwrap()));
+        let mut env = Env::new();
+        for x in [-2.5, -1., -0.3, 0.7, 1., 4.] {
+            env.set("x", x);
+            env.set("y", x + 1.5);
+            let (a, b) = (e.eval(&env).unwrap(), t.eval(&env).unwrap());
+            assert!(a.is_nan() || (a - b).abs() <= 1e-9 * a.abs().max(1.), "{s} at {x}: {a} != {t} = {b}");
+        }
+    }
+
+    #[test]
+    fn folds_and_collects() {
+        assert_eq!(simplified("x + 0 + 2*3"), "x + 6");
+        assert_eq!(simplified("x*1 + x"), "2*x");
+        assert_eq!(simplified("x^2*x^3"), "x^5");
+        assert_eq!(simplified("x/x^2"), "1/x");
+        assert_eq!(simplified("(x^2)^3"), "x^6");
+        assert_eq!(simplified("(x^3)^(1/3)"), "x");
+    }
+
+    #[test]
+    fn merges_powers_only_when_sound() {
+        assert_eq!(simplified("(x^2)^0.5"), "(x^2)^0.5");
+        assert_eq!(simplified("(x^4)^0.25"), "(x^4)^0.25");
+        for s in ["(x^2)^0.5", "(x^4)^0.25", "(x^2)^3", "(x^3)^2", "(x^-2)^-1", "(x^2)^0.5 / x",
+                  "x*y*x/y^2", "(x + y)^2 - (y + x)^2", "2*x - x - x", "(x^3)^(1/3)*2"] {
+            sound(s)
+        }
+    }
+
+    #[test]
+    fn keeps_the_order_of_matrix_products() {
+        assert_eq!(simplified("[[1, 2], [3, 4]]*A*[[1, 2], [3, 4]]"), "[[1, 2], [3, 4]]*A*[[1, 2], [3, 4]]");
+        assert_eq!(simplified("[1, 2]*(2*3)"), "[1, 2]*6");
+        let s = "[[1, 2], [3, 4]]*[[0, 1], [1, 0]]*[[1, 2], [3, 4]]";
+        let (e, t) = (parse(s).unwrap(), simplify(&parse(s).unwrap()));
+        assert_eq!(e.value(&Env::new()), t.value(&Env::new()));
+    }
+}
@@ -0,0 +1,149 @@
+// Численное решение уравнения f(x) = 0 с одной неизвестной. Шаг - метод Ньютона
+// по производной из diff, а если ее нет - метод секущих. Когда найдены две точки
+// с разными знаками f, корень остается на отрезке между ними: шаг за пределы
+// отрезка заменяется делением его пополам
+use std::ops::Range;
+use super::diff::diff_in;
+use super::expr::{BinOp, Expr};
+use super::{CalcError, Env, ErrorKind, Value};
+
+// Наибольшее число итераций
+const MAX_ITER: usize = 200;
+// Относительная точность корня
+const TOL: f64 = 1e-12;
+// Наибольшее число удвоений шага при поиске отрезка со сменой знака
+const MAX_EXPAND: usize = 60;
+
+// Начальное приближение: точка или отрезок, на концах которого f разных знаков
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Start {
+    Guess(f64),
+    Between(f64, f64)
+}
+
+// Корень уравнения e = 0 по переменной x вблизи guess; e может быть равенством a == b
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn solve(e: &Expr, x: &str, guess: f64, env: &Env) -> Result<f64, CalcError> {
+    root_of(e, x, Start::Guess(guess), env, &[], 0, &e.span())
+}
+
+// Корень на отрезке [a, b]
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn solve_between(e: &Expr, x: &str, a: f64, b: f64, env: &Env) -> Result<f64, CalcError> {
+    root_of(e, x, Start::Between(a, b), env, &[], 0, &e.span())
+}
+
+// locals и depth - как при вычислении формулы, в которой вызван solve
+pub(crate) fn root_of(e: &Expr, x: &str, start: Start, env: &Env, locals: &[(&str, Value)],
+                      depth: usize, span: &Range<usize>) -> Result<f64, CalcError> {
+    // уравнение a == b решается как a - b = 0
+    let e = match e {
+        Expr::Binary(BinOp::Eq, a, b, span) => Expr::Binary(BinOp::Sub, a.clone(), b.clone(), span.clone()),
+        e => e.clone()
+    };
+    // производной может не быть: abs, %, ...
+    let d = diff_in(&e, x, env).ok();
+    let at = |e: &Expr, t: f64| {
+        let mut xs = vec![(x, Value::Num(t))];
+        xs.extend_from_slice(locals);
+        e.eval_local(env, &xs, depth)
+    };
+    root(|t| Ok((at(&e, t)?, d.as_ref().and_then(|d| at(d, t).ok()))), start, span)
+}
+
+// Корень функции f, которая возвращает значение и, если может, производную
+pub(crate) fn root<F>(mut f: F, start: Start, span: &Range<usize>) -> Result<f64, CalcError>
+    where F: FnMut(f64) -> Result<(f64, Option<f64>), CalcError> {
+    let domain = |s: String| CalcError::new(ErrorKind::Domain(s), span.clone());
+    let (mut x, mut bracket) = match start {
+        Start::Guess(g) => (g, None),
+        Start::Between(a, b) => {
+            let (fa, fb) = (f(a)?.0, f(b)?.0);
+            if fa == 0. { return Ok(a) }
+            if fb == 0. { return Ok(b) }
+            if !(fa.is_finite() && fb.is_finite()) || fa.signum() == fb.signum() {
+                return Err(domain(format!("the function has the same sign at both ends of [{a}, {b}]")))
+            }
+            ((a + b) / 2., Some((a, fa, b, fb)))
+        }
+    };
+    let (mut fx, mut dfx) = f(x)?;
+    if !fx.is_finite() { return Err(domain(format!("the function is not defined at {x}"))) }
+    // масштаб значений f: в найденном корне |f| должно быть много меньше
+    let scale = fx.abs().max(1.);
+    // предыдущая точка - для метода секущих и поиска смены знака
+    let mut prev: Option<(f64, f64)> = None;
+    for k in 0..MAX_ITER {
+        if fx == 0. { return Ok(x) }
+        bracket = match (bracket, prev) {
+            (Some((lo, flo, hi, fhi)), _) =>
+                Some(if fx.signum() == flo.signum() { (x, fx, hi, fhi) } else { (lo, flo, x, fx) }),
+            (None, Some((p, fp))) if fp.signum() != fx.signum() => Some((p, fp, x, fx)),
+            (None, _) => None
+        };
+        let h = 1e-7 * x.abs().max(1.);
+        let step = match dfx {
+            Some(d) if d != 0. && d.is_finite() => Some(fx / d),
+            _ => prev.filter(|&(p, fp)| p != x && fp != fx).map(|(p, fp)| fx * (x - p) / (fx - fp))
+        };
+        // касательная горизонтальна и секущей нет: небольшой сдвиг
+        let mut next = step.map_or(x + h, |s| x - s);
+        if let Some((lo, _, hi, _)) = bracket {
+            // деление пополам, если шаг уходит с отрезка, и на каждом третьем шаге,
+            // чтобы медленная сходимость не затягивала поиск
+            if !(next > lo.min(hi) && next < lo.max(hi)) || k % 3 == 2 { next = (lo + hi) / 2.; }
+        }
+        let (mut fn_, mut dn) = f(next)?;
+        // вне области определения f: шаг уменьшается
+        let mut tries = 0;
+        while !fn_.is_finite() && tries < MAX_EXPAND {
+            next = (x + next) / 2.;
+            (fn_, dn) = f(next)?;
+            tries += 1;
+        }
+        if !fn_.is_finite() { break }
+        let width = bracket.map_or(f64::INFINITY, |(lo, _, hi, _)| (hi - lo).abs());
+        let done = (next - x).abs() <= TOL * (1. + next.abs()) || width <= TOL * (1. + next.abs());
+        prev = Some((x, fx));
+        (x, fx, dfx) = (next, fn_, dn);
+        if done {
+            if fx.abs() <= 1e-6 * scale { return Ok(x) }
+            // смена знака в точке разрыва, например у 1/x
+            if bracket.is_some() {
+                return Err(domain(format!("the function changes sign at {x} but has no root there")))
+            }
+        }
+    }
+    // метод не сошелся: ищем отрезок со сменой знака вокруг начальной точки
+    if let Start::Guess(g) = start {
+        let f0 = f(g)?.0;
+        let mut h = 1e-3 * g.abs().max(1.);
+        for _ in 0..MAX_EXPAND {
+            for t in [g - h, g + h] {
+                let ft = f(t)?.0;
+                if ft.is_finite() && ft.signum() != f0.signum() {
+                    return root(f, Start::Between(t.min(g), t.max(g)), span)
+                }
+            }
+            h *= 2.;
+        }
+    }
+    Err(CalcError::new(ErrorKind::NoConvergence("solve".to_string()), span.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, Env, ErrorKind};
+    use super::*;
+
+    #[test]
+    fn finds_roots() {
+        let env = Env::new();
+        let x = solve(&parse("x^2 - 2").unwrap(), "x", 1., &env).unwrap();
+        assert!((x - 2f64.sqrt()).abs() < 1e-12);
+        let x = solve_between(&parse("cos(x) - x").unwrap(), "x", 0., 1., &env).unwrap();
+        assert!((x - 0.739_085_133_215_160_6).abs() < 1e-12);
+        let e = solve(&parse("x^2 + 1").unwrap(), "x", 1., &env).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::NoConvergence(_)));
+    }
+}
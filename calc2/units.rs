@@ -0,0 +1,334 @@
+// Величины с единицами измерения: значение в единицах СИ,
+// размерность и единица для печати результата
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use super::backend::{float_index, Backend};
+use super::expr::BinOp;
+use super::{Arity, ErrorKind, Mode};
+
+// Степени основных единиц СИ: m, kg, s, A, K, mol, cd
+pub type Dim = [i8; 7];
+
+const BASE: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+const NONE: Dim = [0; 7];
+
+// Единицы: имя, множитель к СИ, размерность, допустимы ли приставки СИ
+const UNITS: &[(&str, f64, Dim, bool)] = &[
+    ("m", 1., [1, 0, 0, 0, 0, 0, 0], true),
+    ("g", 1e-3, [0, 1, 0, 0, 0, 0, 0], true),
+    ("s", 1., [0, 0, 1, 0, 0, 0, 0], true),
+    ("A", 1., [0, 0, 0, 1, 0, 0, 0], true),
+    ("K", 1., [0, 0, 0, 0, 1, 0, 0], true),
+    ("mol", 1., [0, 0, 0, 0, 0, 1, 0], true),
+    ("cd", 1., [0, 0, 0, 0, 0, 0, 1], true),
+    ("min", 60., [0, 0, 1, 0, 0, 0, 0], false),
+    ("h", 3600., [0, 0, 1, 0, 0, 0, 0], false),
+    ("day", 86400., [0, 0, 1, 0, 0, 0, 0], false),
+    ("inch", 0.0254, [1, 0, 0, 0, 0, 0, 0], false),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0, 0], false),
+    ("yd", 0.9144, [1, 0, 0, 0, 0, 0, 0], false),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0, 0], false),
+    ("mph", 0.44704, [1, 0, -1, 0, 0, 0, 0], false),
+    ("kmh", 1. / 3.6, [1, 0, -1, 0, 0, 0, 0], false),
+    ("L", 1e-3, [3, 0, 0, 0, 0, 0, 0], true),
+    ("t", 1e3, [0, 1, 0, 0, 0, 0, 0], false),
+    ("lb", 0.45359237, [0, 1, 0, 0, 0, 0, 0], false),
+    ("oz", 0.028349523125, [0, 1, 0, 0, 0, 0, 0], false),
+    ("Hz", 1., [0, 0, -1, 0, 0, 0, 0], true),
+    ("N", 1., [1, 1, -2, 0, 0, 0, 0], true),
+    ("J", 1., [2, 1, -2, 0, 0, 0, 0], true),
+    ("W", 1., [2, 1, -3, 0, 0, 0, 0], true),
+    ("Pa", 1., [-1, 1, -2, 0, 0, 0, 0], true),
+    ("bar", 1e5, [-1, 1, -2, 0, 0, 0, 0], false),
+    ("atm", 101325., [-1, 1, -2, 0, 0, 0, 0], false),
+    ("cal", 4.184, [2, 1, -2, 0, 0, 0, 0], true),
+    ("C", 1., [0, 0, 1, 1, 0, 0, 0], true),
+    ("V", 1., [2, 1, -3, -1, 0, 0, 0], true),
+    ("Ohm", 1., [2, 1, -3, -2, 0, 0, 0], true)
+];
+
+const PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1), ("Q", 1e30), ("R", 1e27), ("Y", 1e24), ("Z", 1e21), ("E", 1e18), ("P", 1e15),
+    ("T", 1e12), ("G", 1e9), ("M", 1e6), ("k", 1e3), ("h", 1e2), ("d", 1e-1), ("c", 1e-2),
+    ("m", 1e-3), ("u", 1e-6), ("µ", 1e-6), ("n", 1e-9), ("p", 1e-12), ("f", 1e-15),
+    ("a", 1e-18), ("z", 1e-21), ("y", 1e-24), ("r", 1e-27), ("q", 1e-30)
+];
+
+// Единица по имени, возможно с приставкой СИ: km, ms, kPa
+pub fn unit(name: &str) -> Option<Quantity> {
+    if let Some((_, k, dim, _)) = UNITS.iter().find(|u| u.0 == name) {
+        return Some(Quantity::with_unit(*k, *dim, name, *k))
+    }
+    PREFIXES.iter().find_map(|(p, m)| {
+        let (_, k, dim, _) = UNITS.iter().find(|u| u.3 && name.strip_prefix(p) == Some(u.0))?;
+        Some(Quantity::with_unit(m * k, *dim, name, m * k))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    // значение в единицах СИ
+    pub value: f64,
+    pub dim: Dim,
+    // единица для печати и ее множитель к СИ; None - печать в основных единицах СИ
+    unit: Option<(String, f64)>
+}
+
+impl Quantity {
+    pub fn number(value: f64) -> Self { Self { value, dim: NONE, unit: None } }
+
+    fn with_unit(value: f64, dim: Dim, name: &str, k: f64) -> Self {
+        Self { value, dim, unit: Some((name.to_string(), k)) }
+    }
+
+    pub fn is_number(&self) -> bool { self.dim == NONE }
+
+    // Единица печати, если она сохраняет смысл после операции
+    fn keep(dim: Dim, unit: Option<(String, f64)>) -> Option<(String, f64)> {
+        if dim == NONE { None } else { unit }
+    }
+
+    fn same_dim(&self, y: &Self) -> Result<(), ErrorKind> {
+        if self.dim == y.dim { return Ok(()) }
+        Err(ErrorKind::Dimension(format!("'{}' and '{}'", dim_name(&self.dim), dim_name(&y.dim))))
+    }
+
+    // Сравнение величин одной размерности
+    pub fn compare(&self, y: &Self) -> Result<Option<Ordering>, ErrorKind> {
+        self.same_dim(y)?;
+        Ok(self.value.partial_cmp(&y.value))
+    }
+
+    pub fn neg(&self) -> Self { Self { value: -self.value, ..self.clone() } }
+
+    pub fn add(&self, y: &Self) -> Result<Self, ErrorKind> {
+        self.same_dim(y)?;
+        Ok(Self { value: self.value + y.value, dim: self.dim, unit: self.unit.clone().or(y.unit.clone()) })
+    }
+
+    pub fn sub(&self, y: &Self) -> Result<Self, ErrorKind> { self.add(&y.neg()) }
+
+    pub fn mul(&self, y: &Self) -> Result<Self, ErrorKind> {
+        let dim = dim_with(|i| self.dim[i].checked_add(y.dim[i]))?;
+        let unit = match (&self.unit, &y.unit) {
+            (Some((a, k)), Some((b, l))) if a == b => Some((format!("{}^2", group(a, "*/^")), k * l)),
+            (Some((a, k)), Some((b, l))) => Some((format!("{a}*{}", group(b, "/")), k * l)),
+            (Some(u), None) if y.is_number() => Some(u.clone()),
+            (None, Some(u)) if self.is_number() => Some(u.clone()),
+            _ => None
+        };
+        Ok(Self { value: self.value * y.value, dim, unit: Self::keep(dim, unit) })
+    }
+
+    pub fn div(&self, y: &Self) -> Result<Self, ErrorKind> {
+        if y.value == 0. { return Err(ErrorKind::DivisionByZero) }
+        let dim = dim_with(|i| self.dim[i].checked_sub(y.dim[i]))?;
+        let unit = match (&self.unit, &y.unit) {
+            (Some((a, k)), Some((b, l))) => Some((format!("{a}/{}", group(b, "*/")), k / l)),
+            (Some(u), None) if y.is_number() => Some(u.clone()),
+            (None, Some((b, l))) if self.is_number() => Some((format!("{}^-1", group(b, "*/^")), 1. / l)),
+            _ => None
+        };
+        Ok(Self { value: self.value / y.value, dim, unit: Self::keep(dim, unit) })
+    }
+
+    pub fn pow(&self, y: &Self) -> Result<Self, ErrorKind> {
+        if !y.is_number() {
+            return Err(ErrorKind::Dimension(format!("the exponent has units '{}'", dim_name(&y.dim))))
+        }
+        if self.is_number() { return Ok(Self::number(self.value.powf(y.value))) }
+        let n = y.value;
+        if n.fract() != 0. {
+            return Err(ErrorKind::Domain("a quantity with units needs an integer power".to_string()))
+        }
+        // показатель вне i8 переполняет любую ненулевую степень размерности
+        let k = i8::try_from(n as i64).ok();
+        let dim = dim_with(|i| if self.dim[i] == 0 { Some(0) } else { k.and_then(|k| self.dim[i].checked_mul(k)) })?;
+        let unit = self.unit.as_ref().map(|(a, k)| (format!("{}^{n}", group(a, "*/^")), k.powf(n)));
+        Ok(Self { value: self.value.powf(n), dim, unit: Self::keep(dim, unit) })
+    }
+
+    pub fn sqrt(&self) -> Result<Self, ErrorKind> {
+        if self.dim.iter().any(|d| d % 2 != 0) {
+            return Err(ErrorKind::Dimension(format!("sqr() of '{}'", dim_name(&self.dim))))
+        }
+        Ok(Self { value: self.value.sqrt(), dim: self.dim.map(|d| d / 2), unit: None })
+    }
+
+    // Перевод в единицу y: размерности должны совпадать
+    pub fn to(&self, y: &Self) -> Result<Self, ErrorKind> {
+        self.same_dim(y)?;
+        Ok(Self { value: self.value, dim: self.dim, unit: y.unit.clone() })
+    }
+}
+
+// Размерность с показателями f(i); показатель вне -127..=127 - ошибка
+fn dim_with(f: impl Fn(usize) -> Option<i8>) -> Result<Dim, ErrorKind> {
+    let mut dim = NONE;
+    for (i, d) in dim.iter_mut().enumerate() {
+        *d = f(i).filter(|d| *d != i8::MIN).ok_or_else(|| ErrorKind::Dimension(format!("the power of '{}' is out of range", BASE[i])))?;
+    }
+    Ok(dim)
+}
+
+// Составное имя единицы со знаками ops берется в скобки
+fn group(name: &str, ops: &str) -> String {
+    if name.contains(|c| ops.contains(c)) { format!("({name})") } else { name.to_string() }
+}
+
+// Размерность в основных единицах СИ: kg*m^2/s^2, у безразмерной величины - 1
+fn dim_name(dim: &Dim) -> String {
+    if *dim == NONE { return "1".to_string() }
+    let part = |sign: i8| {
+        let xs: Vec<_> = BASE.iter().zip(dim).filter(|(_, d)| **d * sign > 0)
+            .map(|(b, d)| if *d * sign == 1 { b.to_string() } else { format!("{b}^{}", d * sign) })
+            .collect();
+        xs.join("*")
+    };
+    match (part(1), part(-1)) {
+        (num, den) if den.is_empty() => num,
+        (num, _) if num.is_empty() => BASE.iter().zip(dim).filter(|(_, d)| **d != 0)
+            .map(|(b, d)| format!("{b}^{d}")).collect::<Vec<_>>().join("*"),
+        (num, den) if den.contains('*') => format!("{num}/({den})"),
+        (num, den) => format!("{num}/{den}")
+    }
+}
+
+impl Display for Quantity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.unit {
+            _ if self.is_number() => write!(f, "{}", self.value),
+            Some((name, k)) => write!(f, "{} {name}", self.value / k),
+            None => write!(f, "{} {}", self.value, dim_name(&self.dim))
+        }
+    }
+}
+
+// Вычисление с единицами измерения; имена единиц - константы режима
+pub struct UnitBackend;
+
+impl Backend for UnitBackend {
+    type Value = Quantity;
+
+    fn num(&self, x: f64) -> Result<Quantity, ErrorKind> { Ok(Quantity::number(x)) }
+
+    fn neg(&self, a: &Quantity) -> Result<Quantity, ErrorKind> { Ok(a.neg()) }
+
+    fn binary(&self, op: BinOp, a: &Quantity, b: &Quantity) -> Result<Quantity, ErrorKind> {
+        match op {
+            BinOp::Add => a.add(b),
+            BinOp::Sub => a.sub(b),
+            BinOp::Mul => a.mul(b),
+            BinOp::Div => a.div(b),
+            BinOp::Pow => a.pow(b),
+            _ => Err(ErrorKind::Unsupported(op.symbol().to_string()))
+        }
+    }
+
+    fn compare(&self, op: BinOp, a: &Quantity, b: &Quantity) -> Result<bool, ErrorKind> {
+        Ok(op.compare(a.compare(b)?))
+    }
+
+    fn arity(&self, fname: &str) -> Option<Arity> {
+        match fname {
+            "min" | "max" | "avg" => Some(Arity::AtLeast(1)),
+            "pow" | "to" => Some(Arity::Exact(2)),
+            "sqr" | "exp" | "ln" | "log" | "sin" | "cos" | "tan" => Some(Arity::Exact(1)),
+            "pi" | "e" => Some(Arity::Exact(0)),
+            _ => None
+        }
+    }
+
+    fn call(&self, fname: &str, args: &[Quantity]) -> Result<Quantity, ErrorKind> {
+        let mut r = args.first().cloned().unwrap_or(Quantity::number(0.));
+        match fname {
+            "min" | "max" | "avg" => {
+                for x in &args[1..] {
+                    r.same_dim(x)?;
+                    let v = match fname {
+                        "min" => r.value.min(x.value),
+                        "max" => r.value.max(x.value),
+                        _ => r.value + x.value
+                    };
+                    r.value = v;
+                }
+                if fname == "avg" { r.value /= args.len() as f64; }
+                Ok(r)
+            },
+            "pow" => r.pow(&args[1]),
+            "to" => r.to(&args[1]),
+            "sqr" => r.sqrt(),
+            "pi" => Ok(Quantity::number(std::f64::consts::PI)),
+            "e" => Ok(Quantity::number(std::f64::consts::E)),
+            _ if !r.is_number() =>
+                Err(ErrorKind::Dimension(format!("{fname}() of '{}'", dim_name(&r.dim)))),
+            _ => {
+                let x = r.value;
+                Ok(Quantity::number(match fname {
+                    "exp" => x.exp(),
+                    "ln" => x.ln(),
+                    "log" => x.log10(),
+                    "sin" => x.sin(),
+                    "cos" => x.cos(),
+                    _ => x.tan()
+                }))
+            }
+        }
+    }
+
+    fn constant(&self, name: &str) -> Option<Quantity> { unit(name) }
+
+    fn index(&self, a: &Quantity) -> Option<i64> {
+        if a.is_number() { float_index(a.value) } else { None }
+    }
+
+    fn mode(&self) -> Option<Mode> { Some(Mode::Units) }
+
+    fn float(&self, a: &Quantity) -> Option<f64> { a.is_number().then_some(a.value) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Calc, Env, Mode};
+    use super::*;
+
+    fn units(s: &str) -> Result<String, ErrorKind> {
+        Calc::with_mode(s, Mode::Units).and_then(|c| c.calc_mode(Mode::Units, &mut Env::new())).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn converts_units() {
+        assert_eq!(units("3 km / 20 min to kmh"), Ok("9 kmh".to_string()));
+        assert_eq!(units("2 m * 3 m"), Ok("6 m^2".to_string()));
+        assert_eq!(units("1 h to s"), Ok("3600 s".to_string()));
+    }
+
+    #[test]
+    fn unit_suffix_only_in_units_mode() {
+        let e = Calc::new("2 h").err().map(|e| e.kind);
+        assert_eq!(e, Some(ErrorKind::UnexpectedToken("h".to_string())));
+        assert_eq!(units("2 h to min"), Ok("120 min".to_string()));
+        // без суффикса единица - константа режима
+        assert_eq!(Calc::new("2*h").and_then(|c| c.calc_mode(Mode::Units, &mut Env::new())).ok(),
+            Some("2 h".to_string()));
+    }
+
+    #[test]
+    fn checks_dimensions() {
+        assert!(matches!(units("1 m + 1 s"), Err(ErrorKind::Dimension(_))));
+        assert!(matches!(units("1 m / 0 s"), Err(ErrorKind::DivisionByZero)));
+        assert!(matches!(units("(1 m)^0.5"), Err(ErrorKind::Domain(_))));
+    }
+
+    #[test]
+    fn dimension_overflow_is_an_error() {
+        assert!(matches!(units("(1 m^100)^2"), Err(ErrorKind::Dimension(_))));
+        assert!(matches!(units("(1 m)^200000"), Err(ErrorKind::Dimension(_))));
+        assert!(matches!(units("(1 m)^-129"), Err(ErrorKind::Dimension(_))));
+        assert!(matches!(units("(1 m)^1.5"), Err(ErrorKind::Domain(_))));
+        assert!(matches!(units("1 m^100 * 1 m^100"), Err(ErrorKind::Dimension(_))));
+        assert!(matches!(units("1 m^-100 / 1 m^100"), Err(ErrorKind::Dimension(_))));
+        assert_eq!(units("1 m^127 * 1 m^-127"), Ok("1".to_string()));
+    }
+}
@@ -0,0 +1,266 @@
+// Значение формулы: число режима вычисления, логическое значение, список чисел, матрица
+// или формула. Логические значения дают сравнения, and, or, not и константы true, false;
+// списки - литералы [1, 2, 3] и функция range, матрицы - литералы [[1, 2], [3, 4]];
+// формулы - производная diff(x^3, x) по переменной без значения
+use std::fmt::{Display, Formatter};
+use super::expr::{BinOp, Expr};
+use super::simplify::simplify;
+use super::matrix::Matrix;
+use super::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<T = f64> {
+    Num(T),
+    Bool(bool),
+    List(Vec<T>),
+    Matrix(Matrix<T>),
+    Expr(Expr)
+}
+
+// Форма списка или матрицы в поэлементных операциях
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    List(usize),
+    Matrix(usize, usize)
+}
+
+impl Shape {
+    fn len(self) -> usize {
+        match self {
+            Shape::List(n) => n,
+            Shape::Matrix(r, c) => r * c
+        }
+    }
+
+    fn value<T>(self, xs: Vec<T>) -> Value<T> {
+        match self {
+            Shape::List(_) => Value::List(xs),
+            Shape::Matrix(rows, cols) => Value::Matrix(Matrix { rows, cols, data: xs })
+        }
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::List(n) => write!(f, "a list of length {n}"),
+            Shape::Matrix(r, c) => write!(f, "a {r}x{c} matrix")
+        }
+    }
+}
+
+// Ошибка: формы операндов поэлементной операции не совпадают
+fn mismatch(a: Shape, b: Shape) -> ErrorKind {
+    ErrorKind::Dimension(match (a, b) {
+        (Shape::List(n), Shape::List(m)) => format!("lists of lengths {n} and {m}"),
+        (a, b) => format!("{a} and {b}")
+    })
+}
+
+impl<T> Value<T> {
+    // Число; логическое значение, список или матрица там, где ожидается число, - ошибка
+    pub fn num(self) -> Result<T, ErrorKind> {
+        match self {
+            Value::Num(x) => Ok(x),
+            v => Err(ErrorKind::Type(format!("expected a number, found {}", v.kind())))
+        }
+    }
+
+    pub fn bool(self) -> Result<bool, ErrorKind> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            v => Err(ErrorKind::Type(format!("expected a boolean, found {}", v.kind())))
+        }
+    }
+
+    // Элементы списка или матрицы по строкам; число - список из одного элемента
+    pub fn items(self) -> Result<Vec<T>, ErrorKind> {
+        match self {
+            Value::List(xs) => Ok(xs),
+            Value::Matrix(m) => Ok(m.data),
+            v => Ok(vec![v.num()?])
+        }
+    }
+
+    // Элемент списка или строка матрицы с номером i, считая от нуля;
+    // отрицательный номер - с конца
+    pub(crate) fn at(self, i: i64) -> Result<Value<T>, ErrorKind> {
+        let (n, what) = match &self {
+            Value::List(xs) => (xs.len() as i64, "a list of length"),
+            Value::Matrix(m) => (m.rows as i64, "a matrix with rows"),
+            v => return Err(ErrorKind::Type(format!("expected a list, found {}", v.kind())))
+        };
+        let k = if i < 0 { i + n } else { i };
+        if k < 0 || k >= n {
+            return Err(ErrorKind::Domain(format!("index {i} is out of range for {what} {n}")))
+        }
+        let k = k as usize;
+        Ok(match self {
+            Value::Matrix(m) => Value::List(m.data.into_iter().skip(k * m.cols).take(m.cols).collect()),
+            v => Value::Num(v.items()?.swap_remove(k))
+        })
+    }
+
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Value::Num(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::List(_) => "a list",
+            Value::Matrix(_) => "a matrix",
+            Value::Expr(_) => "a formula"
+        }
+    }
+
+    fn shape(&self) -> Option<Shape> {
+        match self {
+            Value::List(xs) => Some(Shape::List(xs.len())),
+            Value::Matrix(m) => Some(Shape::Matrix(m.rows, m.cols)),
+            _ => None
+        }
+    }
+
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Value<U> {
+        match self {
+            Value::Num(x) => Value::Num(f(x)),
+            Value::Bool(b) => Value::Bool(b),
+            Value::List(xs) => Value::List(xs.into_iter().map(f).collect()),
+            Value::Matrix(m) => Value::Matrix(m.map(f)),
+            Value::Expr(e) => Value::Expr(e)
+        }
+    }
+
+    // Поэлементная операция над числами, списками и матрицами; число применяется
+    // к каждому элементу, списки и матрицы должны быть одной формы
+    pub(crate) fn zip<F>(self, y: Self, mut f: F) -> Result<Self, ErrorKind>
+        where F: FnMut(T, T) -> Result<T, ErrorKind>, T: Clone {
+        spread(vec![self, y], false, |xs| f(xs[0].clone(), xs[1].clone()))
+    }
+
+    // Сравнение op; числа сравнивает cmp, остальные значения - только на равенство
+    pub(crate) fn compare<F>(self, op: BinOp, y: Self, mut cmp: F) -> Result<bool, ErrorKind>
+        where F: FnMut(BinOp, T, T) -> Result<bool, ErrorKind> {
+        let eq = matches!(op, BinOp::Eq | BinOp::Ne);
+        match (self, y) {
+            (Value::Num(a), Value::Num(b)) => cmp(op, a, b),
+            (Value::Bool(a), Value::Bool(b)) if eq => Ok(op.compare(Some(a.cmp(&b)))),
+            (a, b) if eq && a.shape().is_some() && b.shape().is_some() => {
+                let mut same = a.shape() == b.shape();
+                for (x, y) in a.items()?.into_iter().zip(b.items()?) {
+                    if !same { break }
+                    same = cmp(BinOp::Eq, x, y)?;
+                }
+                Ok(same == (op == BinOp::Eq))
+            },
+            (Value::Bool(_), Value::Bool(_)) =>
+                Err(ErrorKind::Type("booleans are not ordered".to_string())),
+            (a, b) if a.shape().is_some() && b.shape().is_some() =>
+                Err(ErrorKind::Type("lists and matrices are not ordered".to_string())),
+            (a, b) => Err(ErrorKind::Type(format!("cannot compare {} with {}", a.kind(), b.kind())))
+        }
+    }
+}
+
+impl<T, E> Value<Result<T, E>> {
+    // Первая ошибка среди чисел или значение без ошибок
+    pub(crate) fn lift(self) -> Result<Value<T>, E> {
+        Ok(match self {
+            Value::Num(x) => Value::Num(x?),
+            Value::Bool(b) => Value::Bool(b),
+            Value::List(xs) => Value::List(xs.into_iter().collect::<Result<_, _>>()?),
+            Value::Matrix(m) => {
+                let data = m.data.into_iter().collect::<Result<_, _>>()?;
+                Value::Matrix(Matrix { rows: m.rows, cols: m.cols, data })
+            },
+            Value::Expr(e) => Value::Expr(e)
+        })
+    }
+}
+
+impl Value {
+    // Формула со значением: число - константа; списки и логические значения в формулы не входят
+    pub(crate) fn formula(self) -> Result<Expr, ErrorKind> {
+        match self {
+            Value::Num(x) => Ok(Expr::num(x)),
+            Value::Expr(e) => Ok(e),
+            v => Err(ErrorKind::Type(format!("expected a number or a formula, found {}", v.kind())))
+        }
+    }
+
+    // Операция над формулами: результат - упрощенная формула
+    pub(crate) fn symbolic(f: impl FnOnce(Vec<Expr>) -> Expr, args: Vec<Value>) -> Result<Value, ErrorKind> {
+        let xs = args.into_iter().map(|a| a.formula()).collect::<Result<_, _>>()?;
+        Ok(Value::Expr(simplify(&f(xs))))
+    }
+}
+
+// Аргументы встроенной функции: функция с переменным числом аргументов получает
+// элементы списков и матриц подряд, функция с заданным числом аргументов применяется
+// к ним поэлементно
+pub(crate) fn spread<T: Clone, F>(args: Vec<Value<T>>, variadic: bool, mut f: F) -> Result<Value<T>, ErrorKind>
+    where F: FnMut(&[T]) -> Result<T, ErrorKind> {
+    if variadic {
+        let mut xs = vec![];
+        for a in args { xs.extend(a.items()?); }
+        return f(&xs).map(Value::Num)
+    }
+    let Some(shape) = args.iter().find_map(|a| a.shape()) else {
+        let xs = args.into_iter().map(|a| a.num()).collect::<Result<Vec<_>, _>>()?;
+        return f(&xs).map(Value::Num)
+    };
+    let n = shape.len();
+    let mut cols = vec![];
+    for a in args {
+        cols.push(match a.shape() {
+            Some(s) if s != shape => return Err(mismatch(shape, s)),
+            Some(_) => a.items()?,
+            None => vec![a.num()?; n]
+        });
+    }
+    let xs = (0..n).map(|i| f(&cols.iter().map(|c| c[i].clone()).collect::<Vec<_>>()))
+        .collect::<Result<_, _>>()?;
+    Ok(shape.value(xs))
+}
+
+// Константы true и false; имя переменной окружения их перекрывает
+pub(crate) fn truth(name: &str) -> Option<bool> {
+    match name {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self { Value::Num(x) }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self { Value::Bool(b) }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(xs: Vec<f64>) -> Self { Value::List(xs) }
+}
+
+impl From<Matrix> for Value {
+    fn from(m: Matrix) -> Self { Value::Matrix(m) }
+}
+
+impl<T: Display> Display for Value<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Num(x) => write!(f, "{x}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::List(xs) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{x}")?;
+                }
+                write!(f, "]")
+            },
+            Value::Matrix(m) => write!(f, "{m}"),
+            Value::Expr(e) => write!(f, "{e}")
+        }
+    }
+}
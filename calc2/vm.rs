@@ -0,0 +1,532 @@
+// Компиляция формулы в байт-код стековой машины.
+// Функции разрешаются и производные раскрываются при компиляции;
+// пользовательская функция компилируется один раз и вызывается с кадром
+// из аргументов на стеке. Рекурсивные функции не компилируются, поэтому
+// глубина стека известна заранее, и вычисление скомпилированной формулы
+// не выделяет память и не дает ошибок.
+// Типы проверяются при компиляции: переменные и результат - числа,
+// логические значения бывают только в условиях и на стеке хранятся как 1 и 0
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use super::diff::expand_diff;
+use super::expr::{BinOp, Expr, Stmt, UnOp};
+use super::eval::is_series;
+use super::funcs::out1;
+use super::value::truth;
+use super::{Arity, CalcError, Env, ErrorKind, NativeFn, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Const(f64),
+    // переменная в ячейке с номером
+    Load(usize),
+    // записать вершину стека в ячейку, не снимая ее
+    Store(usize),
+    Pop,
+    Neg, Add, Sub, Mul, Div, Pow,
+    Not, Mod, And, Or, Xor, Shl, Shr,
+    Lt, Le, Gt, Ge, Eq, Ne,
+    // безусловный переход и переход, если снятое с вершины стека условие ложно
+    Jump(usize),
+    JumpIfNot(usize),
+    // вызов функции с номером в таблице функций и числом аргументов
+    Call(usize, usize),
+    // аргумент пользовательской функции с номером
+    Arg(usize),
+    // вызов пользовательской функции с номером и числом аргументов и возврат из нее;
+    // Ret в конце основного кода - останов
+    CallUser(usize, usize),
+    Ret
+}
+
+#[derive(Clone)]
+pub struct Compiled {
+    code: Vec<Op>,
+    funcs: Vec<NativeFn>,
+    vars: Vec<String>,
+    // место первого упоминания переменной в формуле
+    spans: Vec<Range<usize>>,
+    // наибольшая глубина стека
+    depth: usize,
+    // адреса пользовательских функций; их код идет за основным кодом
+    entries: Vec<usize>,
+    // наибольшая вложенность вызовов пользовательских функций
+    calls: usize
+}
+
+impl Debug for Compiled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Compiled {{ code: {:?}, vars: {:?} }}", self.code, self.vars)
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Compiled {
+    pub fn code(&self) -> &[Op] { &self.code }
+
+    // Переменные формулы в порядке номеров ячеек
+    pub fn vars(&self) -> &[String] { &self.vars }
+
+    pub fn slot(&self, name: &str) -> Option<usize> { self.vars.iter().position(|v| v == name) }
+
+    // Значения переменных из окружения в порядке ячеек;
+    // переменные, которым формула присваивает значение до чтения, могут отсутствовать
+    pub fn bind(&self, env: &Env) -> Result<Vec<f64>, CalcError> {
+        let assigned = |k| self.code.iter()
+            .find(|op| **op == Op::Load(k) || **op == Op::Store(k)) == Some(&Op::Store(k));
+        self.vars.iter().enumerate().map(|(k, v)| match env.get(v) {
+            Some(x) => Ok(x),
+            None if assigned(k) => Ok(f64::NAN),
+            None => Err(CalcError::new(ErrorKind::UnknownVariable(v.clone()), self.spans[k].clone()))
+        }).collect()
+    }
+}
+
+// Стековая машина: стек и ячейки переменных переиспользуются между вызовами
+#[derive(Debug, Default)]
+pub struct Vm {
+    stack: Vec<f64>,
+    slots: Vec<f64>,
+    // адрес возврата и начало кадра вызывающей функции
+    frames: Vec<(usize, usize)>
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Vm {
+    pub fn new() -> Self { Self::default() }
+
+    // vals - значения переменных в порядке Compiled::vars()
+    pub fn run(&mut self, p: &Compiled, vals: &[f64]) -> f64 {
+        self.stack.clear();
+        self.stack.reserve(p.depth);
+        self.frames.clear();
+        self.frames.reserve(p.calls);
+        self.slots.clear();
+        self.slots.extend_from_slice(vals);
+        self.slots.resize(p.vars.len(), f64::NAN);
+        // bp - начало кадра: аргументы вызванной функции на стеке
+        let (mut pc, mut bp) = (0, 0);
+        while let Some(op) = p.code.get(pc) {
+            pc += 1;
+            match *op {
+                Op::Const(x) => self.stack.push(x),
+                Op::Load(k) => self.stack.push(self.slots[k]),
+                Op::Store(k) => self.slots[k] = self.stack[self.stack.len() - 1],
+                Op::Pop => { self.stack.pop(); },
+                Op::Jump(k) => pc = k,
+                Op::JumpIfNot(k) => if self.stack.pop().unwrap() == 0. { pc = k },
+                Op::Neg => {
+                    let n = self.stack.len() - 1;
+                    self.stack[n] = -self.stack[n];
+                },
+                Op::Not => {
+                    let n = self.stack.len() - 1;
+                    self.stack[n] = UnOp::Not.apply(self.stack[n]);
+                },
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow
+                | Op::Mod | Op::And | Op::Or | Op::Xor | Op::Shl | Op::Shr
+                | Op::Lt | Op::Le | Op::Gt | Op::Ge | Op::Eq | Op::Ne => {
+                    let y = self.stack.pop().unwrap();
+                    let n = self.stack.len() - 1;
+                    let x = self.stack[n];
+                    self.stack[n] = match op {
+                        Op::Add => x + y,
+                        Op::Sub => x - y,
+                        Op::Mul => x * y,
+                        Op::Div => x / y,
+                        Op::Pow => x.powf(y),
+                        Op::Mod => x % y,
+                        Op::And => BinOp::And.apply(x, y),
+                        Op::Or => BinOp::Or.apply(x, y),
+                        Op::Xor => BinOp::Xor.apply(x, y),
+                        Op::Shl => BinOp::Shl.apply(x, y),
+                        Op::Shr => BinOp::Shr.apply(x, y),
+                        Op::Lt => BinOp::Lt.apply(x, y),
+                        Op::Le => BinOp::Le.apply(x, y),
+                        Op::Gt => BinOp::Gt.apply(x, y),
+                        Op::Ge => BinOp::Ge.apply(x, y),
+                        Op::Eq => BinOp::Eq.apply(x, y),
+                        _ => BinOp::Ne.apply(x, y)
+                    };
+                },
+                Op::Call(f, argc) => {
+                    let n = self.stack.len() - argc;
+                    let x = (p.funcs[f])(&self.stack[n..]);
+                    self.stack.truncate(n);
+                    self.stack.push(x);
+                },
+                Op::Arg(k) => self.stack.push(self.stack[bp + k]),
+                Op::CallUser(f, argc) => {
+                    self.frames.push((pc, bp));
+                    bp = self.stack.len() - argc;
+                    pc = p.entries[f];
+                },
+                Op::Ret => {
+                    let x = self.stack.pop().unwrap();
+                    self.stack.truncate(bp);
+                    self.stack.push(x);
+                    let Some(frame) = self.frames.pop() else { break };
+                    (pc, bp) = frame;
+                }
+            }
+        }
+        self.stack.last().copied().unwrap_or(0.)
+    }
+}
+
+// Компиляция программы; функции берутся из env на момент компиляции
+pub fn compile(stmts: &[Stmt], env: &Env) -> Result<Compiled, CalcError> {
+    let mut c = Compiler {
+        env: env.clone(),
+        p: Compiled { code: vec![], funcs: vec![], vars: vec![], spans: vec![], depth: 0, entries: vec![], calls: 0 },
+        sp: 0,
+        units: HashMap::new(),
+        bodies: vec![],
+        open: vec![]
+    };
+    let mut has_value = false;
+    for stmt in stmts {
+        match stmt {
+            Stmt::Def { fname, params, body, .. } => {
+                c.env.define(fname, params.clone(), body.clone());
+                // функции, вызывающие переопределенную, компилируются заново
+                c.units.clear();
+            },
+            Stmt::Assign { name, expr, span } => {
+                if has_value { c.emit(Op::Pop); }
+                c.num(expr, &[], &expr.span())?;
+                let k = c.slot(name, span);
+                c.emit(Op::Store(k));
+                has_value = true;
+            },
+            Stmt::Expr(expr) => {
+                if has_value { c.emit(Op::Pop); }
+                c.num(expr, &[], &expr.span())?;
+                has_value = true;
+            }
+        }
+    }
+    if !c.bodies.is_empty() { c.emit(Op::Ret); }
+    for body in c.bodies {
+        let at = c.p.code.len();
+        c.p.entries.push(at);
+        c.p.code.extend(body.into_iter().map(|op| match op {
+            Op::Jump(k) => Op::Jump(at + k),
+            Op::JumpIfNot(k) => Op::JumpIfNot(at + k),
+            op => op
+        }));
+    }
+    Ok(c.p)
+}
+
+// Тип значения на вершине стека
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind { Num, Bool }
+
+impl Kind {
+    // Значение такого типа: типы проверяются по тем же правилам, что и при вычислении
+    fn sample(self) -> Value { if self == Kind::Num { Value::Num(0.) } else { Value::Bool(false) } }
+}
+
+// Скомпилированная пользовательская функция
+#[derive(Clone, Copy)]
+struct Unit {
+    // номер в Compiled::entries
+    index: usize,
+    // глубина стека кадра вместе с аргументами
+    depth: usize,
+    // вложенность вызовов вместе с вызовом самой функции
+    calls: usize,
+    kind: Kind
+}
+
+struct Compiler {
+    env: Env,
+    p: Compiled,
+    // текущая глубина стека
+    sp: usize,
+    units: HashMap<String, Unit>,
+    // код функций с адресами переходов от начала функции
+    bodies: Vec<Vec<Op>>,
+    // функции, тело которых компилируется
+    open: Vec<String>
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) {
+        match op {
+            Op::Const(_) | Op::Load(_) | Op::Arg(_) => self.sp += 1,
+            Op::Pop | Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow
+            | Op::Mod | Op::And | Op::Or | Op::Xor | Op::Shl | Op::Shr
+            | Op::Lt | Op::Le | Op::Gt | Op::Ge | Op::Eq | Op::Ne | Op::JumpIfNot(_) => self.sp -= 1,
+            Op::Call(_, argc) | Op::CallUser(_, argc) => self.sp = self.sp + 1 - argc,
+            Op::Store(_) | Op::Neg | Op::Not | Op::Jump(_) | Op::Ret => ()
+        }
+        self.p.depth = self.p.depth.max(self.sp);
+        self.p.code.push(op);
+    }
+
+    fn slot(&mut self, name: &str, span: &Range<usize>) -> usize {
+        self.p.slot(name).unwrap_or_else(|| {
+            self.p.vars.push(name.to_string());
+            self.p.spans.push(span.clone());
+            self.p.vars.len() - 1
+        })
+    }
+
+    // Ветвление по условию на вершине стека: then - при истинном условии, els - при ложном
+    fn branch<T, E>(&mut self, then: T, els: E) -> Result<(Kind, Kind), CalcError>
+        where T: FnOnce(&mut Self) -> Result<Kind, CalcError>, E: FnOnce(&mut Self) -> Result<Kind, CalcError> {
+        let jump_else = self.p.code.len();
+        self.emit(Op::JumpIfNot(0));
+        let a = then(self)?;
+        let jump_end = self.p.code.len();
+        self.emit(Op::Jump(0));
+        // значение ветви then не лежит на стеке, когда выполняется els
+        self.sp -= 1;
+        self.p.code[jump_else] = Op::JumpIfNot(self.p.code.len());
+        let b = els(self)?;
+        self.p.code[jump_end] = Op::Jump(self.p.code.len());
+        Ok((a, b))
+    }
+
+    fn constant(&mut self, b: bool) -> Result<Kind, CalcError> {
+        self.emit(Op::Const(f64::from(b)));
+        Ok(Kind::Bool)
+    }
+
+    // Пользовательская функция; компилируется при первом вызове
+    fn unit(&mut self, fname: &str, span: &Range<usize>) -> Result<Unit, CalcError> {
+        if let Some(u) = self.units.get(fname) { return Ok(*u) }
+        let f = self.env.func(fname).cloned().unwrap();
+        // рекурсии нужен стек вызовов неизвестной заранее глубины
+        if self.open.iter().any(|g| g == fname) {
+            return Err(CalcError::new(ErrorKind::Unsupported(fname.to_string()), span.clone()))
+        }
+        // тело компилируется отдельно: кадр начинается с аргументов
+        self.open.push(fname.to_string());
+        let caller = (std::mem::take(&mut self.p.code), self.sp, self.p.depth, self.p.calls);
+        (self.sp, self.p.depth, self.p.calls) = (f.params.len(), f.params.len(), 0);
+        let params: Vec<_> = f.params.iter().map(|p| p.as_str()).collect();
+        let kind = self.expr(&f.body, &params)?;
+        self.emit(Op::Ret);
+        let u = Unit { index: self.bodies.len(), depth: self.p.depth, calls: self.p.calls + 1, kind };
+        let (code, sp, depth, calls) = caller;
+        self.bodies.push(std::mem::replace(&mut self.p.code, code));
+        (self.sp, self.p.depth, self.p.calls) = (sp, depth, calls);
+        self.open.pop();
+        self.units.insert(fname.to_string(), u);
+        Ok(u)
+    }
+
+    // Операнд-число операции в положении span
+    fn num(&mut self, e: &Expr, params: &[&str], span: &Range<usize>) -> Result<(), CalcError> {
+        let k = self.expr(e, params)?;
+        k.sample().num().map(|_| ()).map_err(|k| CalcError::new(k, span.clone()))
+    }
+
+    // Операнд-условие операции в положении span
+    fn cond(&mut self, e: &Expr, params: &[&str], span: &Range<usize>) -> Result<(), CalcError> {
+        let k = self.expr(e, params)?;
+        k.sample().bool().map(|_| ()).map_err(|k| CalcError::new(k, span.clone()))
+    }
+
+    // params - параметры компилируемой пользовательской функции
+    fn expr(&mut self, e: &Expr, params: &[&str]) -> Result<Kind, CalcError> {
+        match e {
+            Expr::Num(x) => self.emit(Op::Const(*x)),
+            Expr::Int(n) => self.emit(Op::Const(*n as f64)),
+            Expr::Dec(x, _) => self.emit(Op::Const(*x)),
+            Expr::Var { name, span } => match params.iter().position(|p| p == name) {
+                Some(k) => self.emit(Op::Arg(k)),
+                None => match truth(name) {
+                    Some(b) if self.env.value(name).is_none() => return self.constant(b),
+                    _ => {
+                        let k = self.slot(name, span);
+                        self.emit(Op::Load(k))
+                    }
+                }
+            },
+            Expr::Unary(UnOp::LNot, a, span) => {
+                self.cond(a, params, span)?;
+                self.branch(|c| c.constant(false), |c| c.constant(true))?;
+                return Ok(Kind::Bool)
+            },
+            Expr::Unary(op, a, span) => {
+                self.num(a, params, span)?;
+                self.emit(if *op == UnOp::Neg { Op::Neg } else { Op::Not })
+            },
+            // второй операнд and, or вычисляется, только если от него зависит результат
+            Expr::Binary(op, a, b, span) if op.is_logical() => {
+                self.cond(a, params, span)?;
+                let b = |c: &mut Self| c.cond(b, params, span).map(|_| Kind::Bool);
+                if *op == BinOp::LAnd {
+                    self.branch(b, |c| c.constant(false))?;
+                } else {
+                    self.branch(|c| c.constant(true), b)?;
+                }
+                return Ok(Kind::Bool)
+            },
+            Expr::Binary(op, a, b, span) if op.is_comparison() => {
+                let (x, y) = (self.expr(a, params)?, self.expr(b, params)?);
+                x.sample().compare(*op, y.sample(), |_, _, _| Ok(true))
+                    .map_err(|k| CalcError::new(k, span.clone()))?;
+                self.emit(match op {
+                    BinOp::Lt => Op::Lt,
+                    BinOp::Le => Op::Le,
+                    BinOp::Gt => Op::Gt,
+                    BinOp::Ge => Op::Ge,
+                    BinOp::Eq => Op::Eq,
+                    _ => Op::Ne
+                });
+                return Ok(Kind::Bool)
+            },
+            Expr::Binary(op, a, b, span) => {
+                self.num(a, params, span)?;
+                self.num(b, params, span)?;
+                self.emit(match op {
+                    BinOp::Add => Op::Add,
+                    BinOp::Sub => Op::Sub,
+                    BinOp::Mul => Op::Mul,
+                    BinOp::Div => Op::Div,
+                    BinOp::Pow => Op::Pow,
+                    BinOp::Mod => Op::Mod,
+                    BinOp::And => Op::And,
+                    BinOp::Or => Op::Or,
+                    BinOp::Xor => Op::Xor,
+                    BinOp::Shl => Op::Shl,
+                    _ => Op::Shr
+                })
+            },
+            // списков в байт-коде нет
+            Expr::List(_, span) | Expr::Index(_, _, span) =>
+                return Err(CalcError::new(ErrorKind::Unsupported("lists".to_string()), span.clone())),
+            Expr::Call { fname, args, span } => {
+                if let Some(f) = self.env.func(fname) {
+                    if f.params.len() != args.len() {
+                        return Err(out1(fname, Arity::Exact(f.params.len()), args.len(), span.clone()))
+                    }
+                    let u = self.unit(fname, span)?;
+                    for a in args { self.num(a, params, span)?; }
+                    self.p.depth = self.p.depth.max(self.sp - args.len() + u.depth);
+                    self.p.calls = self.p.calls.max(u.calls);
+                    self.emit(Op::CallUser(u.index, args.len()));
+                    return Ok(u.kind)
+                }
+                // формы вычислителя, если их не перекрывает функция реестра
+                let form = self.env.registry().get(fname).is_none();
+                if form && fname == "diff" {
+                    let d = expand_diff(args, span, Some(&self.env))?;
+                    return self.expr(&d, params)
+                }
+                // solve, integrate и ряды sum и prod вычисляют выражение многократно,
+                // range возвращает список; в байт-код они не переводятся
+                if form && matches!(fname.as_str(), "solve" | "integrate" | "range") || is_series(fname, args) {
+                    return Err(CalcError::new(ErrorKind::Unsupported(fname.clone()), span.clone()))
+                }
+                // if(cond, a, b): ветви должны быть одного типа
+                if form && fname == "if" {
+                    if args.len() != 3 {
+                        return Err(out1(fname, Arity::Exact(3), args.len(), span.clone()))
+                    }
+                    self.cond(&args[0], params, span)?;
+                    let (a, b) = self.branch(|c| c.expr(&args[1], params), |c| c.expr(&args[2], params))?;
+                    if a != b {
+                        let kind = ErrorKind::Type("the branches of 'if' have different types".to_string());
+                        return Err(CalcError::new(kind, span.clone()))
+                    }
+                    return Ok(a)
+                }
+                let Some(f) = self.env.registry().get(fname) else {
+                    return Err(CalcError::new(ErrorKind::UnknownFunction(fname.clone()), span.clone()))
+                };
+                let (arity, f) = (f.arity, f.native());
+                for a in args { self.num(a, params, span)?; }
+                if !arity.accepts(args.len()) {
+                    return Err(out1(fname, arity, args.len(), span.clone()))
+                }
+                self.p.funcs.push(f);
+                self.emit(Op::Call(self.p.funcs.len() - 1, args.len()))
+            }
+        }
+        Ok(Kind::Num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Calc;
+    use super::*;
+
+    // Значение программы s в байт-коде и при обходе дерева
+    fn both(s: &str, env: &mut Env) -> (Result<f64, ErrorKind>, Result<f64, ErrorKind>) {
+        let c = Calc::new(s).unwrap();
+        let vm = c.compile(env).and_then(|p| Ok(Vm::new().run(&p, &p.bind(env)?))).map_err(|e| e.kind);
+        (vm, c.calc_with(env).map_err(|e| e.kind))
+    }
+
+    #[test]
+    fn matches_the_tree_walker() {
+        let mut env = Env::new();
+        env.set("x", 0.5);
+        for s in ["x^2 + 2*x - 1", "sin(x)*cos(x) + max(x, 1, 2)", "if(x > 0 and x < 1, x, -x)", "if(not (x > 1) or x == 2, 1, 0)",
+                  "y = x*3; y + 1", "f(x) = x^2 + 1; f(3) + f(f(2))", "f(x, y) = x - y; f(1, 5)",
+                  "f(x) = 2*x; g(x) = f(x) + f(x + 1); h(x) = g(g(x)); h(x)", "f(x) = if(x > 0, x, -x); f(-3) + f(x)",
+                  "pos(x) = x > 0; if(pos(-x), 1, 2)", "f(x) = x; a = f(2); f(x) = 3*x; a + f(2)",
+                  "f(x) = diff(x^3, x); f(2)", "f(t) = t*y; y = 2; f(3)", "f(k) = diff(x^2*k, x, k); f(x)"] {
+            let (vm, tree) = both(s, &mut env);
+            assert!(tree.is_ok(), "{s}: {tree:?}");
+            assert_eq!(vm, tree, "{s}");
+        }
+    }
+
+    #[test]
+    fn runs_again_with_other_values() {
+        let mut env = Env::new();
+        env.set("x", 0.);
+        env.set("a", 2.);
+        let c = Calc::new("f(t) = t^2 - a; f(x) / (x + 1)").unwrap();
+        let p = c.compile(&env).unwrap();
+        assert_eq!(p.vars(), ["a", "x"]);
+        let mut vals = p.bind(&env).unwrap();
+        let (x, mut vm) = (p.slot("x").unwrap(), Vm::new());
+        for t in [-3., -0.5, 0., 1.5, 7.] {
+            vals[x] = t;
+            env.set("x", t);
+            assert_eq!(vm.run(&p, &vals), c.calc_with(&mut env).unwrap(), "x = {t}");
+        }
+        // ячейки без значения в окружении - ошибка привязки
+        let p = Calc::new("x + b").unwrap().compile(&env).unwrap();
+        assert_eq!(p.bind(&env).map_err(|e| e.kind), Err(ErrorKind::UnknownVariable("b".to_string())));
+    }
+
+    #[test]
+    fn user_functions_are_compiled_once() {
+        let env = Env::new();
+        let s = "f(x) = x*x + x; g(x) = f(f(x)) + f(x); h(x) = g(g(x)) + g(x); h(1)";
+        let p = Calc::new(s).unwrap().compile(&env).unwrap();
+        assert_eq!(p.entries.len(), 3);
+        assert!(p.code().len() < 40);
+        assert_eq!(Vm::new().run(&p, &[]), Calc::new(s).unwrap().calc_with(&mut Env::new()).unwrap());
+    }
+
+    #[test]
+    fn recursion_is_rejected() {
+        let mut env = Env::new();
+        let (vm, tree) = both("fact(n) = if(n <= 1, 1, n*fact(n - 1)); fact(5)", &mut env);
+        assert_eq!(vm, Err(ErrorKind::Unsupported("fact".to_string())));
+        assert_eq!(tree, Ok(120.));
+        let c = Calc::new("a(x) = b(x); b(x) = a(x) + 1; a(1)").unwrap();
+        assert_eq!(c.compile(&env).map_err(|e| e.kind).err(), Some(ErrorKind::Unsupported("a".to_string())));
+    }
+
+    #[test]
+    fn series_are_not_compiled() {
+        let mut env = Env::new();
+        let (vm, tree) = both("sum(2, k, 1, 10)", &mut env);
+        assert_eq!(vm, Err(ErrorKind::Unsupported("sum".to_string())));
+        assert_eq!(tree, Ok(20.));
+        assert_eq!(both("sum(1, 2, 3, 4)", &mut env), (Ok(10.), Ok(10.)));
+    }
+}